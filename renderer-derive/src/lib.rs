@@ -0,0 +1,247 @@
+//! Derive macros of the `renderer` crate, use them through its re-exports
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+/// Checks at compile time that every field of a `#[repr(C)]` struct sits where GLSL puts it,
+/// following the rules named by `#[layout(std140)]`, `#[layout(std430)]` or
+/// `#[layout(scalar)]`, and implements `GpuField` and `GpuLayout` for it
+#[proc_macro_derive(GpuLayout, attributes(layout))]
+pub fn derive_gpu_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    gpu_layout(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn gpu_layout(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "GpuLayout can't be derived for generic structs",
+        ));
+    }
+    check_repr_c(input, "GpuLayout")?;
+    let rules = layout_rules(input)?;
+    let fields = named_fields(input, "GpuLayout")?;
+
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let idents: Vec<_> = fields
+        .iter()
+        .map(|field| field.ident.as_ref().expect("named field"))
+        .collect();
+    let count = fields.len();
+    let rules_name = rules.to_string().to_lowercase();
+
+    let checks = idents.iter().enumerate().map(|(index, ident)| {
+        let inexact = format!(
+            "`{}::{}` is not stored the way {} lays it out, check its array strides and padding",
+            name, ident, rules_name
+        );
+        let misplaced = format!(
+            "`{}::{}` is not at its {} offset, add or remove padding before it",
+            name, ident, rules_name
+        );
+        quote! {
+            assert!(FIELDS[#index].exact, #inexact);
+            assert!(OFFSETS[#index] == ::core::mem::offset_of!(#name, #ident), #misplaced);
+        }
+    });
+    let layouts = ["Std140", "Std430", "Scalar"].map(|variant| {
+        let variant = Ident::new(variant, Span::call_site());
+        quote! {
+            ::renderer::layout::struct_layout(
+                ::renderer::LayoutRules::#variant,
+                [#(::renderer::LayoutRules::#variant.field::<#types>()),*],
+                [#(::core::mem::offset_of!(#name, #idents)),*],
+                ::core::mem::size_of::<#name>(),
+            )
+        }
+    });
+    let [std140, std430, scalar] = layouts;
+
+    Ok(quote! {
+        const _: () = {
+            const FIELDS: [::renderer::FieldLayout; #count] =
+                [#(::renderer::LayoutRules::#rules.field::<#types>()),*];
+            const OFFSETS: [usize; #count] =
+                ::renderer::layout::struct_offsets(FIELDS);
+            #(#checks)*
+        };
+
+        unsafe impl ::renderer::GpuField for #name {
+            const STD140: ::renderer::FieldLayout = #std140;
+            const STD430: ::renderer::FieldLayout = #std430;
+            const SCALAR: ::renderer::FieldLayout = #scalar;
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                #(
+                    ::renderer::GpuField::write_bytes(
+                        &self.#idents,
+                        &mut bytes[::core::mem::offset_of!(#name, #idents)..],
+                    );
+                )*
+            }
+        }
+
+        impl ::renderer::layout::ArrayElement for #name {}
+
+        unsafe impl ::renderer::GpuLayout for #name {
+            const RULES: ::renderer::LayoutRules = ::renderer::LayoutRules::#rules;
+        }
+    })
+}
+
+/// Implements `Vertex` for a `#[repr(C)]` struct, one attribute per field with the format
+/// given by `VertexFormat`. Fields take consecutive locations from 0 unless
+/// `#[vertex(location = N)]` moves them, `#[vertex(format = R8G8B8A8_SRGB)]` overrides the format
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    vertex(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn vertex(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "Vertex can't be derived for generic structs",
+        ));
+    }
+    check_repr_c(input, "Vertex")?;
+
+    let mut entries = vec![];
+    let mut counts = vec![];
+    for field in named_fields(input, "Vertex")? {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let mut location = quote!(::core::option::Option::None);
+        let mut format = None;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("vertex"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("location") {
+                    let value: syn::LitInt = meta.value()?.parse()?;
+                    let value: u32 = value.base10_parse()?;
+                    location = quote!(::core::option::Option::Some(#value));
+                    Ok(())
+                } else if meta.path.is_ident("format") {
+                    format = Some(meta.value()?.parse::<Ident>()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected location or format"))
+                }
+            })?;
+        }
+        let (format, locations) = match format {
+            Some(format) => (quote!(::renderer::ash::vk::Format::#format), quote!(1)),
+            None => (
+                quote!(<#ty as ::renderer::VertexFormat>::FORMAT),
+                quote!(<#ty as ::renderer::VertexFormat>::LOCATIONS),
+            ),
+        };
+        counts.push(locations.clone());
+        entries.push(quote! {
+            ::renderer::vertex::VertexField {
+                location: #location,
+                format: #format,
+                offset: ::core::mem::offset_of!(#name, #ident),
+                size: ::core::mem::size_of::<#ty>(),
+                locations: #locations,
+            }
+        });
+    }
+
+    let count = entries.len();
+    Ok(quote! {
+        impl ::renderer::Vertex for #name {
+            const ATTRIBUTES: &'static [::renderer::ash::vk::VertexInputAttributeDescription] =
+                &::renderer::vertex::vertex_attributes::<
+                    #count,
+                    { 0 #(+ #counts as usize)* },
+                >([#(#entries),*]);
+        }
+    })
+}
+
+fn check_repr_c(input: &DeriveInput, derive: &str) -> Result<(), Error> {
+    let mut repr_c = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attr.parse_nested_meta(|meta| {
+            repr_c |= meta.path.is_ident("C");
+            Ok(())
+        })?;
+    }
+    if repr_c {
+        Ok(())
+    } else {
+        Err(Error::new_spanned(
+            &input.ident,
+            format!("{} needs #[repr(C)] to keep the fields in order", derive),
+        ))
+    }
+}
+
+fn layout_rules(input: &DeriveInput) -> Result<Ident, Error> {
+    let mut rules = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("layout"))
+    {
+        attr.parse_nested_meta(|meta| {
+            let variant = if meta.path.is_ident("std140") {
+                "Std140"
+            } else if meta.path.is_ident("std430") {
+                "Std430"
+            } else if meta.path.is_ident("scalar") {
+                "Scalar"
+            } else {
+                return Err(meta.error("expected std140, std430 or scalar"));
+            };
+            rules = Some(format_ident!("{}", variant));
+            Ok(())
+        })?;
+    }
+    rules.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "missing #[layout(std140)], #[layout(std430)] or #[layout(scalar)]",
+        )
+    })
+}
+
+fn named_fields<'a>(input: &'a DeriveInput, derive: &str) -> Result<Vec<&'a syn::Field>, Error> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            format!("{} can only be derived for structs", derive),
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &data.fields,
+            format!("{} needs named fields", derive),
+        ));
+    };
+    if fields.named.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            format!("{} needs at least one field", derive),
+        ));
+    }
+    Ok(fields.named.iter().collect())
+}
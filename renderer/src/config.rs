@@ -0,0 +1,230 @@
+use std::{ffi::CString, path::PathBuf};
+
+use ash::{ext, khr, vk};
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+
+use crate::{
+    BindlessDesc, DeviceFeatures, GpuPreference, GpuSelector, Renderer, RendererError,
+    SwapchainPreferences, GPU_SELECTOR_ENV,
+};
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_STAGING_BUFFER_SIZE: vk::DeviceSize = 32 * 1024 * 1024;
+
+/// Every knob used to create the renderer, `Default` matches the historical hardcoded setup
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    pub app_name: CString,
+    pub app_version: u32,
+    pub engine_name: CString,
+    pub engine_version: u32,
+    pub api_version: u32,
+    pub validation: bool,
+    pub debug_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    /// Extra instance extensions on top of the ones required by the window system
+    pub instance_extensions: Vec<CString>,
+    /// Instance extensions enabled only when the loader exposes them
+    pub optional_instance_extensions: Vec<CString>,
+    pub device_extensions: Vec<CString>,
+    /// Device extensions enabled only when the selected GPU supports them
+    pub optional_device_extensions: Vec<CString>,
+    /// A gpu lacking any of these is rejected during selection
+    pub required_features: DeviceFeatures,
+    /// Enabled only when the selected GPU supports them
+    pub optional_features: DeviceFeatures,
+    pub frames_in_flight: usize,
+    /// Size of the staging ring used by uploads, bigger uploads get a buffer of their own
+    pub staging_buffer_size: vk::DeviceSize,
+    /// Initial window size, only used when the surface lets the swapchain decide its extent.
+    /// For headless renderers it is the size of the offscreen images and must not be zero
+    pub extent: vk::Extent2D,
+    pub swapchain: SwapchainPreferences,
+    /// Defaults to the `RENDERER_GPU` environment override, falling back to high performance
+    pub gpu_selector: GpuSelector,
+    /// Root of the paths given to `Renderer::load_shader` and of `#include <...>`
+    pub shader_dir: PathBuf,
+    /// Reload changed shaders and rebuild their pipelines between frames
+    pub hot_reload: bool,
+    /// Creates the bindless heap, the gpu must support `DeviceFeatures::bindless`
+    pub bindless: Option<BindlessDesc>,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            app_name: CString::default(),
+            app_version: 0,
+            engine_name: CString::default(),
+            engine_version: 0,
+            api_version: vk::make_api_version(0, 1, 3, 0),
+            validation: false,
+            debug_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            instance_extensions: vec![],
+            optional_instance_extensions: vec![],
+            device_extensions: vec![
+                khr::swapchain::NAME.to_owned(),
+                khr::dynamic_rendering::NAME.to_owned(),
+                khr::synchronization2::NAME.to_owned(),
+                khr::buffer_device_address::NAME.to_owned(),
+                ext::descriptor_indexing::NAME.to_owned(),
+            ],
+            optional_device_extensions: vec![],
+            required_features: DeviceFeatures::renderer_defaults(),
+            optional_features: DeviceFeatures::default(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            staging_buffer_size: DEFAULT_STAGING_BUFFER_SIZE,
+            extent: vk::Extent2D::default(),
+            swapchain: SwapchainPreferences::default(),
+            gpu_selector: GpuSelector::default(),
+            shader_dir: PathBuf::from("shaders"),
+            hot_reload: false,
+            bindless: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RendererBuilder {
+    config: RendererConfig,
+}
+
+impl RendererBuilder {
+    pub fn new(app_name: CString) -> Self {
+        Self {
+            config: RendererConfig {
+                app_name,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn from_config(config: RendererConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn app_version(mut self, version: u32) -> Self {
+        self.config.app_version = version;
+        self
+    }
+
+    pub fn engine(mut self, name: CString, version: u32) -> Self {
+        self.config.engine_name = name;
+        self.config.engine_version = version;
+        self
+    }
+
+    /// Requested vulkan version, build it with `vk::make_api_version`
+    pub fn api_version(mut self, version: u32) -> Self {
+        self.config.api_version = version;
+        self
+    }
+
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.config.validation = enabled;
+        self
+    }
+
+    pub fn debug_severity(mut self, severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.config.debug_severity = severity;
+        self
+    }
+
+    pub fn instance_extension(mut self, name: CString) -> Self {
+        self.config.instance_extensions.push(name);
+        self
+    }
+
+    pub fn optional_instance_extension(mut self, name: CString) -> Self {
+        self.config.optional_instance_extensions.push(name);
+        self
+    }
+
+    pub fn device_extension(mut self, name: CString) -> Self {
+        self.config.device_extensions.push(name);
+        self
+    }
+
+    pub fn optional_device_extension(mut self, name: CString) -> Self {
+        self.config.optional_device_extensions.push(name);
+        self
+    }
+
+    /// Added on top of the features the renderer itself requires
+    pub fn required_features(mut self, features: DeviceFeatures) -> Self {
+        self.config.required_features = self.config.required_features.union(&features);
+        self
+    }
+
+    pub fn optional_features(mut self, features: DeviceFeatures) -> Self {
+        self.config.optional_features = self.config.optional_features.union(&features);
+        self
+    }
+
+    /// Clamped to at least one frame
+    pub fn frames_in_flight(mut self, count: usize) -> Self {
+        self.config.frames_in_flight = count.max(1);
+        self
+    }
+
+    pub fn staging_buffer_size(mut self, size: vk::DeviceSize) -> Self {
+        self.config.staging_buffer_size = size;
+        self
+    }
+
+    pub fn extent(mut self, width: u32, height: u32) -> Self {
+        self.config.extent = vk::Extent2D { width, height };
+        self
+    }
+
+    pub fn swapchain_preferences(mut self, preferences: SwapchainPreferences) -> Self {
+        self.config.swapchain = preferences;
+        self
+    }
+
+    /// The `RENDERER_GPU` environment override still takes precedence
+    pub fn gpu_preference(mut self, preference: GpuPreference) -> Self {
+        self.config.gpu_selector = GpuSelector::Env {
+            var: GPU_SELECTOR_ENV.to_owned(),
+            fallback: Box::new(GpuSelector::Preference(preference)),
+        };
+        self
+    }
+
+    pub fn gpu_selector(mut self, selector: GpuSelector) -> Self {
+        self.config.gpu_selector = selector;
+        self
+    }
+
+    pub fn shader_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.config.shader_dir = dir.into();
+        self
+    }
+
+    pub fn hot_reload(mut self, enabled: bool) -> Self {
+        self.config.hot_reload = enabled;
+        self
+    }
+
+    /// Also adds the features it needs to the required ones
+    pub fn bindless(mut self, desc: BindlessDesc) -> Self {
+        self.config.bindless = Some(desc);
+        self.required_features(DeviceFeatures::bindless())
+    }
+
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    pub fn build<T>(self, window: &T) -> Result<Renderer, RendererError>
+    where
+        T: HasDisplayHandle + HasWindowHandle,
+    {
+        Renderer::new(window, self.config)
+    }
+
+    /// Renders into offscreen images, no window system extension or surface is involved
+    pub fn build_headless(self) -> Result<Renderer, RendererError> {
+        Renderer::headless(self.config)
+    }
+}
@@ -0,0 +1,495 @@
+use std::ptr::NonNull;
+
+use ash::vk;
+
+use super::{ResourceKind, Tlsf, TlsfAllocation};
+
+/// Block size for heaps big enough, smaller heaps use an eighth of their size
+const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+const SMALL_HEAP: vk::DeviceSize = 512 * 1024 * 1024;
+
+/// Where the memory should live, decides the memory type together with the resource requirements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryUsage {
+    /// Device local, never mapped
+    GpuOnly,
+    /// Host visible and coherent, written by the cpu and read by the gpu (staging, uniforms)
+    Upload,
+    /// Host visible and coherent, cached when possible, written by the gpu and read by the cpu
+    Readback,
+}
+
+impl MemoryUsage {
+    /// Required, preferred and unwanted flags
+    fn flags(
+        self,
+    ) -> (
+        vk::MemoryPropertyFlags,
+        vk::MemoryPropertyFlags,
+        vk::MemoryPropertyFlags,
+    ) {
+        use vk::MemoryPropertyFlags as F;
+        match self {
+            Self::GpuOnly => (F::DEVICE_LOCAL, F::empty(), F::HOST_VISIBLE),
+            Self::Upload => (
+                F::HOST_VISIBLE | F::HOST_COHERENT,
+                F::empty(),
+                F::HOST_CACHED,
+            ),
+            Self::Readback => (
+                F::HOST_VISIBLE | F::HOST_COHERENT,
+                F::HOST_CACHED,
+                F::DEVICE_LOCAL,
+            ),
+        }
+    }
+}
+
+/// Resource that gets a `vk::DeviceMemory` of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedicatedResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationRequest {
+    pub requirements: vk::MemoryRequirements,
+    pub usage: MemoryUsage,
+    pub kind: ResourceKind,
+    /// Skips the sub-allocator, big resources get one anyway
+    pub dedicated: Option<DedicatedResource>,
+}
+
+#[derive(Debug)]
+enum Source {
+    Block {
+        memory_type: usize,
+        block: usize,
+        range: TlsfAllocation,
+    },
+    Dedicated {
+        memory_type: usize,
+    },
+}
+
+/// Memory range bound to a resource, must be given back with `Device::free_allocation`
+#[derive(Debug)]
+pub struct Allocation {
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    mapped: Option<NonNull<u8>>,
+    source: Source,
+}
+
+// the mapped pointer stays valid until the memory is freed, the allocation owns its range
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn memory_type(&self) -> u32 {
+        match self.source {
+            Source::Block { memory_type, .. } | Source::Dedicated { memory_type } => {
+                memory_type as u32
+            }
+        }
+    }
+
+    pub fn is_dedicated(&self) -> bool {
+        matches!(self.source, Source::Dedicated { .. })
+    }
+
+    /// Start of the range, host visible memory stays mapped for the whole allocation lifetime
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        self.mapped
+    }
+
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.mapped
+            .map(|ptr| unsafe { std::slice::from_raw_parts(ptr.as_ptr(), self.size as usize) })
+    }
+
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        self.mapped
+            .map(|ptr| unsafe { std::slice::from_raw_parts_mut(ptr.as_ptr(), self.size as usize) })
+    }
+}
+
+/// Usage counters of a memory type, or of all of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Device memory objects backing the sub-allocations
+    pub blocks: usize,
+    pub block_bytes: vk::DeviceSize,
+    pub allocations: usize,
+    /// Bytes handed out from the blocks, padding excluded
+    pub allocated_bytes: vk::DeviceSize,
+    /// Biggest free range in any block, a low value next to lots of free bytes means fragmentation
+    pub largest_free: vk::DeviceSize,
+    pub dedicated_allocations: usize,
+    pub dedicated_bytes: vk::DeviceSize,
+}
+
+impl MemoryStats {
+    fn add(&mut self, other: &MemoryStats) {
+        self.blocks += other.blocks;
+        self.block_bytes += other.block_bytes;
+        self.allocations += other.allocations;
+        self.allocated_bytes += other.allocated_bytes;
+        self.largest_free = self.largest_free.max(other.largest_free);
+        self.dedicated_allocations += other.dedicated_allocations;
+        self.dedicated_bytes += other.dedicated_bytes;
+    }
+
+    /// Everything currently taken from the driver
+    pub fn device_bytes(&self) -> vk::DeviceSize {
+        self.block_bytes + self.dedicated_bytes
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AllocatorStats {
+    pub total: MemoryStats,
+    /// Indexed by memory type
+    pub memory_types: Vec<MemoryStats>,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    mapped: Option<NonNull<u8>>,
+    tlsf: Tlsf,
+}
+
+#[derive(Default)]
+struct MemoryPool {
+    block_size: vk::DeviceSize,
+    /// Freed blocks leave a hole so the indices held by allocations stay valid
+    blocks: Vec<Option<MemoryBlock>>,
+    dedicated: Vec<(vk::DeviceMemory, vk::DeviceSize)>,
+}
+
+/// Sub-allocates device memory from big blocks, one set of blocks per memory type
+pub struct Allocator {
+    device: ash::Device,
+    memory: vk::PhysicalDeviceMemoryProperties,
+    granularity: vk::DeviceSize,
+    /// Blocks get VK_MEMORY_ALLOCATE_DEVICE_ADDRESS_BIT when buffer device address is enabled
+    device_address: bool,
+    pools: Vec<MemoryPool>,
+}
+
+// mapped pointers are only touched through allocations, the allocator itself sits behind a lock
+unsafe impl Send for Allocator {}
+
+impl Allocator {
+    pub(in crate::core) fn new(
+        device: ash::Device,
+        memory: vk::PhysicalDeviceMemoryProperties,
+        limits: &vk::PhysicalDeviceLimits,
+        device_address: bool,
+    ) -> Self {
+        let pools = memory
+            .memory_types_as_slice()
+            .iter()
+            .map(|ty| {
+                let heap_size = memory.memory_heaps[ty.heap_index as usize].size;
+                let block_size = if heap_size <= SMALL_HEAP {
+                    (heap_size / 8).max(1)
+                } else {
+                    DEFAULT_BLOCK_SIZE
+                };
+                MemoryPool {
+                    block_size,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Self {
+            device,
+            memory,
+            granularity: limits.buffer_image_granularity.max(1).next_power_of_two(),
+            device_address,
+            pools,
+        }
+    }
+
+    /// Memory types allowed by `type_bits` that have the required flags, best first
+    fn memory_types(&self, type_bits: u32, usage: MemoryUsage) -> Vec<usize> {
+        let (required, preferred, unwanted) = usage.flags();
+        let mut candidates: Vec<(usize, i32)> = self
+            .memory
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .filter(|(index, ty)| {
+                type_bits & (1 << index) != 0 && ty.property_flags.contains(required)
+            })
+            .map(|(index, ty)| {
+                let score = (ty.property_flags & preferred).as_raw().count_ones() as i32
+                    - (ty.property_flags & unwanted).as_raw().count_ones() as i32;
+                (index, score)
+            })
+            .collect();
+        // stable, equal scores keep the driver order
+        candidates.sort_by_key(|(_, score)| -score);
+        candidates.into_iter().map(|(index, _)| index).collect()
+    }
+
+    pub fn allocate(&mut self, request: &AllocationRequest) -> Result<Allocation, vk::Result> {
+        let requirements = request.requirements;
+        let candidates = self.memory_types(requirements.memory_type_bits, request.usage);
+        if candidates.is_empty() {
+            log::error!(
+                "No memory type for {:?} in type bits {:#b}",
+                request.usage,
+                requirements.memory_type_bits
+            );
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+
+        // out of memory on a heap falls back to the next compatible memory type
+        let mut result = Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        for memory_type in candidates {
+            let dedicated = request.dedicated.is_some()
+                || requirements.size > self.pools[memory_type].block_size / 2;
+            result = if dedicated {
+                self.allocate_dedicated(memory_type, requirements.size, request.dedicated)
+            } else {
+                self.allocate_from_blocks(memory_type, requirements, request.kind)
+            };
+            match result {
+                Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => continue,
+                _ => break,
+            }
+        }
+        result
+    }
+
+    fn allocate_from_blocks(
+        &mut self,
+        memory_type: usize,
+        requirements: vk::MemoryRequirements,
+        kind: ResourceKind,
+    ) -> Result<Allocation, vk::Result> {
+        let alignment = requirements.alignment.max(1);
+        let pool = &mut self.pools[memory_type];
+        let found = pool
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, block)| {
+                let block = block.as_mut()?;
+                let range = block.tlsf.allocate(requirements.size, alignment, kind)?;
+                Some((index, range, block.memory, block.mapped))
+            });
+
+        let (block, range, memory, mapped) = match found {
+            Some(found) => found,
+            None => {
+                let block_size = pool.block_size;
+                let (memory, mapped) = self.allocate_memory(memory_type, block_size, None)?;
+                let mut tlsf = Tlsf::new(block_size, self.granularity);
+                let range = tlsf
+                    .allocate(requirements.size, alignment, kind)
+                    .expect("A new block fits anything up to half its size");
+
+                let pool = &mut self.pools[memory_type];
+                let block = MemoryBlock {
+                    memory,
+                    mapped,
+                    tlsf,
+                };
+                let index = match pool.blocks.iter().position(Option::is_none) {
+                    Some(index) => {
+                        pool.blocks[index] = Some(block);
+                        index
+                    }
+                    None => {
+                        pool.blocks.push(Some(block));
+                        pool.blocks.len() - 1
+                    }
+                };
+                log::trace!(
+                    "Allocated a {} MiB block from memory type {}",
+                    block_size / (1024 * 1024),
+                    memory_type
+                );
+                (index, range, memory, mapped)
+            }
+        };
+
+        Ok(Allocation {
+            memory,
+            offset: range.offset,
+            size: range.size,
+            mapped: mapped.map(|ptr| unsafe { ptr.add(range.offset as usize) }),
+            source: Source::Block {
+                memory_type,
+                block,
+                range,
+            },
+        })
+    }
+
+    fn allocate_dedicated(
+        &mut self,
+        memory_type: usize,
+        size: vk::DeviceSize,
+        resource: Option<DedicatedResource>,
+    ) -> Result<Allocation, vk::Result> {
+        let (memory, mapped) = self.allocate_memory(memory_type, size, resource)?;
+        self.pools[memory_type].dedicated.push((memory, size));
+
+        Ok(Allocation {
+            memory,
+            offset: 0,
+            size,
+            mapped,
+            source: Source::Dedicated { memory_type },
+        })
+    }
+
+    /// Raw device memory, mapped whole when host visible
+    fn allocate_memory(
+        &self,
+        memory_type: usize,
+        size: vk::DeviceSize,
+        resource: Option<DedicatedResource>,
+    ) -> Result<(vk::DeviceMemory, Option<NonNull<u8>>), vk::Result> {
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::default().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default();
+        match resource {
+            Some(DedicatedResource::Buffer(buffer)) => {
+                dedicated_info = dedicated_info.buffer(buffer)
+            }
+            Some(DedicatedResource::Image(image)) => dedicated_info = dedicated_info.image(image),
+            None => (),
+        }
+
+        let mut info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type as u32);
+        if self.device_address {
+            info = info.push_next(&mut flags_info);
+        }
+        if resource.is_some() {
+            info = info.push_next(&mut dedicated_info);
+        }
+        let memory = unsafe { self.device.allocate_memory(&info, None)? };
+
+        let host_visible = self.memory.memory_types[memory_type]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        if !host_visible {
+            return Ok((memory, None));
+        }
+
+        match unsafe {
+            self.device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        } {
+            Ok(ptr) => Ok((memory, NonNull::new(ptr as *mut u8))),
+            Err(err) => {
+                unsafe { self.device.free_memory(memory, None) };
+                Err(err)
+            }
+        }
+    }
+
+    pub fn free(&mut self, allocation: Allocation) {
+        match allocation.source {
+            Source::Dedicated { memory_type } => {
+                let pool = &mut self.pools[memory_type];
+                pool.dedicated
+                    .retain(|(memory, _)| *memory != allocation.memory);
+                unsafe { self.device.free_memory(allocation.memory, None) };
+            }
+            Source::Block {
+                memory_type,
+                block,
+                range,
+            } => {
+                let pool = &mut self.pools[memory_type];
+                let entry = pool.blocks[block]
+                    .as_mut()
+                    .expect("Allocation from a freed block");
+                entry.tlsf.free(range);
+                if !entry.tlsf.is_empty() {
+                    return;
+                }
+
+                // keep one empty block around so a single resource coming and going doesn't
+                // hit the driver every time
+                let empty = pool
+                    .blocks
+                    .iter()
+                    .flatten()
+                    .filter(|block| block.tlsf.is_empty())
+                    .count();
+                if empty > 1 {
+                    if let Some(freed) = pool.blocks[block].take() {
+                        unsafe { self.device.free_memory(freed.memory, None) };
+                        log::trace!("Released an empty block of memory type {}", memory_type);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats::default();
+        for pool in &self.pools {
+            let mut pool_stats = MemoryStats {
+                dedicated_allocations: pool.dedicated.len(),
+                dedicated_bytes: pool.dedicated.iter().map(|(_, size)| size).sum(),
+                ..Default::default()
+            };
+            for block in pool.blocks.iter().flatten() {
+                pool_stats.blocks += 1;
+                pool_stats.block_bytes += block.tlsf.size();
+                pool_stats.allocations += block.tlsf.allocation_count();
+                pool_stats.allocated_bytes += block.tlsf.allocated();
+                pool_stats.largest_free = pool_stats.largest_free.max(block.tlsf.largest_free());
+            }
+            stats.total.add(&pool_stats);
+            stats.memory_types.push(pool_stats);
+        }
+        stats
+    }
+
+    /// Frees every block and dedicated allocation, the ones still alive are reported and
+    /// become dangling
+    pub(in crate::core) fn destroy(&mut self) {
+        let stats = self.stats();
+        if stats.total.allocations + stats.total.dedicated_allocations > 0 {
+            log::warn!(
+                "Destroying the allocator with {} live allocations",
+                stats.total.allocations + stats.total.dedicated_allocations
+            );
+        }
+
+        for pool in &mut self.pools {
+            for block in pool.blocks.drain(..).flatten() {
+                unsafe { self.device.free_memory(block.memory, None) };
+            }
+            for (memory, _) in pool.dedicated.drain(..) {
+                unsafe { self.device.free_memory(memory, None) };
+            }
+        }
+    }
+}
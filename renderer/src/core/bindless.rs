@@ -0,0 +1,538 @@
+use std::sync::{Mutex, MutexGuard};
+
+use ash::vk;
+
+use crate::RendererError;
+
+use super::{Buffer, Device};
+
+/// Size of each array of the bindless set, clamped to the device limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindlessDesc {
+    pub sampled_images: u32,
+    pub storage_images: u32,
+    pub samplers: u32,
+    pub storage_buffers: u32,
+}
+
+impl Default for BindlessDesc {
+    fn default() -> Self {
+        Self {
+            sampled_images: 16 * 1024,
+            storage_images: 1024,
+            samplers: 128,
+            storage_buffers: 8 * 1024,
+        }
+    }
+}
+
+impl BindlessDesc {
+    pub fn count(&self, kind: BindlessKind) -> u32 {
+        match kind {
+            BindlessKind::SampledImage => self.sampled_images,
+            BindlessKind::StorageImage => self.storage_images,
+            BindlessKind::Sampler => self.samplers,
+            BindlessKind::StorageBuffer => self.storage_buffers,
+        }
+    }
+
+    /// Shrinks the arrays to what one set and one stage may hold. Every array is visible to
+    /// all stages, so apart from the samplers they share the per stage `resources` budget
+    pub(in crate::core) fn clamp(&self, per_set: &Self, per_stage: &Self, resources: u32) -> Self {
+        let count = |kind| {
+            self.count(kind)
+                .min(per_set.count(kind))
+                .min(per_stage.count(kind))
+        };
+        let mut desc = Self {
+            sampled_images: count(BindlessKind::SampledImage),
+            storage_images: count(BindlessKind::StorageImage),
+            samplers: count(BindlessKind::Sampler),
+            storage_buffers: count(BindlessKind::StorageBuffer),
+        };
+        let total =
+            desc.sampled_images as u64 + desc.storage_images as u64 + desc.storage_buffers as u64;
+        if total > resources as u64 {
+            let scale = |count: u32| (count as u64 * resources as u64 / total) as u32;
+            desc.sampled_images = scale(desc.sampled_images);
+            desc.storage_images = scale(desc.storage_images);
+            desc.storage_buffers = scale(desc.storage_buffers);
+        }
+        desc
+    }
+}
+
+/// Array of the bindless set, each one lives at the binding of the same number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindlessKind {
+    SampledImage,
+    StorageImage,
+    Sampler,
+    StorageBuffer,
+}
+
+impl BindlessKind {
+    pub const ALL: [Self; 4] = [
+        Self::SampledImage,
+        Self::StorageImage,
+        Self::Sampler,
+        Self::StorageBuffer,
+    ];
+
+    pub fn binding(self) -> u32 {
+        self as u32
+    }
+
+    pub fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            Self::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            Self::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            Self::Sampler => vk::DescriptorType::SAMPLER,
+            Self::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+        }
+    }
+}
+
+/// Indices of one array. Freed indices wait for the frames that may still read them
+#[derive(Debug, Default)]
+struct Slots {
+    capacity: u32,
+    /// Never handed out from here on
+    next: u32,
+    free: Vec<u32>,
+    /// Tagged with the frame they were freed in
+    pending: Vec<(u64, u32)>,
+}
+
+impl Slots {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    fn allocate(&mut self) -> Option<u32> {
+        if let Some(index) = self.free.pop() {
+            return Some(index);
+        }
+        (self.next < self.capacity).then(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+
+    /// Handed out and neither freed nor waiting to be
+    fn is_allocated(&self, index: u32) -> bool {
+        index < self.next
+            && !self.free.contains(&index)
+            && !self.pending.iter().any(|(_, pending)| *pending == index)
+    }
+
+    /// False when the index is not currently allocated, freeing it twice would hand it out
+    /// to two resources
+    fn release(&mut self, frame: u64, index: u32) -> bool {
+        let allocated = self.is_allocated(index);
+        if allocated {
+            self.pending.push((frame, index));
+        }
+        allocated
+    }
+
+    /// Makes the indices freed up to `frame` included available again
+    fn reclaim(&mut self, frame: u64) {
+        let free = &mut self.free;
+        self.pending.retain(|(tag, index)| {
+            if *tag <= frame {
+                free.push(*index);
+            }
+            *tag > frame
+        });
+    }
+
+    fn len(&self) -> u32 {
+        self.next - self.free.len() as u32 - self.pending.len() as u32
+    }
+}
+
+struct Allocation {
+    slots: [Slots; 4],
+    frame: u64,
+}
+
+/// Global descriptor set with large arrays of every resource kind, shaders index them with
+/// the stable ids handed out here. Bound once per command buffer, descriptors can be written
+/// while it is in use (update after bind) and unwritten ones are never read (partially bound).
+/// Give it back with `Device::destroy_bindless_heap`
+pub struct BindlessHeap {
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    desc: BindlessDesc,
+    allocation: Mutex<Allocation>,
+}
+
+impl BindlessHeap {
+    pub(in crate::core) fn new(
+        device: &ash::Device,
+        desc: BindlessDesc,
+    ) -> Result<Self, RendererError> {
+        // freed slots get rewritten while earlier frames still have the set bound
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+            4];
+        let bindings = BindlessKind::ALL.map(|kind| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(kind.binding())
+                .descriptor_type(kind.descriptor_type())
+                .descriptor_count(desc.count(kind))
+                .stage_flags(vk::ShaderStageFlags::ALL)
+        });
+        let mut flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut flags_info);
+        let layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+
+        let sizes = BindlessKind::ALL.map(|kind| vk::DescriptorPoolSize {
+            ty: kind.descriptor_type(),
+            descriptor_count: desc.count(kind),
+        });
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&sizes);
+        let pool = match unsafe { device.create_descriptor_pool(&pool_info, None) } {
+            Ok(pool) => pool,
+            Err(err) => {
+                unsafe { device.destroy_descriptor_set_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        let layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        let set = match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+            Ok(sets) => sets[0],
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(pool, None);
+                    device.destroy_descriptor_set_layout(layout, None);
+                }
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            pool,
+            layout,
+            set,
+            desc,
+            allocation: Mutex::new(Allocation {
+                slots: BindlessKind::ALL.map(|kind| Slots::new(desc.count(kind))),
+                frame: 0,
+            }),
+        })
+    }
+
+    /// Goes in the pipeline set layouts at the set number the shaders use for it
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    pub fn capacity(&self, kind: BindlessKind) -> u32 {
+        self.desc.count(kind)
+    }
+
+    /// Indices in use, freed ones waiting for their frames included
+    pub fn len(&self, kind: BindlessKind) -> u32 {
+        let allocation = self.allocation();
+        let slots = &allocation.slots[kind as usize];
+        slots.len() + slots.pending.len() as u32
+    }
+
+    /// Image view in `layout` (usually SHADER_READ_ONLY_OPTIMAL) for `texture2D` arrays
+    pub fn add_sampled_image(
+        &self,
+        device: &Device,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Result<u32, RendererError> {
+        let index = self.allocate(BindlessKind::SampledImage)?;
+        self.write_sampled_image(device, index, view, layout)?;
+        Ok(index)
+    }
+
+    /// Image view in GENERAL layout for `image2D` arrays
+    pub fn add_storage_image(
+        &self,
+        device: &Device,
+        view: vk::ImageView,
+    ) -> Result<u32, RendererError> {
+        let index = self.allocate(BindlessKind::StorageImage)?;
+        self.write_storage_image(device, index, view)?;
+        Ok(index)
+    }
+
+    pub fn add_sampler(&self, device: &Device, sampler: vk::Sampler) -> Result<u32, RendererError> {
+        let index = self.allocate(BindlessKind::Sampler)?;
+        self.write_sampler(device, index, sampler)?;
+        Ok(index)
+    }
+
+    /// The whole buffer, it needs the STORAGE_BUFFER usage
+    pub fn add_storage_buffer(
+        &self,
+        device: &Device,
+        buffer: &Buffer,
+    ) -> Result<u32, RendererError> {
+        let index = self.allocate(BindlessKind::StorageBuffer)?;
+        self.write_storage_buffer(device, index, buffer.handle(), 0, vk::WHOLE_SIZE)?;
+        Ok(index)
+    }
+
+    /// Points an index to another view, e.g. once a streamed texture gets its full mip chain.
+    /// Frames in flight may see either view. Like every `write_*` it fails for indices that
+    /// aren't allocated, a freed index may already belong to another resource
+    pub fn write_sampled_image(
+        &self,
+        device: &Device,
+        index: u32,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Result<(), RendererError> {
+        let info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(layout)];
+        self.write_images(device, BindlessKind::SampledImage, index, &info)
+    }
+
+    pub fn write_storage_image(
+        &self,
+        device: &Device,
+        index: u32,
+        view: vk::ImageView,
+    ) -> Result<(), RendererError> {
+        let info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+        self.write_images(device, BindlessKind::StorageImage, index, &info)
+    }
+
+    pub fn write_sampler(
+        &self,
+        device: &Device,
+        index: u32,
+        sampler: vk::Sampler,
+    ) -> Result<(), RendererError> {
+        let info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+        self.write_images(device, BindlessKind::Sampler, index, &info)
+    }
+
+    pub fn write_storage_buffer(
+        &self,
+        device: &Device,
+        index: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Result<(), RendererError> {
+        let info = [vk::DescriptorBufferInfo {
+            buffer,
+            offset,
+            range,
+        }];
+        let write = vk::WriteDescriptorSet::default().buffer_info(&info);
+        self.update(device, BindlessKind::StorageBuffer, index, write)
+    }
+
+    /// The index is handed out again once the frames recorded up to now are done. The
+    /// resource itself can be destroyed right away through the device, it is retired as well.
+    /// Fails for indices that aren't allocated, including ones already freed
+    pub fn free(&self, kind: BindlessKind, index: u32) -> Result<(), RendererError> {
+        let mut allocation = self.allocation();
+        let frame = allocation.frame;
+        if !allocation.slots[kind as usize].release(frame, index) {
+            return Err(RendererError::InvalidUsage(format!(
+                "{:?} index {} is not allocated",
+                kind, index
+            )));
+        }
+        Ok(())
+    }
+
+    /// Called at the start of every frame like `Device::advance_frame`, recycles the indices
+    /// freed up to `completed`
+    pub fn advance_frame(&self, frame: u64, completed: Option<u64>) {
+        let mut allocation = self.allocation();
+        allocation.frame = frame;
+        if let Some(completed) = completed {
+            for slots in &mut allocation.slots {
+                slots.reclaim(completed);
+            }
+        }
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (vk::DescriptorPool, vk::DescriptorSetLayout) {
+        (self.pool, self.layout)
+    }
+
+    fn allocate(&self, kind: BindlessKind) -> Result<u32, RendererError> {
+        self.allocation().slots[kind as usize]
+            .allocate()
+            .ok_or(RendererError::OutOfMemory(
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            ))
+    }
+
+    fn write_images(
+        &self,
+        device: &Device,
+        kind: BindlessKind,
+        index: u32,
+        info: &[vk::DescriptorImageInfo],
+    ) -> Result<(), RendererError> {
+        self.update(
+            device,
+            kind,
+            index,
+            vk::WriteDescriptorSet::default().image_info(info),
+        )
+    }
+
+    /// Writes the descriptor at `index` of the `kind` array, the lock is held so the index
+    /// can't be freed in between
+    fn update(
+        &self,
+        device: &Device,
+        kind: BindlessKind,
+        index: u32,
+        write: vk::WriteDescriptorSet,
+    ) -> Result<(), RendererError> {
+        let allocation = self.allocation();
+        if !allocation.slots[kind as usize].is_allocated(index) {
+            return Err(RendererError::InvalidUsage(format!(
+                "{:?} index {} is not allocated",
+                kind, index
+            )));
+        }
+        let write = write
+            .dst_set(self.set)
+            .dst_binding(kind.binding())
+            .dst_array_element(index)
+            .descriptor_type(kind.descriptor_type());
+        unsafe { device.handle().update_descriptor_sets(&[write], &[]) };
+        Ok(())
+    }
+
+    fn allocation(&self) -> MutexGuard<'_, Allocation> {
+        self.allocation
+            .lock()
+            .expect("Bindless allocation lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_every_index_once() {
+        let mut slots = Slots::new(3);
+        assert_eq!(slots.allocate(), Some(0));
+        assert_eq!(slots.allocate(), Some(1));
+        assert_eq!(slots.allocate(), Some(2));
+        assert_eq!(slots.allocate(), None);
+        assert_eq!(slots.len(), 3);
+    }
+
+    #[test]
+    fn recycles_only_after_the_frame_is_done() {
+        let mut slots = Slots::new(2);
+        let first = slots.allocate().unwrap();
+        slots.allocate().unwrap();
+
+        assert!(slots.release(5, first));
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots.allocate(), None);
+
+        slots.reclaim(4);
+        assert_eq!(slots.allocate(), None);
+
+        slots.reclaim(5);
+        assert_eq!(slots.allocate(), Some(first));
+        assert_eq!(slots.allocate(), None);
+    }
+
+    #[test]
+    fn rejects_indices_that_are_not_allocated() {
+        let mut slots = Slots::new(4);
+        let index = slots.allocate().unwrap();
+
+        assert!(slots.is_allocated(index));
+        assert!(!slots.is_allocated(3));
+        assert!(!slots.release(1, 3));
+        assert!(slots.release(1, index));
+        assert!(
+            !slots.is_allocated(index),
+            "pending indices can't be written"
+        );
+        assert!(!slots.release(1, index));
+
+        slots.reclaim(1);
+        assert!(!slots.is_allocated(index));
+        assert!(!slots.release(2, index));
+        assert_eq!(slots.allocate(), Some(index));
+        assert!(slots.is_allocated(index));
+        assert_eq!(slots.allocate(), Some(1));
+    }
+
+    #[test]
+    fn arrays_share_the_per_stage_budget() {
+        let requested = BindlessDesc {
+            sampled_images: 1000,
+            storage_images: 500,
+            samplers: 100,
+            storage_buffers: 500,
+        };
+        let per_set = BindlessDesc {
+            samplers: 64,
+            ..requested
+        };
+        let per_stage = BindlessDesc {
+            storage_images: 200,
+            ..requested
+        };
+
+        let desc = requested.clamp(&per_set, &per_stage, 4000);
+        assert_eq!(
+            desc,
+            BindlessDesc {
+                storage_images: 200,
+                samplers: 64,
+                ..requested
+            }
+        );
+
+        // samplers don't count against the resources
+        let desc = requested.clamp(&per_set, &per_stage, 850);
+        assert_eq!(
+            desc,
+            BindlessDesc {
+                sampled_images: 500,
+                storage_images: 100,
+                samplers: 64,
+                storage_buffers: 250,
+            }
+        );
+    }
+}
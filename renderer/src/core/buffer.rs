@@ -0,0 +1,273 @@
+use std::{mem::size_of, ops::Range};
+
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::{GpuLayout, RendererError};
+
+use super::{Allocation, MemoryUsage};
+
+/// How a buffer gets created, start from one of the usage constructors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferDesc {
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+    /// Host visible memory stays mapped for the whole buffer lifetime
+    pub memory: MemoryUsage,
+}
+
+impl BufferDesc {
+    pub fn new(size: vk::DeviceSize, usage: vk::BufferUsageFlags, memory: MemoryUsage) -> Self {
+        Self {
+            size,
+            usage,
+            memory,
+        }
+    }
+
+    /// Gpu only, filled through a transfer
+    pub fn vertex(size: vk::DeviceSize) -> Self {
+        Self::gpu_only(size, vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    /// Gpu only, filled through a transfer
+    pub fn index(size: vk::DeviceSize) -> Self {
+        Self::gpu_only(size, vk::BufferUsageFlags::INDEX_BUFFER)
+    }
+
+    /// Gpu only, filled through a transfer or by shaders
+    pub fn storage(size: vk::DeviceSize) -> Self {
+        Self::gpu_only(size, vk::BufferUsageFlags::STORAGE_BUFFER)
+    }
+
+    /// Gpu only, also usable as a storage buffer so compute can write the commands
+    pub fn indirect(size: vk::DeviceSize) -> Self {
+        Self::gpu_only(
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+    }
+
+    /// Mapped, written by the cpu every frame
+    pub fn uniform(size: vk::DeviceSize) -> Self {
+        Self::new(
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryUsage::Upload,
+        )
+    }
+
+    /// Mapped source of transfers to gpu only resources
+    pub fn staging(size: vk::DeviceSize) -> Self {
+        Self::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryUsage::Upload,
+        )
+    }
+
+    /// Mapped destination of transfers read back by the cpu
+    pub fn readback(size: vk::DeviceSize) -> Self {
+        Self::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::Readback,
+        )
+    }
+
+    fn gpu_only(size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> Self {
+        Self::new(
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuOnly,
+        )
+    }
+
+    /// Adds usage flags on top of the ones picked by the constructor
+    pub fn with_usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+
+    /// `MemoryUsage::Upload` makes vertex/index/storage buffers mapped and writable every frame
+    pub fn with_memory(mut self, memory: MemoryUsage) -> Self {
+        self.memory = memory;
+        self
+    }
+}
+
+/// Buffer with its memory, give it back with `Device::destroy_buffer` so it outlives the
+/// frames still using it
+#[derive(Debug)]
+pub struct Buffer {
+    handle: vk::Buffer,
+    allocation: Allocation,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    address: Option<vk::DeviceAddress>,
+}
+
+impl Buffer {
+    pub(in crate::core) fn new(
+        handle: vk::Buffer,
+        allocation: Allocation,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        address: Option<vk::DeviceAddress>,
+    ) -> Self {
+        Self {
+            handle,
+            allocation,
+            size,
+            usage,
+            address,
+        }
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (vk::Buffer, Allocation) {
+        (self.handle, self.allocation)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    /// Requested size, the allocation may be bigger
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    /// None unless buffer device address is enabled on the device
+    pub fn device_address(&self) -> Option<vk::DeviceAddress> {
+        self.address
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.allocation.mapped_ptr().is_some()
+    }
+
+    /// Mapped bytes of the buffer, None for gpu only memory
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        let size = self.size as usize;
+        self.allocation
+            .mapped_slice_mut()
+            .map(|bytes| &mut bytes[..size])
+    }
+
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        let size = self.size as usize;
+        self.allocation.mapped_slice().map(|bytes| &bytes[..size])
+    }
+
+    /// Copies `data` at `offset` bytes, fails if the buffer isn't mapped or the data doesn't fit
+    pub fn write_slice<T: Pod>(
+        &mut self,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<(), RendererError> {
+        self.write_bytes(offset, bytemuck::cast_slice(data))
+    }
+
+    pub fn write<T: Pod>(
+        &mut self,
+        offset: vk::DeviceSize,
+        value: &T,
+    ) -> Result<(), RendererError> {
+        self.write_slice(offset, std::slice::from_ref(value))
+    }
+
+    /// Writes a block laid out by `T::RULES`, padding is zeroed
+    pub fn write_block<T: GpuLayout>(
+        &mut self,
+        offset: vk::DeviceSize,
+        block: &T,
+    ) -> Result<(), RendererError> {
+        self.write_bytes(offset, &block.to_bytes())
+    }
+
+    /// Writes `blocks` as a runtime array, one element every `T::RULES` stride
+    pub fn write_blocks<T: GpuLayout>(
+        &mut self,
+        offset: vk::DeviceSize,
+        blocks: &[T],
+    ) -> Result<(), RendererError> {
+        let stride = T::RULES.field::<T>().size;
+        let len = stride.checked_mul(blocks.len()).ok_or_else(|| {
+            RendererError::OutOfRange(format!("{} blocks don't fit in memory", blocks.len()))
+        })?;
+        let mut bytes = vec![0; len];
+        for (index, block) in blocks.iter().enumerate() {
+            block.write_bytes(&mut bytes[index * stride..]);
+        }
+        self.write_bytes(offset, &bytes)
+    }
+
+    fn write_bytes(&mut self, offset: vk::DeviceSize, bytes: &[u8]) -> Result<(), RendererError> {
+        let mapped = self.mapped_slice_mut().ok_or_else(|| {
+            RendererError::InvalidUsage("writing to a buffer that isn't mapped".to_owned())
+        })?;
+        let range = mapped_range(offset, bytes.len(), mapped.len())?;
+        mapped[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Reads `count` elements at `offset` bytes, fails if the buffer isn't mapped or too small
+    pub fn read_slice<T: Pod>(
+        &self,
+        offset: vk::DeviceSize,
+        count: usize,
+    ) -> Result<Vec<T>, RendererError> {
+        let len = count.checked_mul(size_of::<T>()).ok_or_else(|| {
+            RendererError::OutOfRange(format!("{} elements don't fit in memory", count))
+        })?;
+        let mapped = self.mapped_slice().ok_or_else(|| {
+            RendererError::InvalidUsage("reading from a buffer that isn't mapped".to_owned())
+        })?;
+        let range = mapped_range(offset, len, mapped.len())?;
+        // the mapping has no alignment guarantee for T, copy instead of casting in place
+        let mut values = vec![T::zeroed(); count];
+        bytemuck::cast_slice_mut(&mut values).copy_from_slice(&mapped[range]);
+        Ok(values)
+    }
+}
+
+/// Bytes `offset..offset + len` of a mapping `size` bytes long
+fn mapped_range(
+    offset: vk::DeviceSize,
+    len: usize,
+    size: usize,
+) -> Result<Range<usize>, RendererError> {
+    usize::try_from(offset)
+        .ok()
+        .and_then(|start| Some(start..start.checked_add(len)?))
+        .filter(|range| range.end <= size)
+        .ok_or_else(|| {
+            RendererError::OutOfRange(format!(
+                "access of {} bytes at {} overflows a {} bytes buffer",
+                len, offset, size
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_range_rejects_overflowing_accesses() {
+        assert_eq!(mapped_range(0, 16, 16).unwrap(), 0..16);
+        assert_eq!(mapped_range(12, 4, 16).unwrap(), 12..16);
+        assert!(mapped_range(13, 4, 16).is_err());
+        assert!(mapped_range(16, 1, 16).is_err());
+        assert!(mapped_range(u64::MAX, 1, 16).is_err());
+        assert!(mapped_range(1, usize::MAX, 16).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use ash::vk;
+
+use crate::RendererError;
+
+use super::{
+    DescriptorLayoutCache, Device, Pipeline, PipelineLayout, PipelineStage, ShaderModule,
+    SpecValue, Specialization,
+};
+
+/// Compute pipeline from a single entry point. Set layouts and push constant ranges are
+/// derived from the shader when not given
+#[derive(Debug, Clone)]
+pub struct ComputePipelineBuilder<'a> {
+    module: &'a ShaderModule,
+    entry_point: String,
+    spec_constants: Vec<(u32, SpecValue)>,
+    set_layouts: Option<Vec<vk::DescriptorSetLayout>>,
+    push_constant_ranges: Option<Vec<vk::PushConstantRange>>,
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn new(module: &'a ShaderModule, entry_point: &str) -> Self {
+        Self {
+            module,
+            entry_point: entry_point.to_owned(),
+            spec_constants: vec![],
+            set_layouts: None,
+            push_constant_ranges: None,
+        }
+    }
+
+    /// Must match the type the shader declares for `id`
+    pub fn spec_constant(mut self, id: u32, value: SpecValue) -> Self {
+        self.spec_constants.push((id, value));
+        self
+    }
+
+    /// Used instead of the layouts derived from the shader, they stay owned by the caller
+    pub fn set_layouts(mut self, layouts: &[vk::DescriptorSetLayout]) -> Self {
+        self.set_layouts = Some(layouts.to_vec());
+        self
+    }
+
+    /// Adding one replaces the range derived from the shader
+    pub fn push_constant_range(mut self, range: vk::PushConstantRange) -> Self {
+        self.push_constant_ranges
+            .get_or_insert_with(Vec::new)
+            .push(range);
+        self
+    }
+
+    pub fn build(&self, device: &Device) -> Result<Pipeline, RendererError> {
+        device.create_compute_pipeline(self)
+    }
+
+    pub(in crate::core) fn create(
+        &self,
+        device: &ash::Device,
+        cache: &DescriptorLayoutCache,
+    ) -> Result<Pipeline, RendererError> {
+        let stage = PipelineStage::new(self.module, &self.entry_point)?;
+        if stage.stage != vk::ShaderStageFlags::COMPUTE {
+            return Err(RendererError::InvalidPipeline(format!(
+                "\"{}\" is a {:?} entry point, not a compute one",
+                self.entry_point, stage.stage
+            )));
+        }
+
+        let reflections = [stage.reflection()];
+        let specialization = Specialization::new(&reflections, &self.spec_constants)?;
+        let specialization_info = specialization.info(0);
+        let mut stage_info = vk::PipelineShaderStageCreateInfo::default()
+            .stage(stage.stage)
+            .module(self.module.handle())
+            .name(&stage.entry_point);
+        if let Some(info) = &specialization_info {
+            stage_info = stage_info.specialization_info(info);
+        }
+
+        let layout = PipelineLayout::create(
+            device,
+            cache,
+            &reflections,
+            self.set_layouts.as_deref(),
+            self.push_constant_ranges.as_deref(),
+        )?;
+        let info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage_info)
+            .layout(layout.handle());
+
+        let result =
+            unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None) };
+        match result {
+            Ok(pipelines) => Ok(Pipeline::new(
+                pipelines[0],
+                layout,
+                vk::PipelineBindPoint::COMPUTE,
+            )),
+            Err((_, err)) => {
+                layout.destroy(device);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+/// How graphics work reads what a dispatch wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComputeOutput {
+    /// Draw arguments, e.g. written by GPU culling
+    IndirectArgs,
+    VertexBuffer,
+    IndexBuffer,
+    /// Buffers and images read by the vertex or fragment shaders
+    ShaderRead,
+}
+
+impl ComputeOutput {
+    fn destination(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        use vk::{AccessFlags2 as A, PipelineStageFlags2 as S};
+
+        match self {
+            Self::IndirectArgs => (S::DRAW_INDIRECT, A::INDIRECT_COMMAND_READ),
+            Self::VertexBuffer => (S::VERTEX_ATTRIBUTE_INPUT, A::VERTEX_ATTRIBUTE_READ),
+            Self::IndexBuffer => (S::INDEX_INPUT, A::INDEX_READ),
+            Self::ShaderRead => (
+                S::VERTEX_SHADER | S::FRAGMENT_SHADER,
+                A::SHADER_STORAGE_READ | A::SHADER_SAMPLED_READ | A::UNIFORM_READ,
+            ),
+        }
+    }
+}
+
+/// Makes the storage writes of the previous dispatches visible to the draws reading them
+/// as `outputs`. Images changing layout need their own barrier
+pub fn compute_to_graphics_barrier(outputs: &[ComputeOutput]) -> vk::MemoryBarrier2<'static> {
+    let (stage, access) = outputs.iter().fold(
+        (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+        |(stage, access), output| {
+            let (dst_stage, dst_access) = output.destination();
+            (stage | dst_stage, access | dst_access)
+        },
+    );
+    vk::MemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+        .dst_stage_mask(stage)
+        .dst_access_mask(access)
+}
+
+/// Between dispatches where the second reads or overwrites what the first wrote
+pub fn compute_to_compute_barrier() -> vk::MemoryBarrier2<'static> {
+    vk::MemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_access_mask(
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        )
+}
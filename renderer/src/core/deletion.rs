@@ -0,0 +1,43 @@
+use ash::vk;
+
+use super::Allocation;
+
+/// Gpu objects whose destruction waits for the frames that may still use them
+pub(in crate::core) enum Retired {
+    Buffer(vk::Buffer, Allocation),
+    Image(vk::Image, vk::ImageView, Allocation),
+    ImageView(vk::ImageView),
+    Sampler(vk::Sampler),
+    DescriptorPool(vk::DescriptorPool),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+    Pipeline(vk::Pipeline, vk::PipelineLayout),
+}
+
+/// Objects tagged with the frame they were released in
+#[derive(Default)]
+pub(in crate::core) struct DeletionQueue {
+    pending: Vec<(u64, Retired)>,
+}
+
+impl DeletionQueue {
+    pub fn push(&mut self, frame: u64, object: Retired) {
+        self.pending.push((frame, object));
+    }
+
+    /// Removes everything released up to `frame` included
+    pub fn drain_until(&mut self, frame: u64) -> Vec<Retired> {
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(tag, _)| *tag <= frame);
+        self.pending = pending;
+        ready.into_iter().map(|(_, object)| object).collect()
+    }
+
+    pub fn drain_all(&mut self) -> Vec<Retired> {
+        self.pending.drain(..).map(|(_, object)| object).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use ash::vk;
+
+use crate::RendererError;
+
+use super::Device;
+
+/// Pools never get bigger than this many sets
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// What makes two set layouts interchangeable, bindings sorted by number
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LayoutKey(Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>);
+
+impl LayoutKey {
+    fn new(bindings: &[vk::DescriptorSetLayoutBinding]) -> Self {
+        let mut key: Vec<_> = bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                )
+            })
+            .collect();
+        key.sort_unstable_by_key(|(binding, ..)| *binding);
+        Self(key)
+    }
+}
+
+/// Set layouts shared by every user asking for the same bindings, they live as long as the
+/// cache. Immutable samplers are not supported
+#[derive(Default)]
+pub struct DescriptorLayoutCache {
+    layouts: Mutex<HashMap<LayoutKey, vk::DescriptorSetLayout>>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the layout the first time these bindings are seen, in any order
+    pub fn get(
+        &self,
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, RendererError> {
+        self.get_raw(device.handle(), bindings)
+    }
+
+    pub(in crate::core) fn get_raw(
+        &self,
+        device: &ash::Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, RendererError> {
+        let key = LayoutKey::new(bindings);
+        let mut layouts = self.layouts();
+        if let Some(layout) = layouts.get(&key) {
+            return Ok(*layout);
+        }
+
+        let bindings: Vec<_> = key
+            .0
+            .iter()
+            .map(|(binding, descriptor_type, count, stages)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(*binding)
+                    .descriptor_type(*descriptor_type)
+                    .descriptor_count(*count)
+                    .stage_flags(*stages)
+            })
+            .collect();
+        let info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let layout = unsafe { device.create_descriptor_set_layout(&info, None)? };
+        layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The layouts must not be in use anymore
+    pub fn destroy(self, device: &Device) {
+        self.destroy_raw(device.handle());
+    }
+
+    pub(in crate::core) fn destroy_raw(self, device: &ash::Device) {
+        let layouts = self
+            .layouts
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        for layout in layouts.into_values() {
+            unsafe { device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+
+    fn layouts(&self) -> MutexGuard<'_, HashMap<LayoutKey, vk::DescriptorSetLayout>> {
+        self.layouts
+            .lock()
+            .expect("Descriptor layout cache lock poisoned")
+    }
+}
+
+/// Hands out sets from a list of pools, creating a bigger pool whenever the current one runs
+/// out of space. Sets are not freed one by one, `reset` gives back all of them at once
+pub struct DescriptorAllocator {
+    /// Descriptors of each type per set in a pool
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    /// Pools with space left, the last one is used first
+    ready: Vec<vk::DescriptorPool>,
+    full: Vec<vk::DescriptorPool>,
+    /// The last ready pool handed out a set, the others are empty
+    used: bool,
+}
+
+impl Default for DescriptorAllocator {
+    fn default() -> Self {
+        Self::new(64, &Self::DEFAULT_RATIOS)
+    }
+}
+
+impl DescriptorAllocator {
+    /// Mostly uniform buffers and images, enough for the usual material and pass sets.
+    /// Every core descriptor type is there, a set using one a pool lacks never fits
+    pub const DEFAULT_RATIOS: [(vk::DescriptorType, f32); 11] = [
+        (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+        (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+        (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+        (vk::DescriptorType::SAMPLED_IMAGE, 2.0),
+        (vk::DescriptorType::SAMPLER, 1.0),
+        (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+        (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+        (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1.0),
+        (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 0.5),
+        (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 0.5),
+        (vk::DescriptorType::INPUT_ATTACHMENT, 0.5),
+    ];
+
+    /// The first pool holds `sets_per_pool` sets, each new one 50% more
+    pub fn new(sets_per_pool: u32, ratios: &[(vk::DescriptorType, f32)]) -> Self {
+        Self {
+            ratios: ratios.to_vec(),
+            sets_per_pool: sets_per_pool.clamp(1, MAX_SETS_PER_POOL),
+            ready: vec![],
+            full: vec![],
+            used: false,
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, RendererError> {
+        let pool = self.pool(device)?;
+        let set = match Self::allocate_from(device, pool, layout) {
+            // an empty pool that can't hold the set means no pool ever will, keep it for others
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                if self.used =>
+            {
+                self.full
+                    .push(self.ready.pop().expect("the pool was ready"));
+                self.used = false;
+                let pool = self.pool(device)?;
+                Self::allocate_from(device, pool, layout)?
+            }
+            result => result?,
+        };
+        self.used = true;
+        Ok(set)
+    }
+
+    /// Gives back every set allocated so far, none may still be in use
+    pub fn reset(&mut self, device: &Device) -> Result<(), RendererError> {
+        self.ready.append(&mut self.full);
+        self.used = false;
+        for pool in &self.ready {
+            unsafe {
+                device
+                    .handle()
+                    .reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty())?
+            };
+        }
+        Ok(())
+    }
+
+    pub fn pool_count(&self) -> usize {
+        self.ready.len() + self.full.len()
+    }
+
+    pub fn destroy(self, device: &Device) {
+        for pool in self.ready.into_iter().chain(self.full) {
+            unsafe { device.handle().destroy_descriptor_pool(pool, None) };
+        }
+    }
+
+    fn allocate_from(
+        device: &Device,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, vk::Result> {
+        let layouts = [layout];
+        let info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        unsafe { device.handle().allocate_descriptor_sets(&info) }.map(|sets| sets[0])
+    }
+
+    /// Last ready pool, a new one when there is none
+    fn pool(&mut self, device: &Device) -> Result<vk::DescriptorPool, RendererError> {
+        if let Some(pool) = self.ready.last() {
+            return Ok(*pool);
+        }
+
+        let sizes: Vec<_> = self
+            .ratios
+            .iter()
+            .map(|(ty, ratio)| vk::DescriptorPoolSize {
+                ty: *ty,
+                descriptor_count: ((ratio * self.sets_per_pool as f32) as u32).max(1),
+            })
+            .collect();
+        let info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(self.sets_per_pool)
+            .pool_sizes(&sizes);
+        let pool = unsafe { device.handle().create_descriptor_pool(&info, None)? };
+
+        self.sets_per_pool = (self.sets_per_pool + self.sets_per_pool / 2).min(MAX_SETS_PER_POOL);
+        self.ready.push(pool);
+        Ok(pool)
+    }
+}
+
+enum WriteInfo {
+    /// Index in `buffers`
+    Buffer(usize),
+    /// Index in `images`
+    Image(usize),
+}
+
+struct PendingWrite {
+    binding: u32,
+    array_element: u32,
+    descriptor_type: vk::DescriptorType,
+    info: WriteInfo,
+}
+
+/// Collects buffer and image writes to apply them with a single `vkUpdateDescriptorSets`
+#[derive(Default)]
+pub struct DescriptorWriter {
+    buffers: Vec<vk::DescriptorBufferInfo>,
+    images: Vec<vk::DescriptorImageInfo>,
+    writes: Vec<PendingWrite>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buffer(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Self {
+        self.buffer_element(binding, 0, descriptor_type, buffer, offset, range)
+    }
+
+    /// Element `array_element` of an arrayed binding
+    pub fn buffer_element(
+        mut self,
+        binding: u32,
+        array_element: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Self {
+        self.buffers.push(vk::DescriptorBufferInfo {
+            buffer,
+            offset,
+            range,
+        });
+        self.writes.push(PendingWrite {
+            binding,
+            array_element,
+            descriptor_type,
+            info: WriteInfo::Buffer(self.buffers.len() - 1),
+        });
+        self
+    }
+
+    /// `sampler` is only read for SAMPLER and COMBINED_IMAGE_SAMPLER, pass a null one otherwise
+    pub fn image(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+        sampler: vk::Sampler,
+    ) -> Self {
+        self.image_element(binding, 0, descriptor_type, view, layout, sampler)
+    }
+
+    /// Element `array_element` of an arrayed binding
+    pub fn image_element(
+        mut self,
+        binding: u32,
+        array_element: u32,
+        descriptor_type: vk::DescriptorType,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+        sampler: vk::Sampler,
+    ) -> Self {
+        self.images.push(vk::DescriptorImageInfo {
+            sampler,
+            image_view: view,
+            image_layout: layout,
+        });
+        self.writes.push(PendingWrite {
+            binding,
+            array_element,
+            descriptor_type,
+            info: WriteInfo::Image(self.images.len() - 1),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Applies every write to `set`, the writer can be reused for other sets
+    pub fn update(&self, device: &Device, set: vk::DescriptorSet) {
+        let writes: Vec<_> = self
+            .writes
+            .iter()
+            .map(|write| {
+                let info = vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(write.binding)
+                    .dst_array_element(write.array_element)
+                    .descriptor_type(write.descriptor_type);
+                match write.info {
+                    WriteInfo::Buffer(index) => {
+                        info.buffer_info(std::slice::from_ref(&self.buffers[index]))
+                    }
+                    WriteInfo::Image(index) => {
+                        info.image_info(std::slice::from_ref(&self.images[index]))
+                    }
+                }
+            })
+            .collect();
+        unsafe { device.handle().update_descriptor_sets(&writes, &[]) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stages: vk::ShaderStageFlags,
+    ) -> vk::DescriptorSetLayoutBinding<'static> {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+            .stage_flags(stages)
+    }
+
+    #[test]
+    fn layout_key_ignores_binding_order() {
+        let ubo = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX,
+        );
+        let texture = binding(
+            1,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::ShaderStageFlags::FRAGMENT,
+        );
+        assert_eq!(
+            LayoutKey::new(&[ubo, texture]),
+            LayoutKey::new(&[texture, ubo])
+        );
+    }
+
+    #[test]
+    fn layout_key_tells_stages_apart() {
+        let vertex = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX,
+        );
+        let both = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
+        assert_ne!(LayoutKey::new(&[vertex]), LayoutKey::new(&[both]));
+    }
+}
@@ -1,34 +1,757 @@
+use std::{
+    ffi::{CStr, CString},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
 use ash::vk;
 
+use crate::RendererError;
+
+use super::{
+    aspect_flags, Allocation, AllocationRequest, Allocator, AllocatorStats, BindlessDesc,
+    BindlessHeap, Buffer, BufferDesc, ComputePipelineBuilder, DedicatedResource, DeletionQueue,
+    DescriptorLayoutCache, DeviceFeatures, GraphicsPipelineBuilder, Image, ImageDesc,
+    ImageViewDesc, Instance, MemoryUsage, Mesh, MeshDesc, Offscreen, Pipeline, Queue,
+    QueueFamilies, Readback, ResourceKind, Retired, SamplerDesc, ShaderModule, Spirv, Surface,
+    Swapchain, SwapchainPreferences, Texture,
+};
+
 pub struct Device {
+    /// Kept for the physical device queries
+    instance: ash::Instance,
     gpu: vk::PhysicalDevice,
+    properties: vk::PhysicalDeviceProperties,
+    memory: vk::PhysicalDeviceMemoryProperties,
     handle: ash::Device,
-    graphics: vk::Queue,
-    graphics_idx: u32,
+    families: QueueFamilies,
+    graphics: Queue,
+    present: Option<Queue>,
+    compute: Option<Queue>,
+    transfer: Option<Queue>,
+    extensions: Vec<CString>,
+    features: DeviceFeatures,
+    allocator: Mutex<Allocator>,
+    deletion: Mutex<DeletionQueue>,
+    /// Set layouts of the pipelines, and of anyone else asking through `descriptor_set_layout`
+    layout_cache: DescriptorLayoutCache,
+    /// Frame being recorded, tags the objects released during it
+    frame: AtomicU64,
 }
 
 impl Device {
     pub(in crate::core) fn new(
+        instance: &ash::Instance,
         gpu: vk::PhysicalDevice,
         handle: ash::Device,
-        graphics: vk::Queue,
-        graphics_idx: u32,
+        families: QueueFamilies,
+        extensions: Vec<CString>,
+        features: DeviceFeatures,
     ) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(gpu) };
+        let queue_props = unsafe { instance.get_physical_device_queue_family_properties(gpu) };
+        let queue = |family: u32| Queue::new(&handle, family, &queue_props[family as usize]);
+        let memory = unsafe { instance.get_physical_device_memory_properties(gpu) };
+        let allocator = Allocator::new(
+            handle.clone(),
+            memory,
+            &properties.limits,
+            features.buffer_device_address,
+        );
+
         Self {
+            instance: instance.clone(),
             gpu,
+            properties,
+            memory,
+            graphics: queue(families.graphics),
+            present: families.present.map(queue),
+            compute: families.compute.map(queue),
+            transfer: families.transfer.map(queue),
             handle,
-            graphics,
-            graphics_idx,
+            families,
+            extensions,
+            features,
+            allocator: Mutex::new(allocator),
+            deletion: Mutex::new(DeletionQueue::default()),
+            layout_cache: DescriptorLayoutCache::new(),
+            frame: AtomicU64::new(0),
+        }
+    }
+
+    /// Required features plus the optional ones the gpu turned out to support
+    pub fn features(&self) -> &DeviceFeatures {
+        &self.features
+    }
+
+    /// Required extensions plus the optional ones the gpu turned out to support
+    pub fn enabled_extensions(&self) -> &[CString] {
+        &self.extensions
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.extensions.iter().any(|ext| ext.as_c_str() == name)
+    }
+
+    pub fn gpu(&self) -> vk::PhysicalDevice {
+        self.gpu
+    }
+
+    pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.properties
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory
+    }
+
+    /// First memory type allowed by `type_bits` that has all the requested flags
+    pub fn find_memory_type(&self, type_bits: u32, flags: vk::MemoryPropertyFlags) -> Option<u32> {
+        self.memory
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .find(|(index, ty)| type_bits & (1 << index) != 0 && ty.property_flags.contains(flags))
+            .map(|(index, _)| index as u32)
+    }
+
+    /// Features of the format for the tiling, buffer features for `None`
+    pub fn format_features(
+        &self,
+        format: vk::Format,
+        tiling: Option<vk::ImageTiling>,
+    ) -> vk::FormatFeatureFlags {
+        let props = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.gpu, format)
+        };
+        match tiling {
+            Some(vk::ImageTiling::LINEAR) => props.linear_tiling_features,
+            Some(_) => props.optimal_tiling_features,
+            None => props.buffer_features,
+        }
+    }
+
+    pub fn supports_format(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> bool {
+        self.format_features(format, Some(tiling))
+            .contains(features)
+    }
+
+    /// First candidate supporting the features with optimal tiling
+    pub fn select_format(
+        &self,
+        candidates: &[vk::Format],
+        features: vk::FormatFeatureFlags,
+    ) -> Option<vk::Format> {
+        candidates
+            .iter()
+            .copied()
+            .find(|format| self.supports_format(*format, vk::ImageTiling::OPTIMAL, features))
+    }
+
+    /// Best depth format usable as an attachment, every gpu supports one of them
+    pub fn depth_format(&self) -> vk::Format {
+        self.select_format(
+            &[
+                vk::Format::D32_SFLOAT,
+                vk::Format::X8_D24_UNORM_PACK32,
+                vk::Format::D16_UNORM,
+            ],
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+        .unwrap_or(vk::Format::D16_UNORM)
+    }
+
+    /// Raw logical device, needed to create pipelines and record commands
+    pub fn handle(&self) -> &ash::Device {
+        &self.handle
+    }
+
+    pub fn queue_families(&self) -> &QueueFamilies {
+        &self.families
+    }
+
+    pub fn graphics_queue(&self) -> &Queue {
+        &self.graphics
+    }
+
+    pub fn graphics_family(&self) -> u32 {
+        self.families.graphics
+    }
+
+    /// Queue presenting the swapchain images, the graphics one when it can
+    pub fn present_queue(&self) -> &Queue {
+        self.present.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// Dedicated compute queue if the gpu has one, the graphics queue otherwise.
+    /// The renderer records its dispatches on the graphics queue, work submitted here has
+    /// to move exclusive resources across families itself, see `OwnershipTransfer`
+    pub fn compute_queue(&self) -> &Queue {
+        self.compute.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// Dedicated transfer queue if the gpu has one, the graphics queue otherwise
+    pub fn transfer_queue(&self) -> &Queue {
+        self.transfer.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// Nothing in the renderer submits to it, only uploads use the transfer one
+    pub fn dedicated_compute_queue(&self) -> Option<&Queue> {
+        self.compute.as_ref()
+    }
+
+    pub fn dedicated_transfer_queue(&self) -> Option<&Queue> {
+        self.transfer.as_ref()
+    }
+
+    pub fn create_swapchain(
+        &self,
+        instance: &Instance,
+        surface: &Surface,
+        extent: vk::Extent2D,
+        preferences: SwapchainPreferences,
+    ) -> Result<Swapchain, vk::Result> {
+        Swapchain::new(
+            instance.handle(),
+            &self.handle,
+            self.gpu,
+            surface,
+            &self.present_families(),
+            extent,
+            preferences,
+        )
+    }
+
+    /// `count` color images to render into when there is no window
+    pub fn create_offscreen(
+        &self,
+        extent: vk::Extent2D,
+        count: usize,
+    ) -> Result<Offscreen, RendererError> {
+        Offscreen::new(self, extent, count)
+    }
+
+    pub fn destroy_offscreen(&self, mut offscreen: Offscreen) {
+        offscreen.destroy_resources(self);
+    }
+
+    /// Host visible buffer sized for a single `extent` image of `format`
+    pub fn create_readback(
+        &self,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Readback, RendererError> {
+        Readback::new(self, extent, format)
+    }
+
+    pub fn destroy_readback(&self, readback: Readback) {
+        self.destroy_buffer(readback.into_buffer());
+    }
+
+    fn allocator(&self) -> MutexGuard<'_, Allocator> {
+        self.allocator.lock().expect("Allocator lock poisoned")
+    }
+
+    /// Sub-allocates memory for the request, nothing gets bound
+    pub fn allocate_memory(&self, request: &AllocationRequest) -> Result<Allocation, vk::Result> {
+        self.allocator().allocate(request)
+    }
+
+    /// Allocates and binds memory for the buffer, dedicated if the driver asks for it
+    pub fn allocate_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        usage: MemoryUsage,
+    ) -> Result<Allocation, vk::Result> {
+        let info = vk::BufferMemoryRequirementsInfo2::default().buffer(buffer);
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+        unsafe {
+            self.handle
+                .get_buffer_memory_requirements2(&info, &mut requirements)
+        };
+
+        let request = AllocationRequest {
+            requirements: requirements.memory_requirements,
+            usage,
+            kind: ResourceKind::Linear,
+            dedicated: (dedicated.prefers_dedicated_allocation == vk::TRUE)
+                .then_some(DedicatedResource::Buffer(buffer)),
+        };
+        let allocation = self.allocate_memory(&request)?;
+        let bound = unsafe {
+            self.handle
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+        };
+        match bound {
+            Ok(()) => Ok(allocation),
+            Err(err) => {
+                self.free_allocation(allocation);
+                Err(err)
+            }
+        }
+    }
+
+    /// Allocates and binds memory for an optimal tiling image, dedicated if the driver asks for it
+    pub fn allocate_image_memory(
+        &self,
+        image: vk::Image,
+        usage: MemoryUsage,
+    ) -> Result<Allocation, vk::Result> {
+        let info = vk::ImageMemoryRequirementsInfo2::default().image(image);
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+        unsafe {
+            self.handle
+                .get_image_memory_requirements2(&info, &mut requirements)
+        };
+
+        let request = AllocationRequest {
+            requirements: requirements.memory_requirements,
+            usage,
+            kind: ResourceKind::Optimal,
+            dedicated: (dedicated.prefers_dedicated_allocation == vk::TRUE)
+                .then_some(DedicatedResource::Image(image)),
+        };
+        let allocation = self.allocate_memory(&request)?;
+        let bound = unsafe {
+            self.handle
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        };
+        match bound {
+            Ok(()) => Ok(allocation),
+            Err(err) => {
+                self.free_allocation(allocation);
+                Err(err)
+            }
+        }
+    }
+
+    /// The resource using the allocation must not be in use by the gpu anymore
+    pub fn free_allocation(&self, allocation: Allocation) {
+        self.allocator().free(allocation);
+    }
+
+    pub fn allocator_stats(&self) -> AllocatorStats {
+        self.allocator().stats()
+    }
+
+    /// Buffer with bound memory, gets a device address when buffer device address is enabled
+    pub fn create_buffer(&self, desc: &BufferDesc) -> Result<Buffer, vk::Result> {
+        let mut usage = desc.usage;
+        if self.features.buffer_device_address {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
+        let info = vk::BufferCreateInfo::default()
+            .size(desc.size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let handle = unsafe { self.handle.create_buffer(&info, None)? };
+
+        let allocation = match self.allocate_buffer_memory(handle, desc.memory) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { self.handle.destroy_buffer(handle, None) };
+                return Err(err);
+            }
+        };
+
+        let address = self.features.buffer_device_address.then(|| {
+            let info = vk::BufferDeviceAddressInfo::default().buffer(handle);
+            unsafe { self.handle.get_buffer_device_address(&info) }
+        });
+
+        Ok(Buffer::new(handle, allocation, desc.size, usage, address))
+    }
+
+    /// Destroyed once the frames in flight at the time of the call are done with it
+    pub fn destroy_buffer(&self, buffer: Buffer) {
+        let (handle, allocation) = buffer.into_parts();
+        self.retire(Retired::Buffer(handle, allocation));
+    }
+
+    /// Image with bound memory and a view over all of it, fails if the format doesn't support
+    /// the usage or the size, mips, layers or samples go over the device limits
+    pub fn create_image(&self, desc: &ImageDesc) -> Result<Image, RendererError> {
+        let flags = desc.dimension.create_flags();
+        let limits = unsafe {
+            self.instance.get_physical_device_image_format_properties(
+                self.gpu,
+                desc.format,
+                vk::ImageType::TYPE_2D,
+                vk::ImageTiling::OPTIMAL,
+                desc.usage,
+                flags,
+            )
+        }
+        .map_err(|err| match err {
+            vk::Result::ERROR_FORMAT_NOT_SUPPORTED => RendererError::UnsupportedFormat(desc.format),
+            err => err.into(),
+        })?;
+
+        let layers = desc.array_layers();
+        if desc.extent.width > limits.max_extent.width
+            || desc.extent.height > limits.max_extent.height
+            || desc.mip_levels > limits.max_mip_levels
+            || layers > limits.max_array_layers
+            || !limits.sample_counts.contains(desc.samples)
+        {
+            return Err(RendererError::Unsupported(format!(
+                "{:?} image of {}x{}, {} mips, {} layers and {:?} samples, \
+                 the limits are {}x{}, {} mips, {} layers and {:?} samples",
+                desc.format,
+                desc.extent.width,
+                desc.extent.height,
+                desc.mip_levels,
+                layers,
+                desc.samples,
+                limits.max_extent.width,
+                limits.max_extent.height,
+                limits.max_mip_levels,
+                limits.max_array_layers,
+                limits.sample_counts
+            )));
+        }
+
+        let info = vk::ImageCreateInfo::default()
+            .flags(flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(desc.format)
+            .extent(vk::Extent3D {
+                width: desc.extent.width,
+                height: desc.extent.height,
+                depth: 1,
+            })
+            .mip_levels(desc.mip_levels)
+            .array_layers(layers)
+            .samples(desc.samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let handle = unsafe { self.handle.create_image(&info, None)? };
+
+        let allocation = match self.allocate_image_memory(handle, desc.memory) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { self.handle.destroy_image(handle, None) };
+                return Err(err.into());
+            }
+        };
+
+        let view = match self.image_view(handle, desc, &ImageViewDesc::default()) {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe { self.handle.destroy_image(handle, None) };
+                self.free_allocation(allocation);
+                return Err(err.into());
+            }
+        };
+
+        Ok(Image::new(&self.handle, handle, view, allocation, *desc))
+    }
+
+    /// Destroyed once the frames in flight at the time of the call are done with it
+    pub fn destroy_image(&self, image: Image) {
+        let (handle, view, allocation) = image.into_parts();
+        self.retire(Retired::Image(handle, view, allocation));
+    }
+
+    /// Extra view on part of the image, destroy it before the image
+    pub fn create_image_view(
+        &self,
+        image: &Image,
+        desc: &ImageViewDesc,
+    ) -> Result<vk::ImageView, vk::Result> {
+        self.image_view(image.handle(), image.desc(), desc)
+    }
+
+    pub fn destroy_image_view(&self, view: vk::ImageView) {
+        self.retire(Retired::ImageView(view));
+    }
+
+    fn image_view(
+        &self,
+        image: vk::Image,
+        image_desc: &ImageDesc,
+        desc: &ImageViewDesc,
+    ) -> Result<vk::ImageView, vk::Result> {
+        let format = desc.format.unwrap_or(image_desc.format);
+        let mut aspect = desc.aspect;
+        if aspect.is_empty() {
+            aspect = aspect_flags(format);
+            // views of both aspects can't be sampled, depth is the useful one
+            if aspect.contains(vk::ImageAspectFlags::DEPTH) {
+                aspect = vk::ImageAspectFlags::DEPTH;
+            }
+        }
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: aspect,
+            base_mip_level: desc.base_mip_level,
+            level_count: desc.mip_levels.unwrap_or(vk::REMAINING_MIP_LEVELS),
+            base_array_layer: desc.base_array_layer,
+            layer_count: desc.array_layers.unwrap_or(vk::REMAINING_ARRAY_LAYERS),
+        };
+        let info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(desc.view_type.unwrap_or(image_desc.dimension.view_type()))
+            .format(format)
+            .subresource_range(range);
+        unsafe { self.handle.create_image_view(&info, None) }
+    }
+
+    pub fn create_sampler(&self, desc: &SamplerDesc) -> Result<vk::Sampler, vk::Result> {
+        let anisotropy = desc
+            .max_anisotropy
+            .filter(|_| self.features.sampler_anisotropy)
+            .map(|max| max.clamp(1.0, self.properties.limits.max_sampler_anisotropy));
+
+        let info = vk::SamplerCreateInfo::default()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(desc.address_mode)
+            .address_mode_v(desc.address_mode)
+            .address_mode_w(desc.address_mode)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .compare_enable(desc.compare.is_some())
+            .compare_op(desc.compare.unwrap_or(vk::CompareOp::ALWAYS))
+            .min_lod(0.0)
+            .max_lod(desc.max_lod)
+            .border_color(desc.border_color);
+        unsafe { self.handle.create_sampler(&info, None) }
+    }
+
+    pub fn destroy_sampler(&self, sampler: vk::Sampler) {
+        self.retire(Retired::Sampler(sampler));
+    }
+
+    pub fn create_texture(
+        &self,
+        image: &ImageDesc,
+        sampler: &SamplerDesc,
+    ) -> Result<Texture, RendererError> {
+        let image = self.create_image(image)?;
+        match self.create_sampler(sampler) {
+            Ok(sampler) => Ok(Texture::new(image, sampler)),
+            Err(err) => {
+                self.destroy_image(image);
+                Err(err.into())
+            }
+        }
+    }
+
+    pub fn destroy_texture(&self, texture: Texture) {
+        let (image, sampler) = texture.into_parts();
+        self.destroy_image(image);
+        self.destroy_sampler(sampler);
+    }
+
+    /// Gpu only vertex and index buffers sized for the mesh, fill them through transfers
+    pub fn create_mesh(&self, desc: &MeshDesc) -> Result<Mesh, RendererError> {
+        desc.validate()?;
+        let vertices = self.create_buffer(&BufferDesc::vertex(desc.vertex_size()))?;
+        if !desc.is_indexed() {
+            return Ok(Mesh::new(vertices, None, desc));
+        }
+        match self.create_buffer(&BufferDesc::index(desc.index_size())) {
+            Ok(indices) => Ok(Mesh::new(vertices, Some(indices), desc)),
+            Err(err) => {
+                self.destroy_buffer(vertices);
+                Err(err.into())
+            }
+        }
+    }
+
+    pub fn destroy_mesh(&self, mesh: Mesh) {
+        let (vertices, indices) = mesh.into_parts();
+        self.destroy_buffer(vertices);
+        if let Some(indices) = indices {
+            self.destroy_buffer(indices);
+        }
+    }
+
+    /// Validates the reflection before creating the module, a module the parser can't walk
+    /// is rejected
+    pub fn create_shader_module(&self, spirv: &Spirv) -> Result<ShaderModule, RendererError> {
+        let reflection = spirv.reflect()?;
+        let handle = unsafe {
+            self.handle
+                .create_shader_module(&spirv.module_info(), None)?
+        };
+        Ok(ShaderModule::new(handle, reflection))
+    }
+
+    /// Pipelines don't reference their modules, so this can run right after creating them
+    pub fn destroy_shader_module(&self, module: ShaderModule) {
+        unsafe { self.handle.destroy_shader_module(module.handle(), None) };
+    }
+
+    pub fn create_graphics_pipeline(
+        &self,
+        builder: &GraphicsPipelineBuilder,
+    ) -> Result<Pipeline, RendererError> {
+        builder.create(&self.handle, &self.layout_cache)
+    }
+
+    pub fn create_compute_pipeline(
+        &self,
+        builder: &ComputePipelineBuilder,
+    ) -> Result<Pipeline, RendererError> {
+        builder.create(&self.handle, &self.layout_cache)
+    }
+
+    /// Shared layout for these bindings, created the first time they are asked for and kept
+    /// until the device is dropped. Pipelines deriving their layouts from reflection get
+    /// theirs from the same cache
+    pub fn descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, RendererError> {
+        self.layout_cache.get_raw(&self.handle, bindings)
+    }
+
+    /// Destroyed along with its layout once the frames that may use it are done
+    pub fn destroy_pipeline(&self, pipeline: Pipeline) {
+        let (handle, layout) = pipeline.into_parts();
+        self.retire(Retired::Pipeline(handle, layout));
+    }
+
+    /// Needs the `DeviceFeatures::bindless` features, the arrays are clamped to what the gpu
+    /// allows in an update after bind set and in a single stage
+    pub fn create_bindless_heap(&self, desc: &BindlessDesc) -> Result<BindlessHeap, RendererError> {
+        let missing = DeviceFeatures::bindless().missing(&self.features);
+        if !missing.is_empty() {
+            return Err(RendererError::Unsupported(format!(
+                "bindless descriptors without {}",
+                missing.join(", ")
+            )));
         }
+
+        let mut v12 = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut v12);
+        unsafe {
+            self.instance
+                .get_physical_device_properties2(self.gpu, &mut properties)
+        };
+        let per_set = BindlessDesc {
+            sampled_images: v12.max_descriptor_set_update_after_bind_sampled_images,
+            storage_images: v12.max_descriptor_set_update_after_bind_storage_images,
+            samplers: v12.max_descriptor_set_update_after_bind_samplers,
+            storage_buffers: v12.max_descriptor_set_update_after_bind_storage_buffers,
+        };
+        let per_stage = BindlessDesc {
+            sampled_images: v12.max_per_stage_descriptor_update_after_bind_sampled_images,
+            storage_images: v12.max_per_stage_descriptor_update_after_bind_storage_images,
+            samplers: v12.max_per_stage_descriptor_update_after_bind_samplers,
+            storage_buffers: v12.max_per_stage_descriptor_update_after_bind_storage_buffers,
+        };
+        let desc = desc.clamp(
+            &per_set,
+            &per_stage,
+            v12.max_per_stage_update_after_bind_resources,
+        );
+
+        BindlessHeap::new(&self.handle, desc)
+    }
+
+    /// Destroyed once the frames that may use it are done
+    pub fn destroy_bindless_heap(&self, heap: BindlessHeap) {
+        let (pool, layout) = heap.into_parts();
+        self.retire(Retired::DescriptorPool(pool));
+        self.retire(Retired::DescriptorSetLayout(layout));
     }
 
+    fn retire(&self, object: Retired) {
+        let frame = self.frame.load(Ordering::Acquire);
+        self.deletion().push(frame, object);
+    }
+
+    fn deletion(&self) -> MutexGuard<'_, DeletionQueue> {
+        self.deletion.lock().expect("Deletion queue lock poisoned")
+    }
+
+    /// Called at the start of every frame once `completed` is known to be done on the gpu,
+    /// destroys what was released up to it
+    pub fn advance_frame(&self, frame: u64, completed: Option<u64>) {
+        self.frame.store(frame, Ordering::Release);
+        if let Some(completed) = completed {
+            let ready = self.deletion().drain_until(completed);
+            self.destroy_retired(ready);
+        }
+    }
+
+    /// Waits for the gpu to be idle and destroys every released object
+    pub fn flush_retired(&self) {
+        self.wait_idle();
+        let ready = self.deletion().drain_all();
+        self.destroy_retired(ready);
+    }
+
+    fn destroy_retired(&self, objects: Vec<Retired>) {
+        for object in objects {
+            match object {
+                Retired::Buffer(handle, allocation) => {
+                    unsafe { self.handle.destroy_buffer(handle, None) };
+                    self.free_allocation(allocation);
+                }
+                Retired::Image(handle, view, allocation) => {
+                    unsafe {
+                        self.handle.destroy_image_view(view, None);
+                        self.handle.destroy_image(handle, None);
+                    }
+                    self.free_allocation(allocation);
+                }
+                Retired::ImageView(view) => unsafe { self.handle.destroy_image_view(view, None) },
+                Retired::Sampler(sampler) => unsafe { self.handle.destroy_sampler(sampler, None) },
+                Retired::DescriptorPool(pool) => unsafe {
+                    self.handle.destroy_descriptor_pool(pool, None)
+                },
+                Retired::DescriptorSetLayout(layout) => unsafe {
+                    self.handle.destroy_descriptor_set_layout(layout, None)
+                },
+                Retired::Pipeline(handle, layout) => unsafe {
+                    self.handle.destroy_pipeline(handle, None);
+                    self.handle.destroy_pipeline_layout(layout, None);
+                },
+            }
+        }
+    }
+
+    /// Families touching the swapchain images
+    fn present_families(&self) -> Vec<u32> {
+        let mut families = vec![self.families.graphics];
+        families.extend(self.families.present);
+        families
+    }
+
+    /// Pool for command buffers submitted to the graphics queue
     pub fn create_command_pool(
         &self,
         flags: vk::CommandPoolCreateFlags,
+    ) -> Result<vk::CommandPool, vk::Result> {
+        self.create_command_pool_for(self.families.graphics, flags)
+    }
+
+    pub fn create_command_pool_for(
+        &self,
+        family: u32,
+        flags: vk::CommandPoolCreateFlags,
     ) -> Result<vk::CommandPool, vk::Result> {
         let info = vk::CommandPoolCreateInfo::default()
             .flags(flags)
-            .queue_family_index(self.graphics_idx);
+            .queue_family_index(family);
 
         unsafe { self.handle.create_command_pool(&info, None) }
     }
@@ -48,6 +771,26 @@ impl Device {
         Ok(buffers[0])
     }
 
+    pub fn begin_command_buffer(
+        &self,
+        buffer: vk::CommandBuffer,
+        flags: vk::CommandBufferUsageFlags,
+    ) -> Result<(), vk::Result> {
+        let info = vk::CommandBufferBeginInfo::default().flags(flags);
+        unsafe { self.handle.begin_command_buffer(buffer, &info) }
+    }
+
+    pub fn end_command_buffer(&self, buffer: vk::CommandBuffer) -> Result<(), vk::Result> {
+        unsafe { self.handle.end_command_buffer(buffer) }
+    }
+
+    pub fn reset_command_buffer(&self, buffer: vk::CommandBuffer) -> Result<(), vk::Result> {
+        unsafe {
+            self.handle
+                .reset_command_buffer(buffer, vk::CommandBufferResetFlags::empty())
+        }
+    }
+
     pub fn wait_idle(&self) {
         let _ = unsafe { self.handle.device_wait_idle() };
     }
@@ -65,6 +808,10 @@ impl Device {
         unsafe { self.handle.create_fence(&info, None) }
     }
 
+    pub fn is_fence_signaled(&self, fence: vk::Fence) -> Result<bool, vk::Result> {
+        unsafe { self.handle.get_fence_status(fence) }
+    }
+
     pub fn wait_fence(&self, fence: vk::Fence, timeout: u64) -> Result<(), vk::Result> {
         unsafe { self.handle.wait_for_fences(&[fence], true, timeout) }
     }
@@ -88,6 +835,16 @@ impl Device {
 impl Drop for Device {
     fn drop(&mut self) {
         log::trace!("Destroying vulkan device");
+        let pending = self.deletion().len();
+        if pending > 0 {
+            log::trace!("Destroying {} released objects", pending);
+        }
+        self.flush_retired();
+        std::mem::take(&mut self.layout_cache).destroy_raw(&self.handle);
+        self.allocator
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner())
+            .destroy();
         unsafe { self.handle.destroy_device(None) }
     }
 }
@@ -0,0 +1,141 @@
+use ash::vk;
+
+/// Raw feature structs as vulkan wants them, chained through `PhysicalDeviceFeatures2`
+#[derive(Default)]
+pub(in crate::core) struct FeatureChain<'a> {
+    pub core: vk::PhysicalDeviceFeatures,
+    pub v12: vk::PhysicalDeviceVulkan12Features<'a>,
+    pub v13: vk::PhysicalDeviceVulkan13Features<'a>,
+}
+
+macro_rules! device_features {
+    ($($group:ident . $name:ident),* $(,)?) => {
+        /// Flattened view of the vulkan 1.0/1.2/1.3 features the renderer knows about
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+        pub struct DeviceFeatures {
+            $(pub $name: bool,)*
+        }
+
+        impl DeviceFeatures {
+            pub(in crate::core) fn from_chain(chain: &FeatureChain) -> Self {
+                Self {
+                    $($name: chain.$group.$name == vk::TRUE,)*
+                }
+            }
+
+            pub(in crate::core) fn to_chain<'a>(self) -> FeatureChain<'a> {
+                let mut chain = FeatureChain::default();
+                $(chain.$group.$name = self.$name.into();)*
+                chain
+            }
+
+            pub fn union(&self, other: &Self) -> Self {
+                Self {
+                    $($name: self.$name || other.$name,)*
+                }
+            }
+
+            pub fn intersection(&self, other: &Self) -> Self {
+                Self {
+                    $($name: self.$name && other.$name,)*
+                }
+            }
+
+            /// Names of the features set in `self` but not in `supported`
+            pub fn missing(&self, supported: &Self) -> Vec<&'static str> {
+                let mut missing = vec![];
+                $(
+                    if self.$name && !supported.$name {
+                        missing.push(stringify!($name));
+                    }
+                )*
+                missing
+            }
+        }
+    };
+}
+
+device_features! {
+    core.sampler_anisotropy,
+    core.fill_mode_non_solid,
+    core.wide_lines,
+    core.multi_draw_indirect,
+    core.texture_compression_bc,
+    core.shader_int64,
+    v12.buffer_device_address,
+    v12.descriptor_indexing,
+    v12.runtime_descriptor_array,
+    v12.descriptor_binding_partially_bound,
+    v12.descriptor_binding_variable_descriptor_count,
+    v12.descriptor_binding_update_unused_while_pending,
+    v12.descriptor_binding_sampled_image_update_after_bind,
+    v12.descriptor_binding_storage_image_update_after_bind,
+    v12.descriptor_binding_storage_buffer_update_after_bind,
+    v12.shader_sampled_image_array_non_uniform_indexing,
+    v12.shader_storage_image_array_non_uniform_indexing,
+    v12.shader_storage_buffer_array_non_uniform_indexing,
+    v12.timeline_semaphore,
+    v12.scalar_block_layout,
+    v12.draw_indirect_count,
+    v13.dynamic_rendering,
+    v13.synchronization2,
+    v13.maintenance4,
+}
+
+impl DeviceFeatures {
+    /// What the renderer can't work without, matches the device extensions enabled by default
+    pub fn renderer_defaults() -> Self {
+        Self {
+            dynamic_rendering: true,
+            synchronization2: true,
+            buffer_device_address: true,
+            descriptor_indexing: true,
+            ..Default::default()
+        }
+    }
+
+    /// What `Device::create_bindless_heap` needs on top of the renderer defaults
+    pub fn bindless() -> Self {
+        Self {
+            descriptor_indexing: true,
+            runtime_descriptor_array: true,
+            descriptor_binding_partially_bound: true,
+            descriptor_binding_update_unused_while_pending: true,
+            descriptor_binding_sampled_image_update_after_bind: true,
+            descriptor_binding_storage_image_update_after_bind: true,
+            descriptor_binding_storage_buffer_update_after_bind: true,
+            shader_sampled_image_array_non_uniform_indexing: true,
+            shader_storage_image_array_non_uniform_indexing: true,
+            shader_storage_buffer_array_non_uniform_indexing: true,
+            ..Default::default()
+        }
+    }
+
+    /// Queries what the gpu supports, the 1.2/1.3 structs are only chained if `api_version`,
+    /// the one from `effective_api_version`, allows it
+    pub fn query(instance: &ash::Instance, gpu: vk::PhysicalDevice, api_version: u32) -> Self {
+        let mut chain = FeatureChain::default();
+
+        let mut features2 = vk::PhysicalDeviceFeatures2::default();
+        if api_version >= vk::API_VERSION_1_2 {
+            features2 = features2.push_next(&mut chain.v12);
+        }
+        if api_version >= vk::API_VERSION_1_3 {
+            features2 = features2.push_next(&mut chain.v13);
+        }
+        unsafe { instance.get_physical_device_features2(gpu, &mut features2) };
+        chain.core = features2.features;
+
+        Self::from_chain(&chain)
+    }
+}
+
+/// Version both the instance and the gpu support, structs newer than it can't be chained
+pub fn effective_api_version(
+    instance: &ash::Instance,
+    gpu: vk::PhysicalDevice,
+    instance_version: u32,
+) -> u32 {
+    let device_version = unsafe { instance.get_physical_device_properties(gpu) }.api_version;
+    device_version.min(instance_version)
+}
@@ -1,109 +1,224 @@
-use super::Surface;
+use super::{
+    effective_api_version, DeviceFeatures, GpuCandidate, GpuSelector, QueueFamilies, Surface,
+};
+use crate::RendererError;
 use ash::vk;
 use std::{
-    collections::BTreeMap,
-    error::Error,
     ffi::{c_char, CStr},
+    fmt,
     result::Result,
 };
 
-// TODO: maybe structure Error as a proper enum
+/// Why a physical device was discarded during selection
+#[derive(Debug, Clone)]
+pub enum RejectionReason {
+    MissingExtension(String),
+    MissingFeatures(Vec<&'static str>),
+    NoGraphicsQueue,
+    NoPresentQueue,
+    NoSurfaceFormats,
+    NoPresentModes,
+    /// Suitable, but excluded by the `GpuSelector` in use
+    NotSelected(String),
+    /// A query on the device itself failed
+    Query(vk::Result),
+}
+
+#[derive(Debug, Clone)]
+pub struct GpuRejection {
+    pub index: usize,
+    pub name: String,
+    pub reason: RejectionReason,
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingExtension(name) => write!(f, "missing device extension \"{}\"", name),
+            Self::MissingFeatures(names) => {
+                write!(f, "missing device features: {}", names.join(", "))
+            }
+            Self::NoGraphicsQueue => write!(f, "no graphics queue family"),
+            Self::NoPresentQueue => {
+                write!(f, "no queue family can present to the surface")
+            }
+            Self::NoSurfaceFormats => write!(f, "no surface format available"),
+            Self::NoPresentModes => write!(f, "no present mode available"),
+            Self::NotSelected(why) => write!(f, "not selected: {}", why),
+            Self::Query(result) => write!(f, "device query failed: {}", result),
+        }
+    }
+}
+
+impl fmt::Display for GpuRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.index, self.name, self.reason)
+    }
+}
+
+/// Suitable devices ranked best first plus the reason every other device was discarded
+#[derive(Debug, Clone, Default)]
+pub struct GpuReport {
+    pub ranking: Vec<RankedGpu>,
+    pub rejections: Vec<GpuRejection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RankedGpu {
+    pub index: usize,
+    pub name: String,
+    pub score: i64,
+}
 
-/// If ok returns the gpu and the index of the graphics family
+pub struct GpuSelection {
+    pub gpu: vk::PhysicalDevice,
+    pub families: QueueFamilies,
+    pub report: GpuReport,
+}
+
+/// Picks the highest scoring suitable gpu, ties go to the first enumerated one.
+/// Without a surface presentation support is not checked, `api_version` is the instance one
 pub fn select_gpu(
     instance: &ash::Instance,
-    surface: &Surface,
+    api_version: u32,
+    surface: Option<&Surface>,
     extensions: &[*const c_char],
-) -> Result<(vk::PhysicalDevice, u32), Box<dyn Error>> {
-    let mut scoreboard: BTreeMap<i32, (vk::PhysicalDevice, u32)> = BTreeMap::new();
+    features: &DeviceFeatures,
+    selector: &GpuSelector,
+) -> Result<GpuSelection, RendererError> {
+    let selector = selector.resolve();
+    let mut suitable = vec![];
+    let mut report = GpuReport::default();
 
     let gpu_list = unsafe { instance.enumerate_physical_devices()? };
 
-    if gpu_list.is_empty() {
-        return Err("System doesn't have any physical device".into());
-    }
-
-    for gpu in gpu_list {
-        let props = unsafe { instance.get_physical_device_properties(gpu) };
-        let gpu_name = props.device_name_as_c_str()?.to_str()?;
-        log::trace!("Checking device: {}", gpu_name);
-
-        let graphics_index = match is_suitable(instance, gpu, extensions, surface)? {
-            None => {
-                log::trace!("Device is not suitable");
-                continue;
+    for (index, gpu) in gpu_list.into_iter().enumerate() {
+        let candidate = GpuCandidate::query(instance, gpu, index);
+        log::trace!("Checking device: {}", candidate.name);
+
+        let result = is_suitable(instance, api_version, gpu, extensions, features, surface)
+            .and_then(|families| Ok((selector.score(&candidate)?, families)));
+
+        match result {
+            Err(reason) => {
+                log::trace!("Device is not suitable: {}", reason);
+                report.rejections.push(GpuRejection {
+                    index,
+                    name: candidate.name,
+                    reason,
+                });
             }
-            Some(val) => val,
-        };
-
-        let score = rate(&props);
-        scoreboard.insert(score, (gpu, graphics_index));
+            Ok((score, families)) => {
+                suitable.push((gpu, families));
+                report.ranking.push(RankedGpu {
+                    index,
+                    name: candidate.name,
+                    score,
+                });
+            }
+        }
     }
 
-    if scoreboard.is_empty() {
-        return Err("No suitable physical device found".into());
-    }
+    // stable sort, equal scores keep the enumeration order
+    let mut order: Vec<usize> = (0..suitable.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(report.ranking[*i].score));
+    report.ranking = order.iter().map(|i| report.ranking[*i].clone()).collect();
 
-    let chosen = scoreboard.last_key_value().unwrap().1.to_owned();
-    Ok(chosen)
+    let Some(best) = order.first() else {
+        return Err(RendererError::NoSuitableGpu(report.rejections));
+    };
+    let (gpu, families) = suitable[*best];
+    log::info!(
+        "Selected GPU: {} (score {})",
+        report.ranking[0].name,
+        report.ranking[0].score
+    );
+
+    Ok(GpuSelection {
+        gpu,
+        families,
+        report,
+    })
+}
+
+/// Filters the optional extensions down to the ones supported by the gpu
+pub fn supported_extensions(
+    instance: &ash::Instance,
+    gpu: vk::PhysicalDevice,
+    optional: &[*const c_char],
+) -> Result<Vec<*const c_char>, vk::Result> {
+    let supported = unsafe { instance.enumerate_device_extension_properties(gpu)? };
+    let enabled = optional
+        .iter()
+        .copied()
+        .filter(|name| {
+            let name = unsafe { CStr::from_ptr(*name) };
+            let found = supported
+                .iter()
+                .any(|ext| ext.extension_name_as_c_str() == Ok(name));
+            if !found {
+                log::warn!(
+                    "Optional device extension \"{}\" is not supported",
+                    name.to_string_lossy()
+                );
+            }
+            found
+        })
+        .collect();
+    Ok(enabled)
 }
 
 fn is_suitable(
     instance: &ash::Instance,
+    api_version: u32,
     gpu: vk::PhysicalDevice,
     extensions: &[*const c_char],
-    surface: &Surface,
-) -> Result<Option<u32>, Box<dyn Error>> {
+    features: &DeviceFeatures,
+    surface: Option<&Surface>,
+) -> Result<QueueFamilies, RejectionReason> {
     // check that gpu supports all the required extensions
-    let supported_extensions = unsafe { instance.enumerate_device_extension_properties(gpu)? };
+    let supported_extensions = unsafe { instance.enumerate_device_extension_properties(gpu) }
+        .map_err(RejectionReason::Query)?;
     for required in extensions {
         let required = unsafe { CStr::from_ptr(*required) };
 
-        let mut found = false;
-        for supported in &supported_extensions {
-            let supported = supported.extension_name_as_c_str()?;
-            if supported == required {
-                found = true;
-                log::trace!("Device extension \"{}\" is supported", required.to_str()?);
-                break;
-            }
-        }
+        let found = supported_extensions
+            .iter()
+            .any(|supported| supported.extension_name_as_c_str() == Ok(required));
 
         if !found {
-            log::error!(
-                "Device extension \"{}\" is not supported",
-                required.to_str()?
-            );
-            return Ok(None);
+            return Err(RejectionReason::MissingExtension(
+                required.to_string_lossy().into_owned(),
+            ));
         }
+        log::trace!(
+            "Device extension \"{}\" is supported",
+            required.to_string_lossy()
+        );
     }
 
-    // TODO: check that gpu supports swapchain
+    // check that gpu supports all the required features
+    let api_version = effective_api_version(instance, gpu, api_version);
+    let missing = features.missing(&DeviceFeatures::query(instance, gpu, api_version));
+    if !missing.is_empty() {
+        return Err(RejectionReason::MissingFeatures(missing));
+    }
 
-    // check that gpu has a graphics queue family that can present to the surface
     let queue_props = unsafe { instance.get_physical_device_queue_family_properties(gpu) };
-    for (index, props) in queue_props.iter().enumerate() {
-        let support_graphics = props.queue_flags.contains(vk::QueueFlags::GRAPHICS);
-        let support_presenting = surface.support_presenting(gpu, index as u32)?;
 
-        if support_graphics && support_presenting {
-            log::trace!("Device supports a graphics queue that can present to the surface");
-            return Ok(Some(index as u32));
+    if let Some(surface) = surface {
+        // check that gpu can build a swapchain for the surface
+        let formats = surface.formats(gpu).map_err(RejectionReason::Query)?;
+        if formats.is_empty() {
+            return Err(RejectionReason::NoSurfaceFormats);
+        }
+        let present_modes = surface.present_modes(gpu).map_err(RejectionReason::Query)?;
+        if present_modes.is_empty() {
+            return Err(RejectionReason::NoPresentModes);
         }
     }
 
-    Ok(None)
-}
-
-fn rate(props: &vk::PhysicalDeviceProperties) -> i32 {
-    let mut score = match props.device_type {
-        vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
-        vk::PhysicalDeviceType::INTEGRATED_GPU => 100,
-        vk::PhysicalDeviceType::VIRTUAL_GPU => 50,
-        vk::PhysicalDeviceType::CPU => 10,
-        vk::PhysicalDeviceType::OTHER => 1,
-        _ => unreachable!("Added new device type"),
-    };
-    score += props.limits.max_image_dimension2_d as i32;
-    score
+    let families = QueueFamilies::find(gpu, &queue_props, surface)?;
+    log::trace!("Device queue families: {:?}", families);
+    Ok(families)
 }
@@ -0,0 +1,551 @@
+use ash::vk;
+
+use crate::{RendererError, Vertex};
+
+use super::{
+    numeric_type, DescriptorLayoutCache, Device, Pipeline, PipelineLayout, PipelineStage,
+    ShaderModule, SpecValue, Specialization,
+};
+
+/// Vertex buffer bindings and the attributes read from them
+#[derive(Debug, Clone, Default)]
+pub struct VertexLayout {
+    pub bindings: Vec<vk::VertexInputBindingDescription>,
+    pub attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn binding(mut self, binding: u32, stride: u32, input_rate: vk::VertexInputRate) -> Self {
+        self.bindings.push(vk::VertexInputBindingDescription {
+            binding,
+            stride,
+            input_rate,
+        });
+        self
+    }
+
+    pub fn attribute(
+        mut self,
+        location: u32,
+        binding: u32,
+        format: vk::Format,
+        offset: u32,
+    ) -> Self {
+        self.attributes.push(vk::VertexInputAttributeDescription {
+            location,
+            binding,
+            format,
+            offset,
+        });
+        self
+    }
+
+    /// Binding read once per vertex with the attributes of `V`
+    pub fn vertex<V: Vertex>(self, binding: u32) -> Self {
+        self.with_vertex::<V>(binding, vk::VertexInputRate::VERTEX)
+    }
+
+    /// Binding read once per instance, the locations of `I` must not clash with the other
+    /// bindings, set them with `#[vertex(location = ..)]`
+    pub fn instance<I: Vertex>(self, binding: u32) -> Self {
+        self.with_vertex::<I>(binding, vk::VertexInputRate::INSTANCE)
+    }
+
+    fn with_vertex<V: Vertex>(mut self, binding: u32, input_rate: vk::VertexInputRate) -> Self {
+        self.bindings
+            .push(V::binding_description(binding, input_rate));
+        self.attributes.extend(V::attribute_descriptions(binding));
+        self
+    }
+}
+
+/// How a color attachment is blended with what the fragment shader writes, None overwrites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlendState {
+    pub blend: Option<BlendEquation>,
+    pub write_mask: vk::ColorComponentFlags,
+}
+
+/// Color and alpha blend factors and operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlendEquation {
+    pub src_color: vk::BlendFactor,
+    pub dst_color: vk::BlendFactor,
+    pub color_op: vk::BlendOp,
+    pub src_alpha: vk::BlendFactor,
+    pub dst_alpha: vk::BlendFactor,
+    pub alpha_op: vk::BlendOp,
+}
+
+impl BlendState {
+    pub fn opaque() -> Self {
+        Self {
+            blend: None,
+            write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    /// Straight alpha, `src * a + dst * (1 - a)`
+    pub fn alpha() -> Self {
+        Self::with_factors(
+            vk::BlendFactor::SRC_ALPHA,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        )
+    }
+
+    /// Colors already multiplied by their alpha
+    pub fn premultiplied() -> Self {
+        Self::with_factors(vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+    }
+
+    pub fn additive() -> Self {
+        Self::with_factors(vk::BlendFactor::ONE, vk::BlendFactor::ONE)
+    }
+
+    fn with_factors(src: vk::BlendFactor, dst: vk::BlendFactor) -> Self {
+        Self {
+            blend: Some(BlendEquation {
+                src_color: src,
+                dst_color: dst,
+                color_op: vk::BlendOp::ADD,
+                src_alpha: vk::BlendFactor::ONE,
+                dst_alpha: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                alpha_op: vk::BlendOp::ADD,
+            }),
+            write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    pub fn with_write_mask(mut self, write_mask: vk::ColorComponentFlags) -> Self {
+        self.write_mask = write_mask;
+        self
+    }
+
+    fn attachment(&self) -> vk::PipelineColorBlendAttachmentState {
+        let state =
+            vk::PipelineColorBlendAttachmentState::default().color_write_mask(self.write_mask);
+        match self.blend {
+            Some(eq) => state
+                .blend_enable(true)
+                .src_color_blend_factor(eq.src_color)
+                .dst_color_blend_factor(eq.dst_color)
+                .color_blend_op(eq.color_op)
+                .src_alpha_blend_factor(eq.src_alpha)
+                .dst_alpha_blend_factor(eq.dst_alpha)
+                .alpha_blend_op(eq.alpha_op),
+            None => state,
+        }
+    }
+}
+
+/// Graphics pipeline drawn with dynamic rendering, the attachment formats take the place of a
+/// render pass. Viewport and scissor are always dynamic, `Frame::begin_rendering` sets them.
+/// Set layouts and push constant ranges are derived from the shaders when not given
+#[derive(Debug, Clone)]
+pub struct GraphicsPipelineBuilder<'a> {
+    stages: Vec<(&'a ShaderModule, String)>,
+    vertex_layout: VertexLayout,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    line_width: f32,
+    /// Constant factor, slope factor and clamp
+    depth_bias: Option<(f32, f32, f32)>,
+    samples: vk::SampleCountFlags,
+    depth_test: Option<(vk::CompareOp, bool)>,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    color_attachments: Vec<(vk::Format, BlendState)>,
+    depth_format: vk::Format,
+    stencil_format: vk::Format,
+    dynamic_state: Vec<vk::DynamicState>,
+    spec_constants: Vec<(u32, SpecValue)>,
+    set_layouts: Option<Vec<vk::DescriptorSetLayout>>,
+    push_constant_ranges: Option<Vec<vk::PushConstantRange>>,
+}
+
+impl Default for GraphicsPipelineBuilder<'_> {
+    fn default() -> Self {
+        Self {
+            stages: vec![],
+            vertex_layout: VertexLayout::default(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_bias: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_test: None,
+            stencil: None,
+            color_attachments: vec![],
+            depth_format: vk::Format::UNDEFINED,
+            stencil_format: vk::Format::UNDEFINED,
+            dynamic_state: vec![],
+            spec_constants: vec![],
+            set_layouts: None,
+            push_constant_ranges: None,
+        }
+    }
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The stage comes from the entry point declared by the module
+    pub fn shader(mut self, module: &'a ShaderModule, entry_point: &str) -> Self {
+        self.stages.push((module, entry_point.to_owned()));
+        self
+    }
+
+    pub fn vertex_layout(mut self, layout: VertexLayout) -> Self {
+        self.vertex_layout = layout;
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Index 0xFFFF/0xFFFFFFFF restarts strips and fans
+    pub fn primitive_restart(mut self, enabled: bool) -> Self {
+        self.primitive_restart = enabled;
+        self
+    }
+
+    /// LINE and POINT need the fill_mode_non_solid feature
+    pub fn polygon_mode(mut self, mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn line_width(mut self, width: f32) -> Self {
+        self.line_width = width;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant: f32, slope: f32, clamp: f32) -> Self {
+        self.depth_bias = Some((constant, slope, clamp));
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Needs a depth format
+    pub fn depth_test(mut self, compare: vk::CompareOp, write: bool) -> Self {
+        self.depth_test = Some((compare, write));
+        self
+    }
+
+    /// Needs a stencil format
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Attachments are numbered in the order they are added
+    pub fn color_attachment(mut self, format: vk::Format, blend: BlendState) -> Self {
+        self.color_attachments.push((format, blend));
+        self
+    }
+
+    pub fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    pub fn stencil_format(mut self, format: vk::Format) -> Self {
+        self.stencil_format = format;
+        self
+    }
+
+    /// States set while recording on top of the viewport and scissor, which always are
+    pub fn dynamic_state(mut self, states: &[vk::DynamicState]) -> Self {
+        self.dynamic_state = states.to_vec();
+        self
+    }
+
+    /// Must match the type the shaders declare for `id`
+    pub fn spec_constant(mut self, id: u32, value: SpecValue) -> Self {
+        self.spec_constants.push((id, value));
+        self
+    }
+
+    /// Used instead of the layouts derived from the shaders, they stay owned by the caller
+    pub fn set_layouts(mut self, layouts: &[vk::DescriptorSetLayout]) -> Self {
+        self.set_layouts = Some(layouts.to_vec());
+        self
+    }
+
+    /// Adding one replaces the range derived from the shaders
+    pub fn push_constant_range(mut self, range: vk::PushConstantRange) -> Self {
+        self.push_constant_ranges
+            .get_or_insert_with(Vec::new)
+            .push(range);
+        self
+    }
+
+    pub fn build(&self, device: &Device) -> Result<Pipeline, RendererError> {
+        device.create_graphics_pipeline(self)
+    }
+
+    /// The requested states plus the viewport and scissor the viewport state leaves out,
+    /// unless they are set with their count
+    fn dynamic_states(&self) -> Vec<vk::DynamicState> {
+        let mut states = self.dynamic_state.clone();
+        for (state, with_count) in [
+            (
+                vk::DynamicState::VIEWPORT,
+                vk::DynamicState::VIEWPORT_WITH_COUNT,
+            ),
+            (
+                vk::DynamicState::SCISSOR,
+                vk::DynamicState::SCISSOR_WITH_COUNT,
+            ),
+        ] {
+            if !states.contains(&state) && !states.contains(&with_count) {
+                states.push(state);
+            }
+        }
+        states
+    }
+
+    pub(in crate::core) fn create(
+        &self,
+        device: &ash::Device,
+        cache: &DescriptorLayoutCache,
+    ) -> Result<Pipeline, RendererError> {
+        let stages = self
+            .stages
+            .iter()
+            .map(|(module, entry_point)| PipelineStage::new(module, entry_point))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.validate(&stages)?;
+
+        let reflections: Vec<_> = stages.iter().map(PipelineStage::reflection).collect();
+        let specialization = Specialization::new(&reflections, &self.spec_constants)?;
+        let specialization_infos: Vec<_> = (0..stages.len())
+            .map(|index| specialization.info(index))
+            .collect();
+        let stage_infos: Vec<_> = stages
+            .iter()
+            .zip(&specialization_infos)
+            .map(|(stage, specialization)| {
+                let info = vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage.stage)
+                    .module(stage.module.handle())
+                    .name(&stage.entry_point);
+                match specialization {
+                    Some(specialization) => info.specialization_info(specialization),
+                    None => info,
+                }
+            })
+            .collect();
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_layout.bindings)
+            .vertex_attribute_descriptions(&self.vertex_layout.attributes);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart);
+        // counts only, the values are always set while recording
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let (bias_constant, bias_slope, bias_clamp) = self.depth_bias.unwrap_or_default();
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(self.line_width)
+            .depth_bias_enable(self.depth_bias.is_some())
+            .depth_bias_constant_factor(bias_constant)
+            .depth_bias_slope_factor(bias_slope)
+            .depth_bias_clamp(bias_clamp);
+        let multisample =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(self.samples);
+
+        let (depth_compare, depth_write) =
+            self.depth_test.unwrap_or((vk::CompareOp::ALWAYS, false));
+        let (stencil_front, stencil_back) = self.stencil.unwrap_or_default();
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test.is_some())
+            .depth_write_enable(depth_write)
+            .depth_compare_op(depth_compare)
+            .stencil_test_enable(self.stencil.is_some())
+            .front(stencil_front)
+            .back(stencil_back)
+            .max_depth_bounds(1.0);
+
+        let blend_attachments: Vec<_> = self
+            .color_attachments
+            .iter()
+            .map(|(_, blend)| blend.attachment())
+            .collect();
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+        let dynamic_states = self.dynamic_states();
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let color_formats: Vec<_> = self
+            .color_attachments
+            .iter()
+            .map(|(format, _)| *format)
+            .collect();
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_formats)
+            .depth_attachment_format(self.depth_format)
+            .stencil_attachment_format(self.stencil_format);
+
+        let layout = PipelineLayout::create(
+            device,
+            cache,
+            &reflections,
+            self.set_layouts.as_deref(),
+            self.push_constant_ranges.as_deref(),
+        )?;
+
+        let info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stage_infos)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(layout.handle())
+            .push_next(&mut rendering);
+
+        let result =
+            unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None) };
+        match result {
+            Ok(pipelines) => Ok(Pipeline::new(
+                pipelines[0],
+                layout,
+                vk::PipelineBindPoint::GRAPHICS,
+            )),
+            Err((_, err)) => {
+                layout.destroy(device);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Catches what the validation layers would only report once the pipeline is in use:
+    /// vertex inputs without an attribute of the same numeric type and state without the
+    /// attachment it needs
+    fn validate(&self, stages: &[PipelineStage]) -> Result<(), RendererError> {
+        let invalid = |reason: String| Err(RendererError::InvalidPipeline(reason));
+
+        for (index, stage) in stages.iter().enumerate() {
+            if stages[..index]
+                .iter()
+                .any(|other| other.stage == stage.stage)
+            {
+                return invalid(format!("more than one {:?} stage", stage.stage));
+            }
+        }
+        let Some(vertex) = stages
+            .iter()
+            .find(|stage| stage.stage == vk::ShaderStageFlags::VERTEX)
+        else {
+            return invalid("no vertex stage".to_owned());
+        };
+
+        let layout = &self.vertex_layout;
+        for input in &vertex.module.reflection().vertex_inputs {
+            let Some(attribute) = layout
+                .attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+            else {
+                return invalid(format!(
+                    "vertex input at location {} has no attribute",
+                    input.location
+                ));
+            };
+            if numeric_type(attribute.format) != numeric_type(input.format) {
+                return invalid(format!(
+                    "vertex input at location {} is a {:?}, its attribute is {:?}",
+                    input.location, input.format, attribute.format
+                ));
+            }
+        }
+        for attribute in &layout.attributes {
+            if !layout
+                .bindings
+                .iter()
+                .any(|binding| binding.binding == attribute.binding)
+            {
+                return invalid(format!(
+                    "attribute at location {} uses binding {} that isn't declared",
+                    attribute.location, attribute.binding
+                ));
+            }
+        }
+
+        if self.depth_test.is_some() && self.depth_format == vk::Format::UNDEFINED {
+            return invalid("depth test without a depth format".to_owned());
+        }
+        if self.stencil.is_some() && self.stencil_format == vk::Format::UNDEFINED {
+            return invalid("stencil test without a stencil format".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viewport_and_scissor_stay_dynamic() {
+        let states = GraphicsPipelineBuilder::new()
+            .dynamic_state(&[vk::DynamicState::LINE_WIDTH])
+            .dynamic_states();
+        assert_eq!(
+            states,
+            [
+                vk::DynamicState::LINE_WIDTH,
+                vk::DynamicState::VIEWPORT,
+                vk::DynamicState::SCISSOR
+            ]
+        );
+
+        let states = GraphicsPipelineBuilder::new()
+            .dynamic_state(&[
+                vk::DynamicState::SCISSOR,
+                vk::DynamicState::VIEWPORT_WITH_COUNT,
+            ])
+            .dynamic_states();
+        assert_eq!(
+            states,
+            [
+                vk::DynamicState::SCISSOR,
+                vk::DynamicState::VIEWPORT_WITH_COUNT
+            ]
+        );
+    }
+}
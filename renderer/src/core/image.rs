@@ -0,0 +1,538 @@
+use ash::vk;
+
+use super::{aspect_flags, Allocation, ImageUpload, MemoryUsage};
+
+/// Shape of an image, layers are counted in whole cubes for the cube variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDimension {
+    D2,
+    D2Array(u32),
+    Cube,
+    CubeArray(u32),
+}
+
+impl ImageDimension {
+    pub fn array_layers(&self) -> u32 {
+        match *self {
+            Self::D2 => 1,
+            Self::D2Array(layers) => layers,
+            Self::Cube => 6,
+            Self::CubeArray(cubes) => cubes * 6,
+        }
+    }
+
+    pub fn view_type(&self) -> vk::ImageViewType {
+        match self {
+            Self::D2 => vk::ImageViewType::TYPE_2D,
+            Self::D2Array(_) => vk::ImageViewType::TYPE_2D_ARRAY,
+            Self::Cube => vk::ImageViewType::CUBE,
+            Self::CubeArray(_) => vk::ImageViewType::CUBE_ARRAY,
+        }
+    }
+
+    pub fn create_flags(&self) -> vk::ImageCreateFlags {
+        match self {
+            Self::Cube | Self::CubeArray(_) => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            _ => vk::ImageCreateFlags::empty(),
+        }
+    }
+}
+
+/// How an image gets created, start from one of the usage constructors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDesc {
+    pub extent: vk::Extent2D,
+    pub dimension: ImageDimension,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub memory: MemoryUsage,
+}
+
+impl ImageDesc {
+    pub fn new(
+        extent: vk::Extent2D,
+        dimension: ImageDimension,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Self {
+        Self {
+            extent,
+            dimension,
+            format,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage,
+            memory: MemoryUsage::GpuOnly,
+        }
+    }
+
+    /// Sampled by shaders, filled through a transfer
+    pub fn texture(width: u32, height: u32, format: vk::Format) -> Self {
+        Self::new(
+            vk::Extent2D { width, height },
+            ImageDimension::D2,
+            format,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        )
+    }
+
+    /// Like `texture` with `layers` layers of the same size
+    pub fn texture_array(width: u32, height: u32, layers: u32, format: vk::Format) -> Self {
+        Self::texture(width, height, format).with_dimension(ImageDimension::D2Array(layers))
+    }
+
+    /// Like `texture` with six square faces in +X, -X, +Y, -Y, +Z, -Z order
+    pub fn cubemap(size: u32, format: vk::Format) -> Self {
+        Self::texture(size, size, format).with_dimension(ImageDimension::Cube)
+    }
+
+    /// Rendered to, then sampled or copied out
+    pub fn color_attachment(width: u32, height: u32, format: vk::Format) -> Self {
+        Self::new(
+            vk::Extent2D { width, height },
+            ImageDimension::D2,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        )
+    }
+
+    pub fn depth_attachment(width: u32, height: u32, format: vk::Format) -> Self {
+        Self::new(
+            vk::Extent2D { width, height },
+            ImageDimension::D2,
+            format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    /// Written by compute shaders, also sampled afterwards
+    pub fn storage(width: u32, height: u32, format: vk::Format) -> Self {
+        Self::new(
+            vk::Extent2D { width, height },
+            ImageDimension::D2,
+            format,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    pub fn with_dimension(mut self, dimension: ImageDimension) -> Self {
+        self.dimension = dimension;
+        self
+    }
+
+    pub fn with_mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels.clamp(1, mip_count(self.extent));
+        self
+    }
+
+    /// Mips down to 1x1, generating them with blits needs TRANSFER_SRC as well
+    pub fn with_full_mip_chain(mut self) -> Self {
+        self.mip_levels = mip_count(self.extent);
+        self
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Adds usage flags on top of the ones picked by the constructor
+    pub fn with_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.dimension.array_layers()
+    }
+}
+
+/// Levels of a full mip chain for the extent
+pub fn mip_count(extent: vk::Extent2D) -> u32 {
+    32 - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+/// Size of a mip level, never below 1
+pub fn mip_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
+
+/// Layout of an image with the stages and accesses of its last use, what the next barrier
+/// has to wait on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+impl ImageState {
+    pub const UNDEFINED: Self = Self {
+        layout: vk::ImageLayout::UNDEFINED,
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+    };
+
+    /// Stages and accesses an image in `layout` is usually used with
+    pub fn for_layout(layout: vk::ImageLayout) -> Self {
+        use vk::{AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S};
+
+        let shaders = S::VERTEX_SHADER | S::FRAGMENT_SHADER | S::COMPUTE_SHADER;
+        let depth_tests = S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS;
+        let (stage, access) = match layout {
+            L::UNDEFINED | L::PREINITIALIZED => (S::NONE, A::NONE),
+            L::TRANSFER_SRC_OPTIMAL => (S::ALL_TRANSFER, A::TRANSFER_READ),
+            L::TRANSFER_DST_OPTIMAL => (S::ALL_TRANSFER, A::TRANSFER_WRITE),
+            L::COLOR_ATTACHMENT_OPTIMAL | L::ATTACHMENT_OPTIMAL => (
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+            ),
+            L::DEPTH_ATTACHMENT_OPTIMAL
+            | L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            | L::STENCIL_ATTACHMENT_OPTIMAL => (
+                depth_tests,
+                A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            L::DEPTH_READ_ONLY_OPTIMAL
+            | L::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            | L::STENCIL_READ_ONLY_OPTIMAL => (
+                depth_tests | shaders,
+                A::DEPTH_STENCIL_ATTACHMENT_READ | A::SHADER_SAMPLED_READ,
+            ),
+            L::SHADER_READ_ONLY_OPTIMAL | L::READ_ONLY_OPTIMAL => {
+                (shaders, A::SHADER_SAMPLED_READ | A::SHADER_STORAGE_READ)
+            }
+            // the present semaphore is signaled at ALL_COMMANDS, nothing else reads the image
+            L::PRESENT_SRC_KHR => (S::ALL_COMMANDS, A::NONE),
+            _ => (S::ALL_COMMANDS, A::MEMORY_READ | A::MEMORY_WRITE),
+        };
+        Self {
+            layout,
+            stage,
+            access,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        let writes = vk::AccessFlags2::SHADER_WRITE
+            | vk::AccessFlags2::SHADER_STORAGE_WRITE
+            | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+            | vk::AccessFlags2::TRANSFER_WRITE
+            | vk::AccessFlags2::HOST_WRITE
+            | vk::AccessFlags2::MEMORY_WRITE;
+        !self.access.intersects(writes)
+    }
+}
+
+/// Barrier from the previous state to the next, only the accesses worth making available
+/// and visible are kept
+pub fn image_barrier(
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    from: ImageState,
+    to: ImageState,
+) -> vk::ImageMemoryBarrier2<'static> {
+    let writes = if from.is_read_only() {
+        vk::AccessFlags2::NONE
+    } else {
+        from.access
+    };
+    vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(from.stage)
+        .src_access_mask(writes)
+        .dst_stage_mask(to.stage)
+        .dst_access_mask(to.access)
+        .old_layout(from.layout)
+        .new_layout(to.layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range)
+}
+
+/// Image with its memory and a view over every mip and layer. The state is tracked for the
+/// whole image, give it back with `Device::destroy_image`
+pub struct Image {
+    device: ash::Device,
+    handle: vk::Image,
+    view: vk::ImageView,
+    allocation: Allocation,
+    desc: ImageDesc,
+    state: ImageState,
+}
+
+impl Image {
+    pub(in crate::core) fn new(
+        device: &ash::Device,
+        handle: vk::Image,
+        view: vk::ImageView,
+        allocation: Allocation,
+        desc: ImageDesc,
+    ) -> Self {
+        Self {
+            device: device.clone(),
+            handle,
+            view,
+            allocation,
+            desc,
+            state: ImageState::UNDEFINED,
+        }
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (vk::Image, vk::ImageView, Allocation) {
+        (self.handle, self.view, self.allocation)
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    /// View of the whole image, depth only for depth/stencil formats
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn desc(&self) -> &ImageDesc {
+        &self.desc
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.desc.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.desc.extent
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.desc.mip_levels
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.desc.array_layers()
+    }
+
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.desc.usage
+    }
+
+    pub fn aspect(&self) -> vk::ImageAspectFlags {
+        aspect_flags(self.desc.format)
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    pub fn state(&self) -> ImageState {
+        self.state
+    }
+
+    pub fn layout(&self) -> vk::ImageLayout {
+        self.state.layout
+    }
+
+    /// Every mip and layer
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect(),
+            base_mip_level: 0,
+            level_count: self.desc.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.array_layers(),
+        }
+    }
+
+    /// Moves the image to `new_layout`, waiting on its last use. Read only uses in the same
+    /// layout don't need a barrier and record nothing
+    pub fn transition(&mut self, cmd: vk::CommandBuffer, new_layout: vk::ImageLayout) {
+        self.transition_to(cmd, ImageState::for_layout(new_layout));
+    }
+
+    /// Like `transition` with the stages and accesses of the next use spelled out
+    pub fn transition_to(&mut self, cmd: vk::CommandBuffer, next: ImageState) {
+        if self.state.layout == next.layout && self.state.is_read_only() && next.is_read_only() {
+            self.state.stage |= next.stage;
+            self.state.access |= next.access;
+            return;
+        }
+
+        let barriers = [image_barrier(
+            self.handle,
+            self.subresource_range(),
+            self.state,
+            next,
+        )];
+        let info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(cmd, &info) };
+        self.state = next;
+    }
+
+    /// Records a transition done outside of `transition`, by an upload or a render pass
+    pub fn assume_state(&mut self, state: ImageState) {
+        self.state = state;
+    }
+
+    /// Uploads for the first `levels` mips, every layer at once, leaving the image in
+    /// `new_layout`. Each level expects its texels tightly packed, layer after layer
+    pub fn upload_levels(&mut self, levels: u32, new_layout: vk::ImageLayout) -> Vec<ImageUpload> {
+        let old_layout = self.state.layout;
+        let uploads = (0..levels.min(self.desc.mip_levels))
+            .map(|level| {
+                let extent = mip_extent(self.desc.extent, level);
+                ImageUpload {
+                    image: self.handle,
+                    subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: self.aspect(),
+                        mip_level: level,
+                        base_array_layer: 0,
+                        layer_count: self.array_layers(),
+                    },
+                    offset: vk::Offset3D::default(),
+                    extent: vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                    old_layout,
+                    new_layout,
+                }
+            })
+            .collect();
+        // the upload ends with a barrier covering every later command
+        self.state = ImageState {
+            layout: new_layout,
+            stage: vk::PipelineStageFlags2::NONE,
+            access: vk::AccessFlags2::NONE,
+        };
+        uploads
+    }
+}
+
+/// Which part of an image a view covers, the default is the whole image as created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImageViewDesc {
+    /// The image dimension view type when None
+    pub view_type: Option<vk::ImageViewType>,
+    /// The image format when None, must be compatible
+    pub format: Option<vk::Format>,
+    /// Derived from the format when empty
+    pub aspect: vk::ImageAspectFlags,
+    pub base_mip_level: u32,
+    /// Every remaining level when None
+    pub mip_levels: Option<u32>,
+    pub base_array_layer: u32,
+    /// Every remaining layer when None
+    pub array_layers: Option<u32>,
+}
+
+impl ImageViewDesc {
+    /// 2D view of a single mip of a single layer, e.g. to render to it
+    pub fn single(mip_level: u32, array_layer: u32) -> Self {
+        Self {
+            view_type: Some(vk::ImageViewType::TYPE_2D),
+            base_mip_level: mip_level,
+            mip_levels: Some(1),
+            base_array_layer: array_layer,
+            array_layers: Some(1),
+            ..Default::default()
+        }
+    }
+}
+
+/// Filtering and addressing of a sampler, start from `linear` or `nearest`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Used for u, v and w
+    pub address_mode: vk::SamplerAddressMode,
+    /// Clamped to the device limit, ignored without the sampler_anisotropy feature
+    pub max_anisotropy: Option<f32>,
+    /// Depth comparison for shadow maps
+    pub compare: Option<vk::CompareOp>,
+    pub max_lod: f32,
+    pub border_color: vk::BorderColor,
+}
+
+impl SamplerDesc {
+    pub fn linear() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            compare: None,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        }
+    }
+
+    /// Pixel art and voxel textures
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Self::linear()
+        }
+    }
+
+    pub fn with_address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn with_anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+
+    pub fn with_compare(mut self, compare: vk::CompareOp) -> Self {
+        self.compare = Some(compare);
+        self
+    }
+}
+
+/// Sampled image with the sampler it is meant to be read with,
+/// give it back with `Device::destroy_texture`
+pub struct Texture {
+    image: Image,
+    sampler: vk::Sampler,
+}
+
+impl Texture {
+    pub(in crate::core) fn new(image: Image, sampler: vk::Sampler) -> Self {
+        Self { image, sampler }
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (Image, vk::Sampler) {
+        (self.image, self.sampler)
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn image_mut(&mut self) -> &mut Image {
+        &mut self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
@@ -1,18 +1,23 @@
-use std::{
-    error::Error,
-    ffi::{c_char, CStr, CString},
-};
+use std::ffi::{c_char, CStr, CString};
 
 use ash::{self, ext, khr, vk};
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
-use super::{surface::Surface, Device};
+use super::{effective_api_version, surface::Surface, Device, DeviceFeatures, QueueFamilies};
+use crate::RendererError;
 
 pub struct InstanceSpec {
     pub app_name: CString,
+    pub app_version: u32,
+    pub engine_name: CString,
+    pub engine_version: u32,
+    pub api_version: u32,
     pub extensions: Vec<*const c_char>,
+    /// Enabled only if the loader exposes them, missing ones are skipped
+    pub optional_extensions: Vec<*const c_char>,
     pub layers: Vec<*const c_char>,
     pub validation: bool,
+    pub debug_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
 }
 
 pub struct Instance {
@@ -20,26 +25,35 @@ pub struct Instance {
     instance: ash::Instance,
     dbg_loader: Option<ext::debug_utils::Instance>,
     messenger: vk::DebugUtilsMessengerEXT,
+    api_version: u32,
 }
 
 impl Instance {
-    pub fn new(spec: InstanceSpec) -> Result<Self, vk::Result> {
-        let entry = ash::Entry::linked();
+    pub fn new(spec: InstanceSpec) -> Result<Self, RendererError> {
+        let entry = unsafe { ash::Entry::load() }.map_err(RendererError::Loading)?;
+
+        Self::check_layers(&entry, &spec.layers)?;
+        Self::check_extensions(&entry, &spec.extensions)?;
+
+        let mut extensions = spec.extensions.clone();
+        extensions.extend(Self::available_extensions(
+            &entry,
+            &spec.optional_extensions,
+        )?);
 
         let app_info = vk::ApplicationInfo::default()
-            .api_version(vk::make_api_version(0, 1, 3, 0))
-            .application_name(&spec.app_name);
+            .api_version(spec.api_version)
+            .application_name(&spec.app_name)
+            .application_version(spec.app_version)
+            .engine_name(&spec.engine_name)
+            .engine_version(spec.engine_version);
 
         let create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
             .enabled_layer_names(&spec.layers)
-            .enabled_extension_names(&spec.extensions);
+            .enabled_extension_names(&extensions);
 
-        let mut severity = vk::DebugUtilsMessageSeverityFlagsEXT::default();
-        severity |= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
-        severity |= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
-        //severity |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
-        //severity |= vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+        let severity = spec.debug_severity;
 
         let mut type_ = vk::DebugUtilsMessageTypeFlagsEXT::default();
         type_ |= vk::DebugUtilsMessageTypeFlagsEXT::GENERAL;
@@ -56,11 +70,18 @@ impl Instance {
             let _ = create_info.push_next(&mut dbg_info);
         };
 
-        let instance = unsafe { entry.create_instance(&create_info, None)? };
+        let instance = unsafe { entry.create_instance(&create_info, None) }
+            .map_err(RendererError::InstanceCreation)?;
 
         let (dbg_loader, messenger) = if spec.validation {
             let loader = ext::debug_utils::Instance::new(&entry, &instance);
-            let messenger = unsafe { loader.create_debug_utils_messenger(&dbg_info, None)? };
+            let messenger = match unsafe { loader.create_debug_utils_messenger(&dbg_info, None) } {
+                Ok(val) => val,
+                Err(err) => {
+                    unsafe { instance.destroy_instance(None) };
+                    return Err(RendererError::InstanceCreation(err));
+                }
+            };
             (Some(loader), messenger)
         } else {
             (None, vk::DebugUtilsMessengerEXT::null())
@@ -71,19 +92,92 @@ impl Instance {
             instance,
             dbg_loader,
             messenger,
+            api_version: spec.api_version,
         })
     }
 
-    pub fn create_surface<T>(&self, window: &T) -> Result<Surface, Box<dyn Error>>
+    fn check_layers(entry: &ash::Entry, layers: &[*const c_char]) -> Result<(), RendererError> {
+        let available = unsafe { entry.enumerate_instance_layer_properties()? };
+        for required in layers {
+            let required = unsafe { CStr::from_ptr(*required) };
+            let found = available
+                .iter()
+                .any(|layer| layer.layer_name_as_c_str() == Ok(required));
+
+            if !found {
+                return Err(RendererError::MissingLayer {
+                    name: required.to_string_lossy().into_owned(),
+                    result: vk::Result::ERROR_LAYER_NOT_PRESENT,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_extensions(
+        entry: &ash::Entry,
+        extensions: &[*const c_char],
+    ) -> Result<(), RendererError> {
+        let available = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        for required in extensions {
+            let required = unsafe { CStr::from_ptr(*required) };
+            let found = available
+                .iter()
+                .any(|ext| ext.extension_name_as_c_str() == Ok(required));
+
+            if !found {
+                return Err(RendererError::MissingExtension {
+                    name: required.to_string_lossy().into_owned(),
+                    result: vk::Result::ERROR_EXTENSION_NOT_PRESENT,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Filters the optional extensions down to the ones the loader exposes
+    fn available_extensions(
+        entry: &ash::Entry,
+        optional: &[*const c_char],
+    ) -> Result<Vec<*const c_char>, RendererError> {
+        let available = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        let enabled = optional
+            .iter()
+            .copied()
+            .filter(|name| {
+                let name = unsafe { CStr::from_ptr(*name) };
+                let found = available
+                    .iter()
+                    .any(|ext| ext.extension_name_as_c_str() == Ok(name));
+                if !found {
+                    log::warn!(
+                        "Optional instance extension \"{}\" is not available",
+                        name.to_string_lossy()
+                    );
+                }
+                found
+            })
+            .collect();
+        Ok(enabled)
+    }
+
+    pub fn create_surface<T>(&self, window: &T) -> Result<Surface, RendererError>
     where
         T: HasDisplayHandle + HasWindowHandle,
     {
         let loader = khr::surface::Instance::new(&self.entry, &self.instance);
-        let rwh = window.window_handle()?.as_raw();
-        let rdh = window.display_handle()?.as_raw();
+        let rwh = window
+            .window_handle()
+            .map_err(|err| RendererError::SurfaceCreation(Box::new(err)))?
+            .as_raw();
+        let rdh = window
+            .display_handle()
+            .map_err(|err| RendererError::SurfaceCreation(Box::new(err)))?
+            .as_raw();
 
         let surface =
-            unsafe { ash_window::create_surface(&self.entry, &self.instance, rdh, rwh, None)? };
+            unsafe { ash_window::create_surface(&self.entry, &self.instance, rdh, rwh, None) }
+                .map_err(|err| RendererError::SurfaceCreation(Box::new(err)))?;
 
         Ok(Surface::new(loader, surface))
     }
@@ -92,27 +186,61 @@ impl Instance {
         &self.instance
     }
 
+    /// The version requested at creation, the one usable with a gpu is the lower of the two
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
     pub fn create_device(
         &self,
         gpu: vk::PhysicalDevice,
-        graphics_index: u32,
+        families: &QueueFamilies,
         extensions: &[*const c_char],
-    ) -> Result<Device, vk::Result> {
+        features: &DeviceFeatures,
+    ) -> Result<Device, RendererError> {
         let priority = &[1.0_f32];
-        let queue_info = vk::DeviceQueueCreateInfo::default()
-            .queue_family_index(graphics_index)
-            .queue_priorities(priority);
+        let queue_infos: Vec<_> = families
+            .unique()
+            .into_iter()
+            .map(|family| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family)
+                    .queue_priorities(priority)
+            })
+            .collect();
+
+        // same gating used when querying, a struct the instance or device version doesn't know is invalid
+        let api_version = effective_api_version(&self.instance, gpu, self.api_version);
+        let mut chain = features.to_chain();
+        let mut features2 = vk::PhysicalDeviceFeatures2::default().features(chain.core);
+        if api_version >= vk::API_VERSION_1_2 {
+            features2 = features2.push_next(&mut chain.v12);
+        }
+        if api_version >= vk::API_VERSION_1_3 {
+            features2 = features2.push_next(&mut chain.v13);
+        }
 
-        let binding = [queue_info];
         let create_info = vk::DeviceCreateInfo::default()
             .enabled_extension_names(extensions)
-            .queue_create_infos(&binding);
+            .queue_create_infos(&queue_infos)
+            .push_next(&mut features2);
 
-        let handle = unsafe { self.instance.create_device(gpu, &create_info, None) }?;
+        let handle = unsafe { self.instance.create_device(gpu, &create_info, None) }
+            .map_err(RendererError::DeviceCreation)?;
 
-        let graphics = unsafe { handle.get_device_queue(graphics_index, 0) };
+        let extensions = extensions
+            .iter()
+            .map(|name| unsafe { CStr::from_ptr(*name) }.to_owned())
+            .collect();
 
-        Ok(Device::new(gpu, handle, graphics, graphics_index))
+        Ok(Device::new(
+            &self.instance,
+            gpu,
+            handle,
+            *families,
+            extensions,
+            *features,
+        ))
     }
 }
 
@@ -0,0 +1,216 @@
+use ash::vk;
+
+use crate::{RendererError, Vertex};
+
+use super::Buffer;
+
+/// Integer types index buffers can hold
+pub trait MeshIndex: bytemuck::Pod {
+    const INDEX_TYPE: vk::IndexType;
+}
+
+impl MeshIndex for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl MeshIndex for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+/// Range of a mesh drawn on its own, over the indices or over the vertices of a mesh without them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Submesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Added to every index read, ignored without an index buffer
+    pub vertex_offset: i32,
+}
+
+impl Submesh {
+    pub fn new(first_index: u32, index_count: u32) -> Self {
+        Self {
+            first_index,
+            index_count,
+            vertex_offset: 0,
+        }
+    }
+
+    pub fn with_vertex_offset(mut self, vertex_offset: i32) -> Self {
+        self.vertex_offset = vertex_offset;
+        self
+    }
+}
+
+/// Sizes of the buffers a mesh is made of, start from `MeshDesc::new`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshDesc {
+    pub vertex_count: u32,
+    pub vertex_stride: u32,
+    /// 0 for a mesh drawn without an index buffer
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    /// Empty draws the whole mesh as one range
+    pub submeshes: Vec<Submesh>,
+}
+
+impl MeshDesc {
+    pub fn new<V: Vertex>(vertex_count: u32) -> Self {
+        Self {
+            vertex_count,
+            vertex_stride: std::mem::size_of::<V>() as u32,
+            index_count: 0,
+            index_type: vk::IndexType::UINT32,
+            submeshes: vec![],
+        }
+    }
+
+    pub fn with_indices<I: MeshIndex>(mut self, index_count: u32) -> Self {
+        self.index_count = index_count;
+        self.index_type = I::INDEX_TYPE;
+        self
+    }
+
+    pub fn with_submeshes(mut self, submeshes: &[Submesh]) -> Self {
+        self.submeshes = submeshes.to_vec();
+        self
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.index_count > 0
+    }
+
+    /// Elements the submesh ranges are over
+    fn element_count(&self) -> u32 {
+        if self.is_indexed() {
+            self.index_count
+        } else {
+            self.vertex_count
+        }
+    }
+
+    pub(in crate::core) fn vertex_size(&self) -> vk::DeviceSize {
+        self.vertex_count as vk::DeviceSize * self.vertex_stride as vk::DeviceSize
+    }
+
+    pub(in crate::core) fn index_size(&self) -> vk::DeviceSize {
+        let size = match self.index_type {
+            vk::IndexType::UINT16 => 2,
+            _ => 4,
+        };
+        self.index_count as vk::DeviceSize * size
+    }
+
+    /// Fails on an empty mesh or a submesh going past the end of the mesh
+    pub fn validate(&self) -> Result<(), RendererError> {
+        if self.vertex_count == 0 || self.vertex_stride == 0 {
+            return Err(RendererError::InvalidMesh("mesh without vertices".into()));
+        }
+        let count = self.element_count();
+        for (index, submesh) in self.submeshes.iter().enumerate() {
+            let end = submesh.first_index as u64 + submesh.index_count as u64;
+            if end > count as u64 {
+                return Err(RendererError::InvalidMesh(format!(
+                    "submesh {} ends at {} but the mesh has {} {}",
+                    index,
+                    end,
+                    count,
+                    if self.is_indexed() {
+                        "indices"
+                    } else {
+                        "vertices"
+                    }
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Vertex and index buffers drawn together, give it back with `Device::destroy_mesh`
+pub struct Mesh {
+    vertices: Buffer,
+    indices: Option<Buffer>,
+    index_type: vk::IndexType,
+    vertex_count: u32,
+    index_count: u32,
+    submeshes: Vec<Submesh>,
+}
+
+impl Mesh {
+    pub(in crate::core) fn new(vertices: Buffer, indices: Option<Buffer>, desc: &MeshDesc) -> Self {
+        let submeshes = if desc.submeshes.is_empty() {
+            vec![Submesh::new(0, desc.element_count())]
+        } else {
+            desc.submeshes.clone()
+        };
+        Self {
+            vertices,
+            indices,
+            index_type: desc.index_type,
+            vertex_count: desc.vertex_count,
+            index_count: desc.index_count,
+            submeshes,
+        }
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (Buffer, Option<Buffer>) {
+        (self.vertices, self.indices)
+    }
+
+    pub fn vertex_buffer(&self) -> &Buffer {
+        &self.vertices
+    }
+
+    pub fn index_buffer(&self) -> Option<&Buffer> {
+        self.indices.as_ref()
+    }
+
+    /// None without an index buffer
+    pub fn index_type(&self) -> Option<vk::IndexType> {
+        self.indices.as_ref().map(|_| self.index_type)
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// Always at least one, the whole mesh when no ranges were given
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    #[repr(C)]
+    struct Position([f32; 3]);
+
+    impl Vertex for Position {
+        const ATTRIBUTES: &'static [vk::VertexInputAttributeDescription] = &[];
+    }
+
+    #[test]
+    fn submeshes_stay_inside_the_mesh() {
+        let desc = MeshDesc::new::<Position>(4)
+            .with_indices::<u16>(6)
+            .with_submeshes(&[Submesh::new(0, 3), Submesh::new(3, 3)]);
+        assert_eq!(desc.vertex_size(), 48);
+        assert_eq!(desc.index_size(), 12);
+        assert!(desc.validate().is_ok());
+
+        let desc = desc.with_submeshes(&[Submesh::new(3, 4)]);
+        assert!(desc.validate().is_err());
+
+        // without indices the ranges are over vertices
+        let desc = MeshDesc::new::<Position>(4).with_submeshes(&[Submesh::new(0, 4)]);
+        assert!(desc.validate().is_ok());
+        assert!(MeshDesc::new::<Position>(0).validate().is_err());
+    }
+}
@@ -1,9 +1,51 @@
+mod allocator;
+mod bindless;
+mod buffer;
+mod compute_pipeline;
+mod deletion;
+mod descriptors;
 mod device;
+mod features;
+mod format;
 mod gpu;
+mod graphics_pipeline;
+mod image;
 pub mod instance;
+mod mesh;
+mod offscreen;
+mod pipeline;
+mod queue;
+mod readback;
+mod selector;
+mod shader;
+mod spirv;
 pub mod surface;
+mod swapchain;
+mod tlsf;
+mod upload;
 
+pub use allocator::*;
+pub use bindless::*;
+pub use buffer::*;
+pub use compute_pipeline::*;
+use deletion::*;
+pub use descriptors::*;
 pub use device::*;
+pub use features::*;
+pub use format::*;
 pub use gpu::*;
+pub use graphics_pipeline::*;
+pub use image::*;
 pub use instance::*;
+pub use mesh::*;
+pub use offscreen::*;
+pub use pipeline::*;
+pub use queue::*;
+pub use readback::*;
+pub use selector::*;
+pub use shader::*;
+pub use spirv::*;
 pub use surface::*;
+pub use swapchain::*;
+pub use tlsf::*;
+pub use upload::*;
@@ -0,0 +1,119 @@
+use ash::vk;
+
+use super::{Device, Image, ImageDesc, ImageDimension, Readback};
+use crate::RendererError;
+
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+struct OffscreenImage {
+    image: Image,
+    readback: Readback,
+}
+
+/// Color images used instead of a swapchain when rendering without a window,
+/// one per frame in flight, each with a host visible buffer the frame is copied to.
+/// Give it back with `Device::destroy_offscreen`
+#[derive(Default)]
+pub struct Offscreen {
+    extent: vk::Extent2D,
+    images: Vec<OffscreenImage>,
+}
+
+impl Offscreen {
+    pub(in crate::core) fn new(
+        device: &Device,
+        extent: vk::Extent2D,
+        count: usize,
+    ) -> Result<Self, RendererError> {
+        let mut offscreen = Self {
+            extent,
+            images: vec![],
+        };
+        if let Err(err) = offscreen.recreate(device, extent, count) {
+            offscreen.destroy_resources(device);
+            return Err(err);
+        }
+        Ok(offscreen)
+    }
+
+    /// The previous images are released to `device`, nothing may use them anymore
+    pub fn recreate(
+        &mut self,
+        device: &Device,
+        extent: vk::Extent2D,
+        count: usize,
+    ) -> Result<(), RendererError> {
+        self.destroy_resources(device);
+        self.extent = vk::Extent2D {
+            width: extent.width.max(1),
+            height: extent.height.max(1),
+        };
+
+        for _ in 0..count {
+            let image = self.create_image(device)?;
+            self.images.push(image);
+        }
+
+        log::trace!(
+            "Offscreen target created: {}x{}, {} images",
+            self.extent.width,
+            self.extent.height,
+            count
+        );
+        Ok(())
+    }
+
+    fn create_image(&self, device: &Device) -> Result<OffscreenImage, RendererError> {
+        let desc = ImageDesc::new(
+            self.extent,
+            ImageDimension::D2,
+            OFFSCREEN_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        let image = device.create_image(&desc)?;
+        match device.create_readback(self.extent, OFFSCREEN_FORMAT) {
+            Ok(readback) => Ok(OffscreenImage { image, readback }),
+            Err(err) => {
+                device.destroy_image(image);
+                Err(err)
+            }
+        }
+    }
+
+    /// Copies the image, which must be in TRANSFER_SRC_OPTIMAL, into its readback buffer and
+    /// makes the copy visible to the host
+    pub fn record_readback(&self, buffer: vk::CommandBuffer, index: usize) {
+        let target = &self.images[index];
+        target.readback.record_copy(buffer, target.image.handle());
+    }
+
+    /// Tightly packed pixels of the last readback, the copy must have completed already
+    pub fn read(&self, index: usize) -> Result<Vec<u8>, RendererError> {
+        self.images[index].readback.read()
+    }
+
+    pub fn image(&self, index: usize) -> vk::Image {
+        self.images[index].image.handle()
+    }
+
+    pub fn view(&self, index: usize) -> vk::ImageView {
+        self.images[index].image.view()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        OFFSCREEN_FORMAT
+    }
+
+    pub(in crate::core) fn destroy_resources(&mut self, device: &Device) {
+        for target in self.images.drain(..) {
+            device.destroy_image(target.image);
+            device.destroy_readback(target.readback);
+        }
+    }
+}
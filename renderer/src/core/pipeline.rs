@@ -0,0 +1,533 @@
+use std::{collections::BTreeMap, ffi::CString};
+
+use ash::vk;
+
+use crate::RendererError;
+
+use super::{DescriptorLayoutCache, ShaderModule, ShaderReflection, SpecValue};
+
+/// Layout a pipeline was created with. Set layouts derived from reflection come from the
+/// device layout cache and are shared with every pipeline using the same bindings, neither
+/// they nor the ones passed in by the caller are destroyed with the pipeline
+#[derive(Debug)]
+pub struct PipelineLayout {
+    handle: vk::PipelineLayout,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineLayout {
+    pub fn handle(&self) -> vk::PipelineLayout {
+        self.handle
+    }
+
+    /// Indexed by set number
+    pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.set_layouts
+    }
+
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constant_ranges
+    }
+
+    /// Uses the given set layouts and push constant ranges, or derives the missing ones from
+    /// what the stages declare, checking both against the stages either way
+    pub(in crate::core) fn create(
+        device: &ash::Device,
+        cache: &DescriptorLayoutCache,
+        stages: &[(vk::ShaderStageFlags, &ShaderReflection)],
+        set_layouts: Option<&[vk::DescriptorSetLayout]>,
+        push_constant_ranges: Option<&[vk::PushConstantRange]>,
+    ) -> Result<Self, RendererError> {
+        let push_constant_ranges = match push_constant_ranges {
+            Some(ranges) => {
+                check_push_constants(stages, ranges)?;
+                ranges.to_vec()
+            }
+            None => merge_push_constants(stages),
+        };
+
+        let set_layouts = match set_layouts {
+            Some(layouts) => {
+                let used = stages
+                    .iter()
+                    .flat_map(|(_, reflection)| &reflection.bindings)
+                    .map(|binding| binding.set + 1)
+                    .max()
+                    .unwrap_or(0);
+                if used as usize > layouts.len() {
+                    return Err(RendererError::InvalidPipeline(format!(
+                        "shaders use {} descriptor sets, {} set layouts given",
+                        used,
+                        layouts.len()
+                    )));
+                }
+                layouts.to_vec()
+            }
+            None => cached_set_layouts(device, cache, &merge_bindings(stages)?)?,
+        };
+
+        let info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let handle = unsafe { device.create_pipeline_layout(&info, None)? };
+
+        Ok(Self {
+            handle,
+            set_layouts,
+            push_constant_ranges,
+        })
+    }
+
+    pub(in crate::core) fn destroy(self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_layout(self.handle, None) };
+    }
+}
+
+/// Pipeline with the layout it was created with, give it back with `Device::destroy_pipeline`
+#[derive(Debug)]
+pub struct Pipeline {
+    handle: vk::Pipeline,
+    layout: PipelineLayout,
+    bind_point: vk::PipelineBindPoint,
+}
+
+impl Pipeline {
+    pub(in crate::core) fn new(
+        handle: vk::Pipeline,
+        layout: PipelineLayout,
+        bind_point: vk::PipelineBindPoint,
+    ) -> Self {
+        Self {
+            handle,
+            layout,
+            bind_point,
+        }
+    }
+
+    /// Takes ownership of both handles, they get destroyed together
+    pub fn from_raw(
+        handle: vk::Pipeline,
+        layout: vk::PipelineLayout,
+        bind_point: vk::PipelineBindPoint,
+    ) -> Self {
+        let layout = PipelineLayout {
+            handle: layout,
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+        };
+        Self::new(handle, layout, bind_point)
+    }
+
+    pub(in crate::core) fn into_parts(self) -> (vk::Pipeline, vk::PipelineLayout) {
+        (self.handle, self.layout.handle)
+    }
+
+    pub fn handle(&self) -> vk::Pipeline {
+        self.handle
+    }
+
+    pub fn layout(&self) -> &PipelineLayout {
+        &self.layout
+    }
+
+    pub fn bind_point(&self) -> vk::PipelineBindPoint {
+        self.bind_point
+    }
+}
+
+/// Shader stage of a pipeline being built, the stage comes from the module reflection
+pub(in crate::core) struct PipelineStage<'a> {
+    pub module: &'a ShaderModule,
+    pub entry_point: CString,
+    pub stage: vk::ShaderStageFlags,
+}
+
+impl<'a> PipelineStage<'a> {
+    pub fn new(module: &'a ShaderModule, entry_point: &str) -> Result<Self, RendererError> {
+        let Some(stage) = module.stage(entry_point) else {
+            return Err(RendererError::InvalidPipeline(format!(
+                "shader has no entry point \"{}\"",
+                entry_point
+            )));
+        };
+        let entry_point = CString::new(entry_point).map_err(|_| {
+            RendererError::InvalidPipeline("entry point name with a nul byte".to_owned())
+        })?;
+        Ok(Self {
+            module,
+            entry_point,
+            stage,
+        })
+    }
+
+    pub fn reflection(&self) -> (vk::ShaderStageFlags, &'a ShaderReflection) {
+        (self.stage, self.module.reflection())
+    }
+}
+
+/// Specialization data of every stage, kept alive until the pipeline is created
+pub(in crate::core) struct Specialization {
+    stages: Vec<(Vec<vk::SpecializationMapEntry>, Vec<u8>)>,
+}
+
+impl Specialization {
+    /// Each constant goes to the stages declaring its id, the value must have the declared type
+    pub fn new(
+        stages: &[(vk::ShaderStageFlags, &ShaderReflection)],
+        constants: &[(u32, SpecValue)],
+    ) -> Result<Self, RendererError> {
+        for (id, value) in constants {
+            let declared = stages
+                .iter()
+                .flat_map(|(_, reflection)| &reflection.spec_constants)
+                .find(|constant| constant.id == *id);
+            match declared {
+                None => {
+                    return Err(RendererError::InvalidPipeline(format!(
+                        "no stage declares specialization constant {}",
+                        id
+                    )))
+                }
+                Some(declared)
+                    if std::mem::discriminant(&declared.default)
+                        != std::mem::discriminant(value) =>
+                {
+                    return Err(RendererError::InvalidPipeline(format!(
+                        "specialization constant {} is a {:?}, got {:?}",
+                        id, declared.default, value
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let stages = stages
+            .iter()
+            .map(|(_, reflection)| {
+                let mut entries = vec![];
+                let mut data = vec![];
+                for (id, value) in constants {
+                    if reflection.spec_constants.iter().any(|c| c.id == *id) {
+                        entries.push(
+                            vk::SpecializationMapEntry::default()
+                                .constant_id(*id)
+                                .offset(data.len() as u32)
+                                .size(value.size()),
+                        );
+                        data.extend(value.to_bytes());
+                    }
+                }
+                (entries, data)
+            })
+            .collect();
+        Ok(Self { stages })
+    }
+
+    /// Info of the stage at `index`, None when it has nothing to specialize
+    pub fn info(&self, index: usize) -> Option<vk::SpecializationInfo<'_>> {
+        let (entries, data) = &self.stages[index];
+        (!entries.is_empty()).then(|| {
+            vk::SpecializationInfo::default()
+                .map_entries(entries)
+                .data(data)
+        })
+    }
+}
+
+/// One range covering every block, visible to every stage that declares one
+fn merge_push_constants(
+    stages: &[(vk::ShaderStageFlags, &ShaderReflection)],
+) -> Vec<vk::PushConstantRange> {
+    let mut merged: Option<vk::PushConstantRange> = None;
+    for (stage, reflection) in stages {
+        let Some(block) = &reflection.push_constants else {
+            continue;
+        };
+        let range = merged.get_or_insert(vk::PushConstantRange {
+            stage_flags: *stage,
+            offset: block.offset,
+            size: block.size,
+        });
+        let end = (range.offset + range.size).max(block.offset + block.size);
+        range.offset = range.offset.min(block.offset);
+        range.size = end - range.offset;
+        range.stage_flags |= *stage;
+    }
+    merged.into_iter().collect()
+}
+
+fn check_push_constants(
+    stages: &[(vk::ShaderStageFlags, &ShaderReflection)],
+    ranges: &[vk::PushConstantRange],
+) -> Result<(), RendererError> {
+    for (stage, reflection) in stages {
+        let Some(block) = &reflection.push_constants else {
+            continue;
+        };
+        let covered = ranges.iter().any(|range| {
+            range.stage_flags.contains(*stage)
+                && range.offset <= block.offset
+                && block.offset + block.size <= range.offset + range.size
+        });
+        if !covered {
+            return Err(RendererError::InvalidPipeline(format!(
+                "push constants {}..{} of the {:?} stage are outside the given ranges",
+                block.offset,
+                block.offset + block.size,
+                stage
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Bindings of every stage by set, a binding shared by several stages must agree on its type
+/// and count
+fn merge_bindings(
+    stages: &[(vk::ShaderStageFlags, &ShaderReflection)],
+) -> Result<BTreeMap<u32, Vec<vk::DescriptorSetLayoutBinding<'static>>>, RendererError> {
+    let mut sets: BTreeMap<u32, Vec<vk::DescriptorSetLayoutBinding>> = BTreeMap::new();
+    for (stage, reflection) in stages {
+        for binding in &reflection.bindings {
+            if binding.count == 0 {
+                return Err(RendererError::InvalidPipeline(format!(
+                    "runtime sized array at set {} binding {} needs explicit set layouts",
+                    binding.set, binding.binding
+                )));
+            }
+            let set = sets.entry(binding.set).or_default();
+            match set.iter_mut().find(|b| b.binding == binding.binding) {
+                Some(existing) => {
+                    if existing.descriptor_type != binding.descriptor_type
+                        || existing.descriptor_count != binding.count
+                    {
+                        return Err(RendererError::InvalidPipeline(format!(
+                            "set {} binding {} is declared differently across stages",
+                            binding.set, binding.binding
+                        )));
+                    }
+                    existing.stage_flags |= *stage;
+                }
+                None => set.push(
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(binding.binding)
+                        .descriptor_type(binding.descriptor_type)
+                        .descriptor_count(binding.count)
+                        .stage_flags(*stage),
+                ),
+            }
+        }
+    }
+    Ok(sets)
+}
+
+/// One layout per set up to the highest one used, unused sets get an empty layout
+fn cached_set_layouts(
+    device: &ash::Device,
+    cache: &DescriptorLayoutCache,
+    sets: &BTreeMap<u32, Vec<vk::DescriptorSetLayoutBinding>>,
+) -> Result<Vec<vk::DescriptorSetLayout>, RendererError> {
+    let count = sets.keys().next_back().map_or(0, |set| set + 1);
+    (0..count)
+        .map(|set| {
+            let bindings = sets.get(&set).map(Vec::as_slice).unwrap_or_default();
+            cache.get_raw(device, bindings)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{DescriptorBinding, PushConstantBlock, SpecConstant};
+
+    const VERTEX: vk::ShaderStageFlags = vk::ShaderStageFlags::VERTEX;
+    const FRAGMENT: vk::ShaderStageFlags = vk::ShaderStageFlags::FRAGMENT;
+
+    fn binding(set: u32, binding: u32, descriptor_type: vk::DescriptorType) -> DescriptorBinding {
+        DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count: 1,
+            name: None,
+        }
+    }
+
+    fn push(offset: u32, size: u32) -> Option<PushConstantBlock> {
+        Some(PushConstantBlock {
+            name: None,
+            offset,
+            size,
+        })
+    }
+
+    fn spec(id: u32, default: SpecValue) -> SpecConstant {
+        SpecConstant {
+            id,
+            name: None,
+            default,
+        }
+    }
+
+    #[test]
+    fn bindings_merge_stage_flags() {
+        let vertex = ShaderReflection {
+            bindings: vec![binding(0, 0, vk::DescriptorType::UNIFORM_BUFFER)],
+            ..Default::default()
+        };
+        let fragment = ShaderReflection {
+            bindings: vec![
+                binding(0, 0, vk::DescriptorType::UNIFORM_BUFFER),
+                binding(2, 1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            ],
+            ..Default::default()
+        };
+        let sets = merge_bindings(&[(VERTEX, &vertex), (FRAGMENT, &fragment)]).unwrap();
+
+        assert_eq!(sets.keys().copied().collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(sets[&0].len(), 1);
+        assert_eq!(sets[&0][0].stage_flags, VERTEX | FRAGMENT);
+        assert_eq!(sets[&2][0].binding, 1);
+        assert_eq!(sets[&2][0].stage_flags, FRAGMENT);
+    }
+
+    #[test]
+    fn bindings_must_agree_across_stages() {
+        let vertex = ShaderReflection {
+            bindings: vec![binding(0, 0, vk::DescriptorType::UNIFORM_BUFFER)],
+            ..Default::default()
+        };
+        let other_type = ShaderReflection {
+            bindings: vec![binding(0, 0, vk::DescriptorType::STORAGE_BUFFER)],
+            ..Default::default()
+        };
+        assert!(merge_bindings(&[(VERTEX, &vertex), (FRAGMENT, &other_type)]).is_err());
+
+        let mut other_count = vertex.clone();
+        other_count.bindings[0].count = 4;
+        assert!(merge_bindings(&[(VERTEX, &vertex), (FRAGMENT, &other_count)]).is_err());
+
+        let mut runtime_array = vertex.clone();
+        runtime_array.bindings[0].count = 0;
+        assert!(merge_bindings(&[(VERTEX, &runtime_array)]).is_err());
+    }
+
+    #[test]
+    fn push_constants_merge_into_one_range() {
+        let vertex = ShaderReflection {
+            push_constants: push(0, 64),
+            ..Default::default()
+        };
+        let fragment = ShaderReflection {
+            push_constants: push(64, 16),
+            ..Default::default()
+        };
+        let none = ShaderReflection::default();
+        let ranges = merge_push_constants(&[
+            (VERTEX, &vertex),
+            (FRAGMENT, &fragment),
+            (vk::ShaderStageFlags::GEOMETRY, &none),
+        ]);
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].offset, ranges[0].size), (0, 80));
+        assert_eq!(ranges[0].stage_flags, VERTEX | FRAGMENT);
+        assert!(merge_push_constants(&[(VERTEX, &none)]).is_empty());
+    }
+
+    #[test]
+    fn given_push_ranges_must_cover_each_stage() {
+        let vertex = ShaderReflection {
+            push_constants: push(0, 64),
+            ..Default::default()
+        };
+        let fragment = ShaderReflection {
+            push_constants: push(64, 16),
+            ..Default::default()
+        };
+        let stages = [(VERTEX, &vertex), (FRAGMENT, &fragment)];
+        let range = |stage_flags, offset, size| vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        };
+
+        // overlapping ranges, each stage covered by its own
+        assert!(check_push_constants(
+            &stages,
+            &[range(VERTEX, 0, 64), range(VERTEX | FRAGMENT, 48, 32)]
+        )
+        .is_ok());
+        // the fragment block only partially covered
+        assert!(
+            check_push_constants(&stages, &[range(VERTEX, 0, 64), range(FRAGMENT, 64, 8)]).is_err()
+        );
+        // covered bytes, wrong stage
+        assert!(check_push_constants(&stages, &[range(VERTEX, 0, 80)]).is_err());
+    }
+
+    #[test]
+    fn specialization_data_per_stage() {
+        let vertex = ShaderReflection {
+            spec_constants: vec![spec(0, SpecValue::U32(1)), spec(1, SpecValue::F64(0.0))],
+            ..Default::default()
+        };
+        let fragment = ShaderReflection {
+            spec_constants: vec![
+                spec(1, SpecValue::F64(0.0)),
+                spec(2, SpecValue::Bool(false)),
+            ],
+            ..Default::default()
+        };
+        let none = ShaderReflection::default();
+        let specialization = Specialization::new(
+            &[(VERTEX, &vertex), (FRAGMENT, &fragment), (VERTEX, &none)],
+            &[
+                (1, SpecValue::F64(2.5)),
+                (0, SpecValue::U32(7)),
+                (2, SpecValue::Bool(true)),
+            ],
+        )
+        .unwrap();
+
+        let info = specialization.info(0).unwrap();
+        let entries = unsafe { std::slice::from_raw_parts(info.p_map_entries, 2) };
+        assert_eq!(info.map_entry_count, 2);
+        assert_eq!(info.data_size, 12);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.constant_id, entry.offset, entry.size))
+                .collect::<Vec<_>>(),
+            [(1, 0, 8), (0, 8, 4)]
+        );
+        let (_, data) = &specialization.stages[0];
+        assert_eq!(data[..8], 2.5f64.to_ne_bytes());
+        assert_eq!(data[8..], 7u32.to_ne_bytes());
+
+        let (entries, data) = &specialization.stages[1];
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.constant_id, entry.offset, entry.size))
+                .collect::<Vec<_>>(),
+            [(1, 0, 8), (2, 8, 4)]
+        );
+        assert_eq!(data[8..], 1u32.to_ne_bytes());
+        assert!(specialization.info(2).is_none());
+    }
+
+    #[test]
+    fn specialization_rejects_unknown_and_mistyped_constants() {
+        let vertex = ShaderReflection {
+            spec_constants: vec![spec(0, SpecValue::U32(1))],
+            ..Default::default()
+        };
+        let stages = [(VERTEX, &vertex)];
+        assert!(Specialization::new(&stages, &[(3, SpecValue::U32(1))]).is_err());
+        assert!(Specialization::new(&stages, &[(0, SpecValue::I32(1))]).is_err());
+        assert!(Specialization::new(&stages, &[(0, SpecValue::U32(9))]).is_ok());
+    }
+}
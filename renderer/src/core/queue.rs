@@ -0,0 +1,283 @@
+use std::sync::Mutex;
+
+use ash::vk;
+
+use super::{RejectionReason, Surface};
+
+/// Queue families used by the device, the optional ones are only set when the gpu has a
+/// family dedicated to that kind of work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    /// Set only when the graphics family can't present to the surface
+    pub present: Option<u32>,
+    /// Compute capable family without graphics
+    pub compute: Option<u32>,
+    /// Transfer capable family without graphics and compute
+    pub transfer: Option<u32>,
+}
+
+impl QueueFamilies {
+    /// Graphics family, presenting from it when possible. Without a surface presentation
+    /// support is not checked
+    pub(in crate::core) fn find(
+        gpu: vk::PhysicalDevice,
+        props: &[vk::QueueFamilyProperties],
+        surface: Option<&Surface>,
+    ) -> Result<Self, RejectionReason> {
+        let has = |index: usize, flags: vk::QueueFlags| {
+            props[index].queue_count > 0 && props[index].queue_flags.contains(flags)
+        };
+        let lacks =
+            |index: usize, flags: vk::QueueFlags| !props[index].queue_flags.intersects(flags);
+
+        let graphics: Vec<u32> = (0..props.len())
+            .filter(|index| has(*index, vk::QueueFlags::GRAPHICS))
+            .map(|index| index as u32)
+            .collect();
+        let Some(first_graphics) = graphics.first().copied() else {
+            return Err(RejectionReason::NoGraphicsQueue);
+        };
+
+        let (graphics, present) = match surface {
+            None => (first_graphics, None),
+            Some(surface) => {
+                let mut presenting = vec![];
+                for index in 0..props.len() as u32 {
+                    if surface
+                        .support_presenting(gpu, index)
+                        .map_err(RejectionReason::Query)?
+                    {
+                        presenting.push(index);
+                    }
+                }
+
+                if let Some(index) = graphics.iter().find(|index| presenting.contains(index)) {
+                    (*index, None)
+                } else if let Some(index) = presenting.first() {
+                    log::trace!("Graphics family can't present, using family {}", index);
+                    (first_graphics, Some(*index))
+                } else {
+                    return Err(RejectionReason::NoPresentQueue);
+                }
+            }
+        };
+
+        let compute = (0..props.len())
+            .find(|index| {
+                has(*index, vk::QueueFlags::COMPUTE) && lacks(*index, vk::QueueFlags::GRAPHICS)
+            })
+            .map(|index| index as u32);
+
+        // graphics and compute families support transfers implicitly, only count dedicated ones
+        let transfer = (0..props.len())
+            .find(|index| {
+                has(*index, vk::QueueFlags::TRANSFER)
+                    && lacks(*index, vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|index| index as u32);
+
+        Ok(Self {
+            graphics,
+            present,
+            compute,
+            transfer,
+        })
+    }
+
+    /// Every family a queue is created from, without duplicates
+    pub fn unique(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics];
+        for family in [self.present, self.compute, self.transfer]
+            .into_iter()
+            .flatten()
+        {
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        families
+    }
+}
+
+/// Queue of one family, submissions are serialized so it can be shared between threads
+pub struct Queue {
+    device: ash::Device,
+    handle: vk::Queue,
+    family: u32,
+    flags: vk::QueueFlags,
+    granularity: vk::Extent3D,
+    lock: Mutex<()>,
+}
+
+impl Queue {
+    pub(in crate::core) fn new(
+        device: &ash::Device,
+        family: u32,
+        props: &vk::QueueFamilyProperties,
+    ) -> Self {
+        Self {
+            device: device.clone(),
+            handle: unsafe { device.get_device_queue(family, 0) },
+            family,
+            flags: props.queue_flags,
+            granularity: props.min_image_transfer_granularity,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Raw handle, access to it must be externally synchronized with `submit`
+    pub fn handle(&self) -> vk::Queue {
+        self.handle
+    }
+
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    pub fn flags(&self) -> vk::QueueFlags {
+        self.flags
+    }
+
+    /// Image copies on this queue must be aligned to it, (0, 0, 0) allows whole mips only
+    pub fn min_image_transfer_granularity(&self) -> vk::Extent3D {
+        self.granularity
+    }
+
+    /// Submits command buffers, they execute in order
+    pub fn submit(
+        &self,
+        buffers: &[vk::CommandBuffer],
+        wait: &[(vk::Semaphore, vk::PipelineStageFlags2)],
+        signal: &[(vk::Semaphore, vk::PipelineStageFlags2)],
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        let wait: Vec<_> = wait
+            .iter()
+            .map(|(semaphore, stage)| {
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(*semaphore)
+                    .stage_mask(*stage)
+            })
+            .collect();
+        let signal: Vec<_> = signal
+            .iter()
+            .map(|(semaphore, stage)| {
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(*semaphore)
+                    .stage_mask(*stage)
+            })
+            .collect();
+        let buffers: Vec<_> = buffers
+            .iter()
+            .map(|buffer| vk::CommandBufferSubmitInfo::default().command_buffer(*buffer))
+            .collect();
+
+        let submit = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(&wait)
+            .signal_semaphore_infos(&signal)
+            .command_buffer_infos(&buffers);
+
+        self.locked(|queue| unsafe { self.device.queue_submit2(queue, &[submit], fence) })
+    }
+
+    pub fn wait_idle(&self) -> Result<(), vk::Result> {
+        self.locked(|queue| unsafe { self.device.queue_wait_idle(queue) })
+    }
+
+    /// Runs `f` while holding the submission lock, for queue operations other than submit
+    pub(in crate::core) fn locked<R>(&self, f: impl FnOnce(vk::Queue) -> R) -> R {
+        let _guard = self.lock.lock().unwrap_or_else(|err| err.into_inner());
+        f(self.handle)
+    }
+}
+
+/// Barriers moving an exclusive resource from one queue family to another. The release half
+/// is recorded on the source queue, the acquire half on the destination one after a semaphore
+/// wait, both with the same families, ranges and layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnershipTransfer {
+    pub src_family: u32,
+    pub dst_family: u32,
+}
+
+impl OwnershipTransfer {
+    pub fn new(src_family: u32, dst_family: u32) -> Self {
+        Self {
+            src_family,
+            dst_family,
+        }
+    }
+
+    /// `stage` and `access` are the ones of the last writes on the source queue
+    pub fn release_buffer(
+        &self,
+        buffer: vk::Buffer,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) -> vk::BufferMemoryBarrier2<'static> {
+        vk::BufferMemoryBarrier2::default()
+            .src_stage_mask(stage)
+            .src_access_mask(access)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+    }
+
+    /// `stage` and `access` are the ones of the first uses on the destination queue
+    pub fn acquire_buffer(
+        &self,
+        buffer: vk::Buffer,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) -> vk::BufferMemoryBarrier2<'static> {
+        vk::BufferMemoryBarrier2::default()
+            .dst_stage_mask(stage)
+            .dst_access_mask(access)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+    }
+
+    pub fn release_image(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(stage)
+            .src_access_mask(access)
+            .old_layout(layouts.0)
+            .new_layout(layouts.1)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .image(image)
+            .subresource_range(range)
+    }
+
+    pub fn acquire_image(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .dst_stage_mask(stage)
+            .dst_access_mask(access)
+            .old_layout(layouts.0)
+            .new_layout(layouts.1)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .image(image)
+            .subresource_range(range)
+    }
+}
@@ -0,0 +1,98 @@
+use ash::vk;
+
+use super::{texel_size, Buffer, BufferDesc, Device};
+use crate::RendererError;
+
+/// Host visible buffer an image gets copied into so its pixels can be read on the cpu,
+/// give it back with `Device::destroy_readback`
+pub struct Readback {
+    device: ash::Device,
+    buffer: Buffer,
+    extent: vk::Extent2D,
+    format: vk::Format,
+}
+
+impl Readback {
+    pub(in crate::core) fn new(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self, RendererError> {
+        let texel = texel_size(format).ok_or(RendererError::UnsupportedFormat(format))?;
+        let size = (extent.width as vk::DeviceSize)
+            .checked_mul(extent.height as vk::DeviceSize)
+            .and_then(|texels| texels.checked_mul(texel as vk::DeviceSize))
+            .ok_or_else(|| {
+                RendererError::OutOfRange(format!(
+                    "readback of a {}x{} image",
+                    extent.width, extent.height
+                ))
+            })?;
+        let buffer = device.create_buffer(&BufferDesc::readback(size))?;
+
+        Ok(Self {
+            device: device.handle().clone(),
+            buffer,
+            extent,
+            format,
+        })
+    }
+
+    pub(in crate::core) fn into_buffer(self) -> Buffer {
+        self.buffer
+    }
+
+    /// Records the copy of mip 0/layer 0 of `image`, which must be in TRANSFER_SRC_OPTIMAL
+    /// and match the extent and format of the readback, followed by a barrier to host reads
+    pub fn record_copy(&self, buffer: vk::CommandBuffer, image: vk::Image) {
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            });
+
+        unsafe {
+            self.device.cmd_copy_image_to_buffer(
+                buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffer.handle(),
+                &[region],
+            )
+        };
+
+        // makes the copy visible to the host once the submission's fence is signaled
+        let barriers = [vk::BufferMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.handle())
+            .offset(0)
+            .size(vk::WHOLE_SIZE)];
+        let info = vk::DependencyInfo::default().buffer_memory_barriers(&barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(buffer, &info) };
+    }
+
+    /// Tightly packed texels, the copy must have completed on the gpu already
+    pub fn read(&self) -> Result<Vec<u8>, RendererError> {
+        self.buffer.read_slice(0, self.buffer.size() as usize)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+}
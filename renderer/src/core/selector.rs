@@ -0,0 +1,389 @@
+use std::{borrow::Cow, fmt, str::FromStr, sync::Arc};
+
+use ash::vk;
+
+use super::RejectionReason;
+
+/// Environment variable read by `GpuSelector::default()`, see `GpuSelector::from_str` for the syntax
+pub const GPU_SELECTOR_ENV: &str = "RENDERER_GPU";
+
+/// Which kind of physical device should win when more than one is suitable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GpuPreference {
+    /// Discrete GPUs first, then integrated, virtual and software ones
+    #[default]
+    HighPerformance,
+    /// Integrated GPUs first, useful on laptops to save battery
+    LowPower,
+}
+
+/// Everything known about a physical device when it gets scored
+#[derive(Debug, Clone)]
+pub struct GpuCandidate {
+    /// Position in `vkEnumeratePhysicalDevices`, also used to break ties
+    pub index: usize,
+    pub name: String,
+    pub uuid: [u8; vk::UUID_SIZE],
+    pub properties: vk::PhysicalDeviceProperties,
+    pub memory: vk::PhysicalDeviceMemoryProperties,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+}
+
+impl GpuCandidate {
+    pub(in crate::core) fn query(
+        instance: &ash::Instance,
+        gpu: vk::PhysicalDevice,
+        index: usize,
+    ) -> Self {
+        let mut id_props = vk::PhysicalDeviceIDProperties::default();
+        let mut props2 = vk::PhysicalDeviceProperties2::default().push_next(&mut id_props);
+        unsafe { instance.get_physical_device_properties2(gpu, &mut props2) };
+        let properties = props2.properties;
+
+        let name = properties
+            .device_name_as_c_str()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self {
+            index,
+            name,
+            uuid: id_props.device_uuid,
+            properties,
+            memory: unsafe { instance.get_physical_device_memory_properties(gpu) },
+            queue_families: unsafe { instance.get_physical_device_queue_family_properties(gpu) },
+        }
+    }
+
+    /// Total size of the device local heaps
+    pub fn device_local_memory(&self) -> vk::DeviceSize {
+        self.memory
+            .memory_heaps_as_slice()
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+}
+
+/// Custom scoring function, `None` rejects the device
+pub type GpuScorer = Arc<dyn Fn(&GpuCandidate) -> Option<i64> + Send + Sync>;
+
+/// Policy used to pick the physical device among the suitable ones
+#[derive(Clone)]
+pub enum GpuSelector {
+    /// Best device type for the preference, then the biggest image dimension limit
+    Preference(GpuPreference),
+    /// Case insensitive substring of the device name
+    NameContains(String),
+    VendorDevice {
+        vendor_id: u32,
+        device_id: Option<u32>,
+    },
+    Uuid([u8; vk::UUID_SIZE]),
+    /// Position in `vkEnumeratePhysicalDevices`
+    Index(usize),
+    /// Parses the variable as a selector, `fallback` is used when it is unset or invalid
+    Env {
+        var: String,
+        fallback: Box<GpuSelector>,
+    },
+    Custom(GpuScorer),
+}
+
+impl Default for GpuSelector {
+    fn default() -> Self {
+        Self::Env {
+            var: GPU_SELECTOR_ENV.to_owned(),
+            fallback: Box::new(Self::Preference(GpuPreference::default())),
+        }
+    }
+}
+
+impl GpuSelector {
+    pub fn custom<F>(scorer: F) -> Self
+    where
+        F: Fn(&GpuCandidate) -> Option<i64> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(scorer))
+    }
+
+    /// Replaces `Env` with the selector it points to
+    pub(in crate::core) fn resolve(&self) -> Cow<'_, GpuSelector> {
+        let Self::Env { var, fallback } = self else {
+            return Cow::Borrowed(self);
+        };
+
+        match std::env::var(var) {
+            Ok(value) => match value.parse::<GpuSelector>() {
+                Ok(selector) => {
+                    log::info!("GPU selection overridden by {}={}", var, value);
+                    Cow::Owned(selector)
+                }
+                Err(err) => {
+                    log::warn!("Ignoring {}: {}", var, err);
+                    fallback.resolve()
+                }
+            },
+            Err(_) => fallback.resolve(),
+        }
+    }
+
+    /// Higher is better, an error means the device must not be picked
+    pub(in crate::core) fn score(&self, gpu: &GpuCandidate) -> Result<i64, RejectionReason> {
+        let default_score = rate(&gpu.properties, GpuPreference::default());
+        match self {
+            Self::Preference(preference) => Ok(rate(&gpu.properties, *preference)),
+            Self::NameContains(name) => {
+                if gpu.name.to_lowercase().contains(&name.to_lowercase()) {
+                    Ok(default_score)
+                } else {
+                    Err(RejectionReason::NotSelected(format!(
+                        "name doesn't contain \"{}\"",
+                        name
+                    )))
+                }
+            }
+            Self::VendorDevice {
+                vendor_id,
+                device_id,
+            } => {
+                let vendor_matches = gpu.properties.vendor_id == *vendor_id;
+                let device_matches = device_id.is_none_or(|id| gpu.properties.device_id == id);
+                if vendor_matches && device_matches {
+                    Ok(default_score)
+                } else {
+                    Err(RejectionReason::NotSelected(format!(
+                        "id {:04x}:{:04x} doesn't match",
+                        gpu.properties.vendor_id, gpu.properties.device_id
+                    )))
+                }
+            }
+            Self::Uuid(uuid) => {
+                if gpu.uuid == *uuid {
+                    Ok(default_score)
+                } else {
+                    Err(RejectionReason::NotSelected(
+                        "uuid doesn't match".to_owned(),
+                    ))
+                }
+            }
+            Self::Index(index) => {
+                if gpu.index == *index {
+                    Ok(default_score)
+                } else {
+                    Err(RejectionReason::NotSelected(format!(
+                        "index {} wasn't requested",
+                        gpu.index
+                    )))
+                }
+            }
+            Self::Env { .. } => self.resolve().score(gpu),
+            Self::Custom(scorer) => scorer(gpu).ok_or(RejectionReason::NotSelected(
+                "rejected by custom scorer".to_owned(),
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Preference(preference) => f.debug_tuple("Preference").field(preference).finish(),
+            Self::NameContains(name) => f.debug_tuple("NameContains").field(name).finish(),
+            Self::VendorDevice {
+                vendor_id,
+                device_id,
+            } => f
+                .debug_struct("VendorDevice")
+                .field("vendor_id", vendor_id)
+                .field("device_id", device_id)
+                .finish(),
+            Self::Uuid(uuid) => f.debug_tuple("Uuid").field(uuid).finish(),
+            Self::Index(index) => f.debug_tuple("Index").field(index).finish(),
+            Self::Env { var, fallback } => f
+                .debug_struct("Env")
+                .field("var", var)
+                .field("fallback", fallback)
+                .finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Accepted syntax:
+/// `index:N` or `N`, `name:SUBSTR`, `id:VENDOR[:DEVICE]` in hex, `uuid:HEX`,
+/// `high-performance`, `low-power`, anything else is treated as a name substring
+impl FromStr for GpuSelector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("empty gpu selector".to_owned());
+        }
+
+        let parse_hex = |hex: &str| {
+            u32::from_str_radix(hex.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("\"{}\" is not a valid hex id", hex))
+        };
+
+        if let Ok(index) = value.parse::<usize>() {
+            return Ok(Self::Index(index));
+        }
+
+        match value.split_once(':') {
+            Some(("index", index)) => index
+                .parse()
+                .map(Self::Index)
+                .map_err(|_| format!("\"{}\" is not a valid index", index)),
+            Some(("name", name)) => Ok(Self::NameContains(name.to_owned())),
+            Some(("id", ids)) => {
+                let (vendor, device) = match ids.split_once(':') {
+                    Some((vendor, device)) => (vendor, Some(device)),
+                    None => (ids, None),
+                };
+                Ok(Self::VendorDevice {
+                    vendor_id: parse_hex(vendor)?,
+                    device_id: device.map(parse_hex).transpose()?,
+                })
+            }
+            Some(("uuid", hex)) => {
+                let hex: String = hex.chars().filter(|c| *c != '-').collect();
+                // checked before slicing, a multi byte char would split a byte boundary
+                if hex.len() != vk::UUID_SIZE * 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("uuid must be {} hex digits", vk::UUID_SIZE * 2));
+                }
+                let mut uuid = [0u8; vk::UUID_SIZE];
+                for (i, byte) in uuid.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                        .map_err(|_| format!("\"{}\" is not a valid uuid", hex))?;
+                }
+                Ok(Self::Uuid(uuid))
+            }
+            _ => match value {
+                "high-performance" => Ok(Self::Preference(GpuPreference::HighPerformance)),
+                "low-power" => Ok(Self::Preference(GpuPreference::LowPower)),
+                _ => Ok(Self::NameContains(value.to_owned())),
+            },
+        }
+    }
+}
+
+fn rate(props: &vk::PhysicalDeviceProperties, preference: GpuPreference) -> i64 {
+    let (discrete, integrated) = match preference {
+        GpuPreference::HighPerformance => (1000, 100),
+        GpuPreference::LowPower => (100, 1000),
+    };
+    let type_rank: i64 = match props.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => discrete,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => integrated,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 50,
+        vk::PhysicalDeviceType::CPU => 10,
+        vk::PhysicalDeviceType::OTHER => 1,
+        // types newer than the ones known here are valid, just ranked last
+        _ => 0,
+    };
+    // the limit only breaks ties, it lives in the low 32 bits so the device type stays dominant
+    (type_rank << 32) + i64::from(props.limits.max_image_dimension2_d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(
+        device_type: vk::PhysicalDeviceType,
+        max_image: u32,
+    ) -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            device_type,
+            limits: vk::PhysicalDeviceLimits {
+                max_image_dimension2_d: max_image,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn device_type_outranks_the_image_limit() {
+        let integrated = properties(vk::PhysicalDeviceType::INTEGRATED_GPU, 8192);
+        let virtual_gpu = properties(vk::PhysicalDeviceType::VIRTUAL_GPU, u32::MAX);
+        let preference = GpuPreference::HighPerformance;
+        assert!(rate(&integrated, preference) > rate(&virtual_gpu, preference));
+
+        let small = properties(vk::PhysicalDeviceType::DISCRETE_GPU, 8192);
+        let big = properties(vk::PhysicalDeviceType::DISCRETE_GPU, 16384);
+        assert!(rate(&big, preference) > rate(&small, preference));
+    }
+
+    #[test]
+    fn unknown_device_types_rank_last() {
+        let unknown = properties(vk::PhysicalDeviceType::from_raw(42), 32768);
+        let other = properties(vk::PhysicalDeviceType::OTHER, 0);
+        let preference = GpuPreference::LowPower;
+        assert!(rate(&unknown, preference) < rate(&other, preference));
+    }
+
+    #[test]
+    fn parses_selectors() {
+        assert!(matches!("3".parse(), Ok(GpuSelector::Index(3))));
+        assert!(matches!("index:1".parse(), Ok(GpuSelector::Index(1))));
+        assert!(matches!(
+            "name:RTX".parse(),
+            Ok(GpuSelector::NameContains(name)) if name == "RTX"
+        ));
+        assert!(matches!(
+            "radeon".parse(),
+            Ok(GpuSelector::NameContains(name)) if name == "radeon"
+        ));
+        assert!(matches!(
+            "id:0x10de:2684".parse(),
+            Ok(GpuSelector::VendorDevice {
+                vendor_id: 0x10de,
+                device_id: Some(0x2684),
+            })
+        ));
+        assert!(matches!(
+            "id:1002".parse(),
+            Ok(GpuSelector::VendorDevice {
+                vendor_id: 0x1002,
+                device_id: None,
+            })
+        ));
+        assert!(matches!(
+            "high-performance".parse(),
+            Ok(GpuSelector::Preference(GpuPreference::HighPerformance))
+        ));
+        assert!(matches!(
+            "low-power".parse(),
+            Ok(GpuSelector::Preference(GpuPreference::LowPower))
+        ));
+
+        let mut expected = [0u8; vk::UUID_SIZE];
+        expected[0] = 0x01;
+        expected[15] = 0xef;
+        assert!(matches!(
+            "uuid:01000000-0000-0000-0000-0000000000ef".parse(),
+            Ok(GpuSelector::Uuid(uuid)) if uuid == expected
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_selectors() {
+        for value in [
+            "",
+            "   ",
+            "index:first",
+            "id:nvidia",
+            "id:10de:gpu",
+            "uuid:0123",
+            "uuid:0123456789abcdef0123456789abcdeg",
+            // 32 bytes long but the multi byte chars would land inside a slice
+            "uuid:0é123456789abcdef0123456789abcd",
+        ] {
+            assert!(value.parse::<GpuSelector>().is_err(), "{:?} parsed", value);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use ash::vk;
+
+use super::{ShaderReflection, Spirv};
+
+/// Compiled module with what its SPIR-V declares, give it back with
+/// `Device::destroy_shader_module` once the pipelines using it are created
+#[derive(Debug)]
+pub struct ShaderModule {
+    handle: vk::ShaderModule,
+    reflection: ShaderReflection,
+}
+
+impl ShaderModule {
+    pub(in crate::core) fn new(handle: vk::ShaderModule, reflection: ShaderReflection) -> Self {
+        Self { handle, reflection }
+    }
+
+    pub fn handle(&self) -> vk::ShaderModule {
+        self.handle
+    }
+
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+
+    /// Stage of the named entry point
+    pub fn stage(&self, entry_point: &str) -> Option<vk::ShaderStageFlags> {
+        self.reflection
+            .entry_point(entry_point)
+            .map(|entry| entry.stage)
+    }
+}
+
+impl Spirv {
+    pub(in crate::core) fn module_info(&self) -> vk::ShaderModuleCreateInfo<'_> {
+        vk::ShaderModuleCreateInfo::default().code(self.words())
+    }
+}
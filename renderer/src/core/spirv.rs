@@ -0,0 +1,1096 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use ash::vk;
+
+use crate::RendererError;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+/// Newest version a vulkan 1.3 device has to accept
+const MAX_VERSION: (u32, u32) = (1, 6);
+
+mod op {
+    pub const NAME: u32 = 5;
+    pub const ENTRY_POINT: u32 = 15;
+    pub const EXECUTION_MODE: u32 = 16;
+    pub const TYPE_BOOL: u32 = 20;
+    pub const TYPE_INT: u32 = 21;
+    pub const TYPE_FLOAT: u32 = 22;
+    pub const TYPE_VECTOR: u32 = 23;
+    pub const TYPE_MATRIX: u32 = 24;
+    pub const TYPE_IMAGE: u32 = 25;
+    pub const TYPE_SAMPLER: u32 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u32 = 27;
+    pub const TYPE_ARRAY: u32 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u32 = 29;
+    pub const TYPE_STRUCT: u32 = 30;
+    pub const TYPE_POINTER: u32 = 32;
+    pub const CONSTANT: u32 = 43;
+    pub const SPEC_CONSTANT_TRUE: u32 = 48;
+    pub const SPEC_CONSTANT_FALSE: u32 = 49;
+    pub const SPEC_CONSTANT: u32 = 50;
+    pub const VARIABLE: u32 = 59;
+    pub const DECORATE: u32 = 71;
+    pub const MEMBER_DECORATE: u32 = 72;
+    pub const TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+}
+
+mod decoration {
+    pub const SPEC_ID: u32 = 1;
+    pub const BLOCK: u32 = 2;
+    pub const BUFFER_BLOCK: u32 = 3;
+    pub const ROW_MAJOR: u32 = 4;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BUILT_IN: u32 = 11;
+    pub const LOCATION: u32 = 30;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+mod storage {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT: u32 = 1;
+    pub const UNIFORM: u32 = 2;
+    pub const PUSH_CONSTANT: u32 = 9;
+    pub const STORAGE_BUFFER: u32 = 12;
+}
+
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+/// Deeper type nesting than any real shader, past it the type is assumed to refer to itself
+const MAX_TYPE_DEPTH: u32 = 64;
+
+/// Why a SPIR-V binary was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpirvError {
+    /// The byte length isn't a multiple of 4
+    Misaligned(usize),
+    /// Shorter than the 5 word header
+    Truncated,
+    BadMagic(u32),
+    UnsupportedVersion(u32, u32),
+    /// Broken instruction stream, the offset is in words from the start of the module
+    Malformed {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// A type reflection can't describe, like a self referencing or overflowing one
+    InvalidType {
+        id: u32,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Misaligned(len) => write!(f, "{} bytes is not a whole number of words", len),
+            Self::Truncated => write!(f, "shorter than the SPIR-V header"),
+            Self::BadMagic(magic) => write!(f, "bad magic number {:#010x}", magic),
+            Self::UnsupportedVersion(major, minor) => {
+                write!(f, "unsupported SPIR-V version {}.{}", major, minor)
+            }
+            Self::Malformed { offset, reason } => write!(f, "{} at word {}", reason, offset),
+            Self::InvalidType { id, reason } => write!(f, "type %{} {}", id, reason),
+        }
+    }
+}
+
+impl std::error::Error for SpirvError {}
+
+/// Validated SPIR-V words in host byte order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spirv {
+    words: Vec<u32>,
+}
+
+impl Spirv {
+    /// Either byte order is accepted, as the magic number tells them apart
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SpirvError> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(SpirvError::Misaligned(bytes.len()));
+        }
+        let mut words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        if words.first() == Some(&MAGIC.swap_bytes()) {
+            words.iter_mut().for_each(|word| *word = word.swap_bytes());
+        }
+        Self::from_words(words)
+    }
+
+    pub fn from_words(words: Vec<u32>) -> Result<Self, SpirvError> {
+        if words.len() < HEADER_WORDS {
+            return Err(SpirvError::Truncated);
+        }
+        if words[0] != MAGIC {
+            return Err(SpirvError::BadMagic(words[0]));
+        }
+        let spirv = Self { words };
+        let (major, minor) = spirv.version();
+        if major != 1 || minor > MAX_VERSION.1 || spirv.words[1] & 0xFF00_00FF != 0 {
+            return Err(SpirvError::UnsupportedVersion(major, minor));
+        }
+
+        let mut offset = HEADER_WORDS;
+        while offset < spirv.words.len() {
+            let count = (spirv.words[offset] >> 16) as usize;
+            if count == 0 {
+                return Err(SpirvError::Malformed {
+                    offset,
+                    reason: "zero word count",
+                });
+            }
+            if offset + count > spirv.words.len() {
+                return Err(SpirvError::Malformed {
+                    offset,
+                    reason: "instruction past the end of the module",
+                });
+            }
+            offset += count;
+        }
+        Ok(spirv)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::from_bytes(&bytes)?)
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    /// Major and minor version from the header
+    pub fn version(&self) -> (u32, u32) {
+        ((self.words[1] >> 16) & 0xFF, (self.words[1] >> 8) & 0xFF)
+    }
+
+    pub fn reflect(&self) -> Result<ShaderReflection, SpirvError> {
+        Module::parse(&self.words)?.reflect()
+    }
+}
+
+/// Everything a pipeline needs to know about a module to build its layouts
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// At most one block, as a module can declare a single push constant variable
+    pub push_constants: Option<PushConstantBlock>,
+    /// Sorted by set then binding
+    pub bindings: Vec<DescriptorBinding>,
+    /// Located inputs of the vertex entry points, sorted by location
+    pub vertex_inputs: Vec<VertexInput>,
+    /// Sorted by constant id
+    pub spec_constants: Vec<SpecConstant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    /// Local workgroup size of compute like stages, when given as literals
+    pub workgroup_size: Option<[u32; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushConstantBlock {
+    pub name: Option<String>,
+    /// Offset of the first member, blocks can skip the bytes pushed by other stages
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Array length, 0 for runtime sized arrays
+    pub count: u32,
+    /// The variable name, or the block name for anonymous blocks
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    /// UNDEFINED for types a vertex attribute can't have
+    pub format: vk::Format,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecConstant {
+    pub id: u32,
+    pub name: Option<String>,
+    pub default: SpecValue,
+}
+
+/// Default of a specialization constant, also its type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+impl SpecValue {
+    /// Bytes taken in the specialization data, booleans are VkBool32
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Bool(_) | Self::I32(_) | Self::U32(_) | Self::F32(_) => 4,
+            Self::I64(_) | Self::U64(_) | Self::F64(_) => 8,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Self::Bool(value) => (value as u32).to_ne_bytes().to_vec(),
+            Self::I32(value) => value.to_ne_bytes().to_vec(),
+            Self::U32(value) => value.to_ne_bytes().to_vec(),
+            Self::I64(value) => value.to_ne_bytes().to_vec(),
+            Self::U64(value) => value.to_ne_bytes().to_vec(),
+            Self::F32(value) => value.to_ne_bytes().to_vec(),
+            Self::F64(value) => value.to_ne_bytes().to_vec(),
+        }
+    }
+}
+
+impl ShaderReflection {
+    /// Union of the stages of every entry point
+    pub fn stages(&self) -> vk::ShaderStageFlags {
+        self.entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, entry| {
+                stages | entry.stage
+            })
+    }
+
+    pub fn entry_point(&self, name: &str) -> Option<&EntryPoint> {
+        self.entry_points.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn binding(&self, set: u32, binding: u32) -> Option<&DescriptorBinding> {
+        self.bindings
+            .iter()
+            .find(|b| b.set == set && b.binding == binding)
+    }
+
+    pub fn spec_constant(&self, name: &str) -> Option<&SpecConstant> {
+        self.spec_constants
+            .iter()
+            .find(|constant| constant.name.as_deref() == Some(name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarKind {
+    Bool,
+    Sint,
+    Uint,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar(ScalarKind, u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage(u32),
+    /// Element type and the id of the length constant
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+    AccelerationStructure,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    spec_id: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+    array_stride: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+}
+
+struct RawEntryPoint {
+    model: u32,
+    id: u32,
+    name: String,
+    interface: Vec<u32>,
+}
+
+/// What reflection needs out of a module, everything else is skipped
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<RawEntryPoint>,
+    local_sizes: HashMap<u32, [u32; 3]>,
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+    types: HashMap<u32, Type>,
+    /// Literal words of the constants, scalar ones only
+    constants: HashMap<u32, (u32, Vec<u32>)>,
+    spec_constants: Vec<(u32, SpecValue)>,
+    /// Id, pointer type and storage class, in declaration order
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Result<Self, SpirvError> {
+        let mut module = Self::default();
+        let mut offset = HEADER_WORDS;
+        while offset < words.len() {
+            let count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xFFFF;
+            let operands = &words[offset + 1..offset + count];
+            module
+                .instruction(opcode, operands)
+                .ok_or(SpirvError::Malformed {
+                    offset,
+                    reason: "missing operands",
+                })?;
+            offset += count;
+        }
+        Ok(module)
+    }
+
+    /// None when the instruction is shorter than its opcode requires
+    fn instruction(&mut self, opcode: u32, operands: &[u32]) -> Option<()> {
+        let at = |index: usize| operands.get(index).copied();
+        match opcode {
+            op::NAME => {
+                let (name, _) = string(operands.get(1..)?)?;
+                self.names.insert(at(0)?, name);
+            }
+            op::ENTRY_POINT => {
+                let (name, used) = string(operands.get(2..)?)?;
+                self.entry_points.push(RawEntryPoint {
+                    model: at(0)?,
+                    id: at(1)?,
+                    name,
+                    interface: operands.get(2 + used..)?.to_vec(),
+                });
+            }
+            op::EXECUTION_MODE if at(1)? == EXECUTION_MODE_LOCAL_SIZE => {
+                self.local_sizes.insert(at(0)?, [at(2)?, at(3)?, at(4)?]);
+            }
+            op::DECORATE => {
+                let entry = self.decorations.entry(at(0)?).or_default();
+                match at(1)? {
+                    decoration::SPEC_ID => entry.spec_id = Some(at(2)?),
+                    decoration::BLOCK => entry.block = true,
+                    decoration::BUFFER_BLOCK => entry.buffer_block = true,
+                    decoration::ARRAY_STRIDE => entry.array_stride = Some(at(2)?),
+                    decoration::BUILT_IN => entry.built_in = true,
+                    decoration::LOCATION => entry.location = Some(at(2)?),
+                    decoration::BINDING => entry.binding = Some(at(2)?),
+                    decoration::DESCRIPTOR_SET => entry.set = Some(at(2)?),
+                    _ => {}
+                }
+            }
+            op::MEMBER_DECORATE => {
+                let entry = self.member_decorations.entry((at(0)?, at(1)?)).or_default();
+                match at(2)? {
+                    decoration::OFFSET => entry.offset = Some(at(3)?),
+                    decoration::MATRIX_STRIDE => entry.matrix_stride = Some(at(3)?),
+                    decoration::ROW_MAJOR => entry.row_major = true,
+                    _ => {}
+                }
+            }
+            op::TYPE_BOOL => {
+                self.types
+                    .insert(at(0)?, Type::Scalar(ScalarKind::Bool, 32));
+            }
+            op::TYPE_INT => {
+                let kind = if at(2)? == 1 {
+                    ScalarKind::Sint
+                } else {
+                    ScalarKind::Uint
+                };
+                self.types.insert(at(0)?, Type::Scalar(kind, at(1)?));
+            }
+            op::TYPE_FLOAT => {
+                self.types
+                    .insert(at(0)?, Type::Scalar(ScalarKind::Float, at(1)?));
+            }
+            op::TYPE_VECTOR => {
+                self.types.insert(at(0)?, Type::Vector(at(1)?, at(2)?));
+            }
+            op::TYPE_MATRIX => {
+                self.types.insert(at(0)?, Type::Matrix(at(1)?, at(2)?));
+            }
+            op::TYPE_IMAGE => {
+                let image = Type::Image {
+                    dim: at(2)?,
+                    sampled: at(6)?,
+                };
+                self.types.insert(at(0)?, image);
+            }
+            op::TYPE_SAMPLER => {
+                self.types.insert(at(0)?, Type::Sampler);
+            }
+            op::TYPE_SAMPLED_IMAGE => {
+                self.types.insert(at(0)?, Type::SampledImage(at(1)?));
+            }
+            op::TYPE_ARRAY => {
+                self.types.insert(at(0)?, Type::Array(at(1)?, at(2)?));
+            }
+            op::TYPE_RUNTIME_ARRAY => {
+                self.types.insert(at(0)?, Type::RuntimeArray(at(1)?));
+            }
+            op::TYPE_STRUCT => {
+                self.types
+                    .insert(at(0)?, Type::Struct(operands.get(1..)?.to_vec()));
+            }
+            op::TYPE_POINTER => {
+                self.types.insert(at(0)?, Type::Pointer(at(2)?));
+            }
+            op::TYPE_ACCELERATION_STRUCTURE => {
+                self.types.insert(at(0)?, Type::AccelerationStructure);
+            }
+            op::CONSTANT | op::SPEC_CONSTANT => {
+                let (ty, id) = (at(0)?, at(1)?);
+                let value = operands.get(2..)?.to_vec();
+                if opcode == op::SPEC_CONSTANT {
+                    if let Some(value) = self.spec_value(ty, &value) {
+                        self.spec_constants.push((id, value));
+                    }
+                }
+                self.constants.insert(id, (ty, value));
+            }
+            op::SPEC_CONSTANT_TRUE | op::SPEC_CONSTANT_FALSE => {
+                let value = opcode == op::SPEC_CONSTANT_TRUE;
+                self.spec_constants.push((at(1)?, SpecValue::Bool(value)));
+                self.constants.insert(at(1)?, (at(0)?, vec![value as u32]));
+            }
+            op::VARIABLE => {
+                self.variables.push((at(1)?, at(0)?, at(2)?));
+            }
+            _ => {}
+        }
+        Some(())
+    }
+
+    fn spec_value(&self, ty: u32, words: &[u32]) -> Option<SpecValue> {
+        let low = *words.first()?;
+        let wide = || Some(((*words.get(1)? as u64) << 32) | low as u64);
+        match self.types.get(&ty)? {
+            Type::Scalar(ScalarKind::Sint, 32) => Some(SpecValue::I32(low as i32)),
+            Type::Scalar(ScalarKind::Uint, 32) => Some(SpecValue::U32(low)),
+            Type::Scalar(ScalarKind::Sint, 64) => Some(SpecValue::I64(wide()? as i64)),
+            Type::Scalar(ScalarKind::Uint, 64) => Some(SpecValue::U64(wide()?)),
+            Type::Scalar(ScalarKind::Float, 32) => Some(SpecValue::F32(f32::from_bits(low))),
+            Type::Scalar(ScalarKind::Float, 64) => Some(SpecValue::F64(f64::from_bits(wide()?))),
+            _ => None,
+        }
+    }
+
+    fn reflect(&self) -> Result<ShaderReflection, SpirvError> {
+        let mut reflection = ShaderReflection::default();
+
+        for entry in &self.entry_points {
+            let Some(stage) = stage(entry.model) else {
+                continue;
+            };
+            reflection.entry_points.push(EntryPoint {
+                name: entry.name.clone(),
+                stage,
+                workgroup_size: self.local_sizes.get(&entry.id).copied(),
+            });
+        }
+
+        let vertex_interface: Vec<u32> = self
+            .entry_points
+            .iter()
+            .filter(|entry| entry.model == 0)
+            .flat_map(|entry| entry.interface.iter().copied())
+            .collect();
+
+        for (id, pointer, class) in &self.variables {
+            let Some(Type::Pointer(pointee)) = self.types.get(pointer) else {
+                continue;
+            };
+            let decorations = self.decorations.get(id).cloned().unwrap_or_default();
+            let name = self.names.get(id).filter(|name| !name.is_empty()).cloned();
+
+            match *class {
+                storage::PUSH_CONSTANT => {
+                    reflection.push_constants = self.push_constant_block(*pointee, name)?;
+                }
+                storage::INPUT if vertex_interface.contains(id) && !decorations.built_in => {
+                    let Some(location) = decorations.location else {
+                        continue;
+                    };
+                    let (format, locations) = self.vertex_format(*pointee, 0)?;
+                    for index in 0..locations {
+                        reflection.vertex_inputs.push(VertexInput {
+                            location: location + index,
+                            format,
+                            name: name.clone(),
+                        });
+                    }
+                }
+                storage::UNIFORM_CONSTANT | storage::UNIFORM | storage::STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (decorations.set, decorations.binding) else {
+                        continue;
+                    };
+                    let (element, count) = self.array_element(*pointee, 0)?;
+                    let Some(descriptor_type) = self.descriptor_type(*class, element) else {
+                        continue;
+                    };
+                    reflection.bindings.push(DescriptorBinding {
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                        name: name.or_else(|| self.names.get(&element).cloned()),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for (id, default) in &self.spec_constants {
+            let Some(spec_id) = self.decorations.get(id).and_then(|d| d.spec_id) else {
+                continue;
+            };
+            reflection.spec_constants.push(SpecConstant {
+                id: spec_id,
+                name: self.names.get(id).cloned(),
+                default: *default,
+            });
+        }
+
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.vertex_inputs.sort_by_key(|input| input.location);
+        reflection
+            .vertex_inputs
+            .dedup_by_key(|input| input.location);
+        reflection
+            .spec_constants
+            .sort_by_key(|constant| constant.id);
+        Ok(reflection)
+    }
+
+    /// Innermost element type and the total element count, 0 when runtime sized
+    fn array_element(&self, ty: u32, depth: u32) -> Result<(u32, u32), SpirvError> {
+        let depth = nested(ty, depth)?;
+        Ok(match self.types.get(&ty) {
+            Some(Type::Array(element, length)) => {
+                let (inner, count) = self.array_element(*element, depth)?;
+                let count = count
+                    .checked_mul(self.constant_u32(*length).unwrap_or(1))
+                    .ok_or_else(|| overflow(ty))?;
+                (inner, count)
+            }
+            Some(Type::RuntimeArray(element)) => (self.array_element(*element, depth)?.0, 0),
+            _ => (ty, 1),
+        })
+    }
+
+    fn constant_u32(&self, id: u32) -> Option<u32> {
+        self.constants
+            .get(&id)
+            .and_then(|(_, words)| words.first().copied())
+    }
+
+    fn descriptor_type(&self, class: u32, ty: u32) -> Option<vk::DescriptorType> {
+        let decorations = self.decorations.get(&ty);
+        let has = |check: fn(&Decorations) -> bool| decorations.is_some_and(check);
+        let descriptor = match (class, self.types.get(&ty)?) {
+            (storage::STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (storage::UNIFORM, Type::Struct(_)) if has(|d| d.buffer_block) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (storage::UNIFORM, Type::Struct(_)) if has(|d| d.block) => {
+                vk::DescriptorType::UNIFORM_BUFFER
+            }
+            (storage::UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (storage::UNIFORM_CONSTANT, Type::SampledImage(image)) => {
+                match self.types.get(image)? {
+                    Type::Image {
+                        dim: DIM_BUFFER, ..
+                    } => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    _ => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                }
+            }
+            (storage::UNIFORM_CONSTANT, Type::Image { dim, sampled }) => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (storage::UNIFORM_CONSTANT, Type::AccelerationStructure) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            _ => return None,
+        };
+        Some(descriptor)
+    }
+
+    fn push_constant_block(
+        &self,
+        ty: u32,
+        name: Option<String>,
+    ) -> Result<Option<PushConstantBlock>, SpirvError> {
+        let Some(Type::Struct(members)) = self.types.get(&ty) else {
+            return Ok(None);
+        };
+        let offset = (0..members.len() as u32)
+            .filter_map(|member| self.member_offset(ty, member))
+            .min()
+            .unwrap_or(0);
+        let size = self.size_of(ty, None, 0)?;
+        Ok(Some(PushConstantBlock {
+            name: name.or_else(|| self.names.get(&ty).cloned()),
+            offset,
+            size: size.saturating_sub(offset),
+        }))
+    }
+
+    fn member_offset(&self, ty: u32, member: u32) -> Option<u32> {
+        self.member_decorations
+            .get(&(ty, member))
+            .and_then(|d| d.offset)
+    }
+
+    /// Bytes used by an explicitly laid out type, up to the end of its last member.
+    /// Matrices need the decorations of the struct member holding them
+    fn size_of(
+        &self,
+        ty: u32,
+        member: Option<&MemberDecorations>,
+        depth: u32,
+    ) -> Result<u32, SpirvError> {
+        let depth = nested(ty, depth)?;
+        let size = match self.types.get(&ty) {
+            Some(Type::Scalar(_, width)) => Some(width / 8),
+            Some(Type::Vector(component, count)) => {
+                self.size_of(*component, None, depth)?.checked_mul(*count)
+            }
+            Some(Type::Matrix(column, columns)) => {
+                let Some(Type::Vector(component, rows)) = self.types.get(column) else {
+                    return Ok(0);
+                };
+                let stride = member.and_then(|m| m.matrix_stride);
+                let row_major = member.is_some_and(|m| m.row_major);
+                let (vectors, length) = if row_major {
+                    (*rows, *columns)
+                } else {
+                    (*columns, *rows)
+                };
+                let Some(skipped) = vectors.checked_sub(1) else {
+                    return Err(SpirvError::InvalidType {
+                        id: ty,
+                        reason: "is a matrix without columns or rows",
+                    });
+                };
+                self.size_of(*component, None, depth)?
+                    .checked_mul(length)
+                    .and_then(|vector| {
+                        stride
+                            .unwrap_or(vector)
+                            .checked_mul(skipped)?
+                            .checked_add(vector)
+                    })
+            }
+            Some(Type::Array(element, length)) => {
+                let length = self.constant_u32(*length).unwrap_or(1);
+                let stride = match self.decorations.get(&ty).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size_of(*element, member, depth)?,
+                };
+                stride.checked_mul(length)
+            }
+            Some(Type::Struct(members)) => {
+                let mut size = Some(0);
+                for (index, member_ty) in members.iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(ty, index as u32));
+                    let offset = decorations.and_then(|d| d.offset).unwrap_or(0);
+                    let end = offset.checked_add(self.size_of(*member_ty, decorations, depth)?);
+                    size = size.zip(end).map(|(size, end)| size.max(end));
+                }
+                size
+            }
+            _ => Some(0),
+        };
+        size.ok_or_else(|| overflow(ty))
+    }
+
+    /// Attribute format and the number of locations taken, one per matrix column
+    fn vertex_format(&self, ty: u32, depth: u32) -> Result<(vk::Format, u32), SpirvError> {
+        let depth = nested(ty, depth)?;
+        Ok(match self.types.get(&ty) {
+            Some(Type::Matrix(column, columns)) => {
+                (self.vertex_format(*column, depth)?.0, *columns)
+            }
+            Some(Type::Vector(component, count)) => match self.types.get(component) {
+                Some(Type::Scalar(kind, width)) => (attribute_format(*kind, *width, *count), 1),
+                _ => (vk::Format::UNDEFINED, 1),
+            },
+            Some(Type::Scalar(kind, width)) => (attribute_format(*kind, *width, 1), 1),
+            _ => (vk::Format::UNDEFINED, 1),
+        })
+    }
+}
+
+/// Depth to pass to the types nested in `ty`, an error once it gets unreasonably deep
+fn nested(ty: u32, depth: u32) -> Result<u32, SpirvError> {
+    if depth >= MAX_TYPE_DEPTH {
+        return Err(SpirvError::InvalidType {
+            id: ty,
+            reason: "nests too deep or refers to itself",
+        });
+    }
+    Ok(depth + 1)
+}
+
+fn overflow(ty: u32) -> SpirvError {
+    SpirvError::InvalidType {
+        id: ty,
+        reason: "is larger than 4GiB",
+    }
+}
+
+fn attribute_format(kind: ScalarKind, width: u32, count: u32) -> vk::Format {
+    use vk::Format as F;
+
+    let formats = match (kind, width) {
+        (ScalarKind::Float, 32) => [
+            F::R32_SFLOAT,
+            F::R32G32_SFLOAT,
+            F::R32G32B32_SFLOAT,
+            F::R32G32B32A32_SFLOAT,
+        ],
+        (ScalarKind::Sint, 32) => [
+            F::R32_SINT,
+            F::R32G32_SINT,
+            F::R32G32B32_SINT,
+            F::R32G32B32A32_SINT,
+        ],
+        (ScalarKind::Uint, 32) => [
+            F::R32_UINT,
+            F::R32G32_UINT,
+            F::R32G32B32_UINT,
+            F::R32G32B32A32_UINT,
+        ],
+        (ScalarKind::Float, 16) => [
+            F::R16_SFLOAT,
+            F::R16G16_SFLOAT,
+            F::R16G16B16_SFLOAT,
+            F::R16G16B16A16_SFLOAT,
+        ],
+        (ScalarKind::Float, 64) => [
+            F::R64_SFLOAT,
+            F::R64G64_SFLOAT,
+            F::R64G64B64_SFLOAT,
+            F::R64G64B64A64_SFLOAT,
+        ],
+        _ => return F::UNDEFINED,
+    };
+    formats
+        .get(count.wrapping_sub(1) as usize)
+        .copied()
+        .unwrap_or(F::UNDEFINED)
+}
+
+fn stage(model: u32) -> Option<vk::ShaderStageFlags> {
+    use vk::ShaderStageFlags as S;
+
+    let stage = match model {
+        0 => S::VERTEX,
+        1 => S::TESSELLATION_CONTROL,
+        2 => S::TESSELLATION_EVALUATION,
+        3 => S::GEOMETRY,
+        4 => S::FRAGMENT,
+        5 => S::COMPUTE,
+        5313 => S::RAYGEN_KHR,
+        5314 => S::INTERSECTION_KHR,
+        5315 => S::ANY_HIT_KHR,
+        5316 => S::CLOSEST_HIT_KHR,
+        5317 => S::MISS_KHR,
+        5318 => S::CALLABLE_KHR,
+        5364 => S::TASK_EXT,
+        5365 => S::MESH_EXT,
+        _ => return None,
+    };
+    Some(stage)
+}
+
+/// Nul terminated UTF-8 literal, with the number of words it takes
+fn string(words: &[u32]) -> Option<(String, usize)> {
+    let mut bytes = vec![];
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return Some((String::from_utf8_lossy(&bytes).into_owned(), index + 1));
+            }
+            bytes.push(byte);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflect(bytes: &[u8]) -> ShaderReflection {
+        Spirv::from_bytes(bytes).unwrap().reflect().unwrap()
+    }
+
+    fn binding(
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        name: &str,
+    ) -> DescriptorBinding {
+        DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            name: Some(name.to_owned()),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mesh = include_bytes!("../../tests/spirv/mesh.spv");
+
+        assert_eq!(
+            Spirv::from_bytes(&mesh[..mesh.len() - 1]),
+            Err(SpirvError::Misaligned(mesh.len() - 1))
+        );
+        assert_eq!(Spirv::from_bytes(&mesh[..16]), Err(SpirvError::Truncated));
+
+        let mut bad = mesh.to_vec();
+        bad[0] = 0;
+        assert!(matches!(
+            Spirv::from_bytes(&bad),
+            Err(SpirvError::BadMagic(_))
+        ));
+
+        let mut future = mesh.to_vec();
+        future[5] = 9;
+        assert_eq!(
+            Spirv::from_bytes(&future),
+            Err(SpirvError::UnsupportedVersion(1, 9))
+        );
+
+        // the last instruction claims more words than are left
+        let mut cut = mesh.to_vec();
+        let last = cut.len() - 4;
+        cut[last + 2] = 2;
+        assert!(matches!(
+            Spirv::from_bytes(&cut),
+            Err(SpirvError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn accepts_big_endian() {
+        let mesh = include_bytes!("../../tests/spirv/mesh.spv");
+        let swapped: Vec<u8> = mesh
+            .chunks_exact(4)
+            .flat_map(|word| [word[3], word[2], word[1], word[0]])
+            .collect();
+
+        let little = Spirv::from_bytes(mesh).unwrap();
+        let big = Spirv::from_bytes(&swapped).unwrap();
+        assert_eq!(little, big);
+        assert_eq!(little.version(), (1, 3));
+    }
+
+    #[test]
+    fn reflects_vertex_shader() {
+        let reflection = reflect(include_bytes!("../../tests/spirv/mesh.spv"));
+
+        assert_eq!(
+            reflection.entry_points,
+            vec![EntryPoint {
+                name: "main".to_owned(),
+                stage: vk::ShaderStageFlags::VERTEX,
+                workgroup_size: None,
+            }]
+        );
+
+        let inputs: Vec<_> = reflection
+            .vertex_inputs
+            .iter()
+            .map(|input| (input.location, input.format))
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                (0, vk::Format::R32G32B32_SFLOAT),
+                (1, vk::Format::R32G32_SFLOAT),
+                (2, vk::Format::R32G32B32A32_SFLOAT),
+                (3, vk::Format::R32G32_UINT),
+            ]
+        );
+
+        // mat4 followed by a uint
+        let push = reflection.push_constants.unwrap();
+        assert_eq!((push.offset, push.size), (0, 68));
+
+        assert_eq!(
+            reflection.bindings,
+            vec![binding(
+                0,
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                1,
+                "camera"
+            )]
+        );
+        assert!(reflection.spec_constants.is_empty());
+    }
+
+    #[test]
+    fn reflects_separate_image_and_sampler() {
+        let reflection = reflect(include_bytes!("../../tests/spirv/textured.spv"));
+
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::FRAGMENT);
+        assert!(reflection.vertex_inputs.is_empty());
+        assert!(reflection.push_constants.is_none());
+        assert_eq!(
+            reflection.bindings,
+            vec![
+                binding(1, 0, vk::DescriptorType::SAMPLED_IMAGE, 1, "albedo"),
+                binding(1, 1, vk::DescriptorType::SAMPLER, 1, "albedo_sampler"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reflects_compute_shader() {
+        let reflection = reflect(include_bytes!("../../tests/spirv/cull.spv"));
+
+        let entry = reflection.entry_point("cull").unwrap();
+        assert_eq!(entry.stage, vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(entry.workgroup_size, Some([64, 1, 1]));
+
+        assert_eq!(
+            reflection.bindings,
+            vec![
+                binding(0, 0, vk::DescriptorType::STORAGE_BUFFER, 1, "instances"),
+                binding(0, 1, vk::DescriptorType::STORAGE_BUFFER, 1, "visible"),
+                binding(0, 2, vk::DescriptorType::UNIFORM_BUFFER, 1, "params"),
+                binding(1, 0, vk::DescriptorType::STORAGE_IMAGE, 1, "depth_pyramid"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reflects_spec_constants_and_sampler_arrays() {
+        let reflection = reflect(include_bytes!("../../tests/spirv/specialized.spv"));
+
+        let constants: Vec<_> = reflection
+            .spec_constants
+            .iter()
+            .map(|constant| {
+                (
+                    constant.id,
+                    constant.name.as_deref().unwrap(),
+                    constant.default,
+                )
+            })
+            .collect();
+        assert_eq!(
+            constants,
+            vec![
+                (0, "USE_FOG", SpecValue::Bool(true)),
+                (1, "SAMPLES", SpecValue::I32(4)),
+                (2, "EXPOSURE", SpecValue::F32(1.5)),
+            ]
+        );
+        assert_eq!(
+            reflection.spec_constant("SAMPLES").unwrap().default.size(),
+            4
+        );
+
+        assert_eq!(
+            reflection.bindings,
+            vec![
+                binding(
+                    0,
+                    0,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    0,
+                    "textures"
+                ),
+                binding(
+                    0,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    4,
+                    "shadows"
+                ),
+            ]
+        );
+
+        // vec4 at 16 and a float at 32
+        assert_eq!(
+            reflection.push_constants,
+            Some(PushConstantBlock {
+                name: Some("push".to_owned()),
+                offset: 16,
+                size: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_types_reflection_cannot_size() {
+        let mut module = Module::default();
+        module.types.insert(1, Type::Scalar(ScalarKind::Float, 32));
+        module.types.insert(2, Type::Vector(1, 4));
+        // a matrix without columns
+        module.types.insert(3, Type::Matrix(2, 0));
+        // an array referring to itself
+        module.types.insert(4, Type::Array(4, 5));
+        // 16 bytes times u32::MAX elements
+        module.types.insert(6, Type::Array(2, 7));
+        module.constants.insert(7, (1, vec![u32::MAX]));
+        module.types.insert(8, Type::Struct(vec![8]));
+
+        for ty in [3, 4, 6, 8] {
+            assert!(matches!(
+                module.size_of(ty, None, 0),
+                Err(SpirvError::InvalidType { id, .. }) if id == ty
+            ));
+        }
+        assert!(module.array_element(4, 0).is_err());
+        assert!(module.array_element(6, 0).is_ok());
+        assert_eq!(module.size_of(2, None, 0), Ok(16));
+    }
+}
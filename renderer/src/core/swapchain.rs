@@ -0,0 +1,334 @@
+use ash::{khr, vk};
+
+use super::{Queue, Surface};
+
+/// Ordered preferences, the first entry supported by the surface wins
+#[derive(Debug, Clone)]
+pub struct SwapchainPreferences {
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    /// Intersected with what the surface supports, COLOR_ATTACHMENT is always requested
+    pub image_usage: vk::ImageUsageFlags,
+}
+
+impl Default for SwapchainPreferences {
+    fn default() -> Self {
+        Self {
+            formats: vec![
+                vk::SurfaceFormatKHR {
+                    format: vk::Format::B8G8R8A8_SRGB,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                },
+                vk::SurfaceFormatKHR {
+                    format: vk::Format::R8G8B8A8_SRGB,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                },
+            ],
+            present_modes: vec![vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        }
+    }
+}
+
+pub struct Swapchain {
+    loader: khr::swapchain::Device,
+    device: ash::Device,
+    gpu: vk::PhysicalDevice,
+    handle: vk::SwapchainKHR,
+    format: vk::SurfaceFormatKHR,
+    present_mode: vk::PresentModeKHR,
+    usage: vk::ImageUsageFlags,
+    /// Graphics family, plus the present one when they differ
+    queue_families: Vec<u32>,
+    extent: vk::Extent2D,
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    preferences: SwapchainPreferences,
+    out_of_date: bool,
+}
+
+impl Swapchain {
+    pub(in crate::core) fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        gpu: vk::PhysicalDevice,
+        surface: &Surface,
+        queue_families: &[u32],
+        extent: vk::Extent2D,
+        preferences: SwapchainPreferences,
+    ) -> Result<Self, vk::Result> {
+        let mut swapchain = Self {
+            loader: khr::swapchain::Device::new(instance, device),
+            device: device.clone(),
+            gpu,
+            handle: vk::SwapchainKHR::null(),
+            format: vk::SurfaceFormatKHR::default(),
+            present_mode: vk::PresentModeKHR::FIFO,
+            usage: vk::ImageUsageFlags::empty(),
+            queue_families: queue_families.to_vec(),
+            extent: vk::Extent2D::default(),
+            images: vec![],
+            views: vec![],
+            preferences,
+            out_of_date: false,
+        };
+        swapchain.recreate(surface, extent)?;
+        Ok(swapchain)
+    }
+
+    /// Rebuilds the swapchain for the new extent, returns false if the surface
+    /// has a zero extent (minimized window) and nothing was created
+    pub fn recreate(
+        &mut self,
+        surface: &Surface,
+        extent: vk::Extent2D,
+    ) -> Result<bool, vk::Result> {
+        let caps = surface.capabilities(self.gpu)?;
+        let extent = choose_extent(&caps, extent);
+
+        if extent.width == 0 || extent.height == 0 {
+            log::trace!("Surface has a zero extent, swapchain creation deferred");
+            self.extent = extent;
+            self.out_of_date = true;
+            return Ok(false);
+        }
+
+        let format = choose_format(&surface.formats(self.gpu)?, &self.preferences.formats);
+        let present_mode = choose_present_mode(
+            &surface.present_modes(self.gpu)?,
+            &self.preferences.present_modes,
+        );
+
+        let mut image_count = caps.min_image_count + 1;
+        if caps.max_image_count > 0 {
+            image_count = image_count.min(caps.max_image_count);
+        }
+
+        let usage = (self.preferences.image_usage | vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            & caps.supported_usage_flags;
+
+        let composite_alpha = if caps
+            .supported_composite_alpha
+            .contains(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        {
+            vk::CompositeAlphaFlagsKHR::OPAQUE
+        } else {
+            vk::CompositeAlphaFlagsKHR::INHERIT
+        };
+
+        let old = self.handle;
+        let mut create_info = vk::SwapchainCreateInfoKHR::default()
+            .surface(surface.handle())
+            .min_image_count(image_count)
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(usage)
+            .pre_transform(caps.current_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(present_mode)
+            .clipped(true)
+            .old_swapchain(old);
+        // concurrent sharing spares ownership transfers when presenting from another family
+        if self.queue_families.len() > 1 {
+            create_info = create_info
+                .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(&self.queue_families);
+        } else {
+            create_info = create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE);
+        }
+
+        let handle = unsafe { self.loader.create_swapchain(&create_info, None)? };
+        self.destroy_resources();
+        self.handle = handle;
+
+        self.images = unsafe { self.loader.get_swapchain_images(handle)? };
+        for image in &self.images {
+            let view_info = vk::ImageViewCreateInfo::default()
+                .image(*image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format.format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
+            let view = unsafe { self.device.create_image_view(&view_info, None)? };
+            self.views.push(view);
+        }
+
+        self.format = format;
+        self.present_mode = present_mode;
+        self.usage = usage;
+        self.extent = extent;
+        self.out_of_date = false;
+
+        log::trace!(
+            "Swapchain created: {}x{} {:?} {:?}, {} images",
+            extent.width,
+            extent.height,
+            format.format,
+            present_mode,
+            self.images.len()
+        );
+        Ok(true)
+    }
+
+    /// Returns None if the swapchain is out of date or minimized and has to be recreated first,
+    /// a suboptimal acquire still returns the image but flags the swapchain for recreation
+    pub fn acquire_next_image(
+        &mut self,
+        semaphore: vk::Semaphore,
+        timeout: u64,
+    ) -> Result<Option<u32>, vk::Result> {
+        if self.handle == vk::SwapchainKHR::null() || self.out_of_date {
+            return Ok(None);
+        }
+
+        let result = unsafe {
+            self.loader
+                .acquire_next_image(self.handle, timeout, semaphore, vk::Fence::null())
+        };
+        match result {
+            Ok((index, suboptimal)) => {
+                self.out_of_date |= suboptimal;
+                Ok(Some(index))
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Out of date and suboptimal results only flag the swapchain for recreation
+    pub fn present(
+        &mut self,
+        queue: &Queue,
+        index: u32,
+        wait: &[vk::Semaphore],
+    ) -> Result<(), vk::Result> {
+        let swapchains = [self.handle];
+        let indices = [index];
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(wait)
+            .swapchains(&swapchains)
+            .image_indices(&indices);
+
+        match queue.locked(|queue| unsafe { self.loader.queue_present(queue, &present_info) }) {
+            Ok(suboptimal) => {
+                self.out_of_date |= suboptimal;
+                Ok(())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn handle(&self) -> vk::SwapchainKHR {
+        self.handle
+    }
+
+    pub fn format(&self) -> vk::SurfaceFormatKHR {
+        self.format
+    }
+
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+
+    pub fn views(&self) -> &[vk::ImageView] {
+        &self.views
+    }
+
+    /// Set after an out of date/suboptimal result or a zero extent
+    pub fn needs_recreate(&self) -> bool {
+        self.out_of_date
+    }
+
+    pub fn mark_out_of_date(&mut self) {
+        self.out_of_date = true;
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.extent.width == 0 || self.extent.height == 0
+    }
+
+    fn destroy_resources(&mut self) {
+        for view in self.views.drain(..) {
+            unsafe { self.device.destroy_image_view(view, None) };
+        }
+        self.images.clear();
+        if self.handle != vk::SwapchainKHR::null() {
+            unsafe { self.loader.destroy_swapchain(self.handle, None) };
+            self.handle = vk::SwapchainKHR::null();
+        }
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        log::trace!("Destroying swapchain");
+        self.destroy_resources();
+    }
+}
+
+fn choose_extent(caps: &vk::SurfaceCapabilitiesKHR, desired: vk::Extent2D) -> vk::Extent2D {
+    // u32::MAX means the surface size is determined by the swapchain
+    if caps.current_extent.width != u32::MAX {
+        return caps.current_extent;
+    }
+    vk::Extent2D {
+        width: desired
+            .width
+            .clamp(caps.min_image_extent.width, caps.max_image_extent.width),
+        height: desired
+            .height
+            .clamp(caps.min_image_extent.height, caps.max_image_extent.height),
+    }
+}
+
+fn choose_format(
+    available: &[vk::SurfaceFormatKHR],
+    preferred: &[vk::SurfaceFormatKHR],
+) -> vk::SurfaceFormatKHR {
+    preferred
+        .iter()
+        .find(|format| available.contains(format))
+        .or_else(|| available.first())
+        .copied()
+        .unwrap_or_default()
+}
+
+fn choose_present_mode(
+    available: &[vk::PresentModeKHR],
+    preferred: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    preferred
+        .iter()
+        .find(|mode| available.contains(mode))
+        .copied()
+        // FIFO is the only mode the spec guarantees
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
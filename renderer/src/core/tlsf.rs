@@ -0,0 +1,656 @@
+//! Two level segregated fit placement over a linear range, knows nothing about vulkan so the
+//! memory allocator can hand it offsets and sizes of a `vk::DeviceMemory` block
+
+/// log2 of the second level subdivisions per power of two
+const SL_BITS: u32 = 5;
+const SL_COUNT: usize = 1 << SL_BITS;
+const FL_COUNT: usize = 64 - SL_BITS as usize + 1;
+
+/// What lives in a range, linear and optimal resources must not share a
+/// `bufferImageGranularity` page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Buffers and linear tiling images
+    Linear,
+    /// Optimal tiling images
+    Optimal,
+}
+
+/// Range handed out by `Tlsf::allocate`, give it back with `Tlsf::free`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsfAllocation {
+    pub offset: u64,
+    pub size: u64,
+    id: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Free,
+    Used(ResourceKind),
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    offset: u64,
+    size: u64,
+    state: State,
+    prev_phys: Option<usize>,
+    next_phys: Option<usize>,
+    prev_free: Option<usize>,
+    next_free: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Tlsf {
+    size: u64,
+    granularity: u64,
+    /// Block arena, `unused` holds the recycled slots
+    blocks: Vec<Block>,
+    unused: Vec<usize>,
+    fl_bitmap: u64,
+    sl_bitmap: [u32; FL_COUNT],
+    heads: Vec<Option<usize>>,
+    allocated: u64,
+    allocations: usize,
+}
+
+impl Tlsf {
+    /// `granularity` is the `bufferImageGranularity` limit, 1 disables the page checks
+    pub fn new(size: u64, granularity: u64) -> Self {
+        assert!(size > 0, "Empty range");
+        assert!(
+            granularity.is_power_of_two(),
+            "Granularity must be a power of two"
+        );
+
+        let mut tlsf = Self {
+            size,
+            granularity,
+            blocks: vec![],
+            unused: vec![],
+            fl_bitmap: 0,
+            sl_bitmap: [0; FL_COUNT],
+            heads: vec![None; FL_COUNT * SL_COUNT],
+            allocated: 0,
+            allocations: 0,
+        };
+        let block = tlsf.new_block(Block {
+            offset: 0,
+            size,
+            state: State::Free,
+            prev_phys: None,
+            next_phys: None,
+            prev_free: None,
+            next_free: None,
+        });
+        tlsf.insert_free(block);
+        tlsf
+    }
+
+    /// Finds room for `size` bytes aligned to `alignment`, None if the range is too full
+    pub fn allocate(
+        &mut self,
+        size: u64,
+        alignment: u64,
+        kind: ResourceKind,
+    ) -> Option<TlsfAllocation> {
+        assert!(size > 0, "Zero sized allocation");
+        assert!(
+            alignment.is_power_of_two(),
+            "Alignment must be a power of two"
+        );
+        if size > self.size {
+            return None;
+        }
+
+        // good fit on the bin heads first, the padded search skips blocks that can't be aligned
+        let mut searches = vec![size];
+        if alignment > 1 {
+            searches.push(size + alignment - 1);
+        }
+
+        for search in searches {
+            if search > self.size {
+                break;
+            }
+            let Some(block) = self.find_suitable(search) else {
+                continue;
+            };
+            if let Some(offset) = self.place(block, size, alignment, kind) {
+                return Some(self.take(block, offset, size, kind));
+            }
+        }
+
+        // the rounding skips the bin of the request itself and granularity conflicts can reject
+        // the heads, fall back to a first fit over every bin that may hold a big enough block
+        let (fl, sl) = mapping(size);
+        for bin in fl * SL_COUNT + sl..self.heads.len() {
+            let mut block = self.heads[bin];
+            while let Some(id) = block {
+                if let Some(offset) = self.place(id, size, alignment, kind) {
+                    return Some(self.take(id, offset, size, kind));
+                }
+                block = self.blocks[id].next_free;
+            }
+        }
+        None
+    }
+
+    pub fn free(&mut self, allocation: TlsfAllocation) {
+        let mut id = allocation.id;
+        let block = &self.blocks[id];
+        assert!(
+            matches!(block.state, State::Used(_)) && block.offset == allocation.offset,
+            "Freeing an allocation that isn't live"
+        );
+
+        self.allocated -= block.size;
+        self.allocations -= 1;
+        self.blocks[id].state = State::Free;
+
+        if let Some(prev) = self.blocks[id].prev_phys {
+            if self.blocks[prev].state == State::Free {
+                self.remove_free(prev);
+                id = self.merge(prev, id);
+            }
+        }
+        if let Some(next) = self.blocks[id].next_phys {
+            if self.blocks[next].state == State::Free {
+                self.remove_free(next);
+                id = self.merge(id, next);
+            }
+        }
+        self.insert_free(id);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Bytes handed out, alignment padding excluded
+    pub fn allocated(&self) -> u64 {
+        self.allocated
+    }
+
+    pub fn allocation_count(&self) -> usize {
+        self.allocations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations == 0
+    }
+
+    pub fn largest_free(&self) -> u64 {
+        let Some(fl) = (u64::BITS - self.fl_bitmap.leading_zeros()).checked_sub(1) else {
+            return 0;
+        };
+        let sl = u32::BITS - self.sl_bitmap[fl as usize].leading_zeros() - 1;
+
+        let mut largest = 0;
+        let mut block = self.heads[fl as usize * SL_COUNT + sl as usize];
+        while let Some(id) = block {
+            largest = largest.max(self.blocks[id].size);
+            block = self.blocks[id].next_free;
+        }
+        largest
+    }
+
+    /// Offset inside `block` where the allocation fits, honoring alignment and granularity
+    fn place(&self, id: usize, size: u64, alignment: u64, kind: ResourceKind) -> Option<u64> {
+        let block = &self.blocks[id];
+        let end = block.offset + block.size;
+
+        let mut offset = align_up(block.offset, alignment);
+        if self.conflicts_before(id, offset, kind) {
+            offset = align_up(offset, self.granularity);
+        }
+        if offset + size > end || self.conflicts_after(id, offset + size, kind) {
+            return None;
+        }
+        Some(offset)
+    }
+
+    /// A different kind of resource ends on the page `offset` starts on
+    fn conflicts_before(&self, id: usize, offset: u64, kind: ResourceKind) -> bool {
+        if self.granularity == 1 {
+            return false;
+        }
+        let page = align_down(offset, self.granularity);
+        let mut prev = self.blocks[id].prev_phys;
+        while let Some(p) = prev {
+            let block = &self.blocks[p];
+            if block.offset + block.size <= page {
+                break;
+            }
+            if matches!(block.state, State::Used(other) if other != kind) {
+                return true;
+            }
+            prev = block.prev_phys;
+        }
+        false
+    }
+
+    /// A different kind of resource starts on the page the byte before `end` is on
+    fn conflicts_after(&self, id: usize, end: u64, kind: ResourceKind) -> bool {
+        if self.granularity == 1 {
+            return false;
+        }
+        let next_page = align_down(end - 1, self.granularity) + self.granularity;
+        let mut next = self.blocks[id].next_phys;
+        while let Some(n) = next {
+            let block = &self.blocks[n];
+            if block.offset >= next_page {
+                break;
+            }
+            if matches!(block.state, State::Used(other) if other != kind) {
+                return true;
+            }
+            next = block.next_phys;
+        }
+        false
+    }
+
+    /// Marks [offset, offset + size) of the free block as used, the leftovers become free blocks
+    fn take(&mut self, id: usize, offset: u64, size: u64, kind: ResourceKind) -> TlsfAllocation {
+        self.remove_free(id);
+
+        let front = offset - self.blocks[id].offset;
+        if front > 0 {
+            let prev_phys = self.blocks[id].prev_phys;
+            let padding = self.new_block(Block {
+                offset: self.blocks[id].offset,
+                size: front,
+                state: State::Free,
+                prev_phys,
+                next_phys: Some(id),
+                prev_free: None,
+                next_free: None,
+            });
+            if let Some(prev) = prev_phys {
+                self.blocks[prev].next_phys = Some(padding);
+            }
+            self.blocks[id].prev_phys = Some(padding);
+            self.blocks[id].offset = offset;
+            self.blocks[id].size -= front;
+            self.insert_free(padding);
+        }
+
+        let tail = self.blocks[id].size - size;
+        if tail > 0 {
+            let next_phys = self.blocks[id].next_phys;
+            let rest = self.new_block(Block {
+                offset: offset + size,
+                size: tail,
+                state: State::Free,
+                prev_phys: Some(id),
+                next_phys,
+                prev_free: None,
+                next_free: None,
+            });
+            if let Some(next) = next_phys {
+                self.blocks[next].prev_phys = Some(rest);
+            }
+            self.blocks[id].next_phys = Some(rest);
+            self.blocks[id].size = size;
+            self.insert_free(rest);
+        }
+
+        self.blocks[id].state = State::Used(kind);
+        self.allocated += size;
+        self.allocations += 1;
+        TlsfAllocation { offset, size, id }
+    }
+
+    /// Folds `second` into `first`, they must be physical neighbours and out of the free lists
+    fn merge(&mut self, first: usize, second: usize) -> usize {
+        let next_phys = self.blocks[second].next_phys;
+        self.blocks[first].size += self.blocks[second].size;
+        self.blocks[first].next_phys = next_phys;
+        if let Some(next) = next_phys {
+            self.blocks[next].prev_phys = Some(first);
+        }
+        self.unused.push(second);
+        first
+    }
+
+    fn find_suitable(&self, size: u64) -> Option<usize> {
+        let (mut fl, sl) = mapping(round_up_to_bin(size));
+        if fl >= FL_COUNT {
+            return None;
+        }
+
+        let mut sl_map = self.sl_bitmap[fl] & (u32::MAX << sl);
+        if sl_map == 0 {
+            let fl_map = match fl + 1 {
+                64.. => 0,
+                next => self.fl_bitmap & (u64::MAX << next),
+            };
+            if fl_map == 0 {
+                return None;
+            }
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmap[fl];
+        }
+        let sl = sl_map.trailing_zeros() as usize;
+        self.heads[fl * SL_COUNT + sl]
+    }
+
+    fn insert_free(&mut self, id: usize) {
+        let (fl, sl) = mapping(self.blocks[id].size);
+        let head = self.heads[fl * SL_COUNT + sl];
+
+        self.blocks[id].prev_free = None;
+        self.blocks[id].next_free = head;
+        if let Some(head) = head {
+            self.blocks[head].prev_free = Some(id);
+        }
+        self.heads[fl * SL_COUNT + sl] = Some(id);
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    fn remove_free(&mut self, id: usize) {
+        let (fl, sl) = mapping(self.blocks[id].size);
+        let (prev, next) = (self.blocks[id].prev_free, self.blocks[id].next_free);
+
+        match prev {
+            Some(prev) => self.blocks[prev].next_free = next,
+            None => self.heads[fl * SL_COUNT + sl] = next,
+        }
+        if let Some(next) = next {
+            self.blocks[next].prev_free = prev;
+        }
+        self.blocks[id].prev_free = None;
+        self.blocks[id].next_free = None;
+
+        if self.heads[fl * SL_COUNT + sl].is_none() {
+            self.sl_bitmap[fl] &= !(1 << sl);
+            if self.sl_bitmap[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+    }
+
+    fn new_block(&mut self, block: Block) -> usize {
+        match self.unused.pop() {
+            Some(id) => {
+                self.blocks[id] = block;
+                id
+            }
+            None => {
+                self.blocks.push(block);
+                self.blocks.len() - 1
+            }
+        }
+    }
+}
+
+/// First and second level bin of a size, sizes below SL_COUNT get a bin each
+fn mapping(size: u64) -> (usize, usize) {
+    if size < SL_COUNT as u64 {
+        return (0, size as usize);
+    }
+    let fl = 63 - size.leading_zeros();
+    let sl = (size >> (fl - SL_BITS)) as usize ^ SL_COUNT;
+    ((fl - SL_BITS + 1) as usize, sl)
+}
+
+/// Rounds up to the smallest size of the next bin so any block found there is big enough
+fn round_up_to_bin(size: u64) -> u64 {
+    if size < SL_COUNT as u64 {
+        return size;
+    }
+    let fl = 63 - size.leading_zeros();
+    let step = 1u64 << (fl - SL_BITS);
+    size.saturating_add(step - 1) & !(step - 1)
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn align_down(value: u64, alignment: u64) -> u64 {
+    value & !(alignment - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_block(tlsf: &Tlsf) -> usize {
+        // merged away blocks always sit after their neighbour, offset 0 is the live first one
+        tlsf.blocks
+            .iter()
+            .position(|block| block.offset == 0 && block.prev_phys.is_none())
+            .unwrap()
+    }
+
+    /// Walks the physical list checking coverage, no adjacent free blocks and the counters
+    fn check_invariants(tlsf: &Tlsf) {
+        let mut offset = 0;
+        let mut allocated = 0;
+        let mut count = 0;
+        let mut prev_free = false;
+        let mut prev = None;
+        let mut block = Some(first_block(tlsf));
+        while let Some(id) = block {
+            let b = &tlsf.blocks[id];
+            assert_eq!(b.offset, offset, "hole or overlap at {}", offset);
+            assert_eq!(b.prev_phys, prev);
+            assert!(b.size > 0);
+            let free = b.state == State::Free;
+            assert!(!(free && prev_free), "adjacent free blocks at {}", b.offset);
+            if !free {
+                allocated += b.size;
+                count += 1;
+            }
+            prev_free = free;
+            offset += b.size;
+            prev = Some(id);
+            block = b.next_phys;
+        }
+        assert_eq!(offset, tlsf.size());
+        assert_eq!(allocated, tlsf.allocated());
+        assert_eq!(count, tlsf.allocation_count());
+    }
+
+    /// Tiny xorshift so the stress test is deterministic without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn range(&mut self, min: u64, max: u64) -> u64 {
+            min + self.next() % (max - min + 1)
+        }
+    }
+
+    #[test]
+    fn mapping_is_monotonic() {
+        let mut last = (0, 0);
+        for size in 1..100_000u64 {
+            let bin = mapping(size);
+            assert!(bin >= last, "size {} went back to bin {:?}", size, bin);
+            last = bin;
+        }
+    }
+
+    #[test]
+    fn rounded_sizes_fit_every_block_of_their_bin() {
+        for size in 1..100_000u64 {
+            let rounded = round_up_to_bin(size);
+            assert!(rounded >= size);
+            // the smallest size mapping to the same bin is the rounded one itself
+            let bin = mapping(rounded);
+            assert!(rounded == 1 || mapping(rounded - 1) < bin);
+        }
+    }
+
+    #[test]
+    fn fills_the_whole_range() {
+        let mut tlsf = Tlsf::new(1024, 1);
+        let allocations: Vec<_> = (0..16)
+            .map(|_| tlsf.allocate(64, 1, ResourceKind::Linear).unwrap())
+            .collect();
+        assert!(tlsf.allocate(1, 1, ResourceKind::Linear).is_none());
+        assert_eq!(tlsf.allocated(), 1024);
+        check_invariants(&tlsf);
+
+        for allocation in allocations {
+            tlsf.free(allocation);
+        }
+        check_invariants(&tlsf);
+        assert!(tlsf.is_empty());
+        assert_eq!(tlsf.largest_free(), 1024);
+    }
+
+    #[test]
+    fn respects_alignment() {
+        let mut tlsf = Tlsf::new(1 << 20, 1);
+        tlsf.allocate(3, 1, ResourceKind::Linear).unwrap();
+        for alignment in [4, 16, 256, 4096, 65536] {
+            let allocation = tlsf.allocate(100, alignment, ResourceKind::Linear).unwrap();
+            assert_eq!(allocation.offset % alignment, 0);
+        }
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn uses_an_exactly_sized_hole() {
+        let mut tlsf = Tlsf::new(300, 1);
+        let a = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        let _b = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        let _c = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        tlsf.free(a);
+
+        let reused = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        assert_eq!(reused.offset, 0);
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn merges_neighbours_on_free() {
+        let mut tlsf = Tlsf::new(300, 1);
+        let a = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        let b = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        let c = tlsf.allocate(100, 1, ResourceKind::Linear).unwrap();
+        tlsf.free(a);
+        tlsf.free(c);
+        assert_eq!(tlsf.largest_free(), 100);
+        tlsf.free(b);
+        assert_eq!(tlsf.largest_free(), 300);
+        assert!(tlsf.allocate(300, 1, ResourceKind::Linear).is_some());
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn separates_resource_kinds_by_granularity() {
+        let mut tlsf = Tlsf::new(8192, 1024);
+        let buffer = tlsf.allocate(100, 4, ResourceKind::Linear).unwrap();
+        let image = tlsf.allocate(100, 4, ResourceKind::Optimal).unwrap();
+        assert_eq!(buffer.offset, 0);
+        assert_eq!(image.offset, 1024);
+
+        // same kind shares the page
+        let other = tlsf.allocate(100, 4, ResourceKind::Linear).unwrap();
+        assert_eq!(other.offset, 100);
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn skips_free_ranges_sharing_a_page_with_another_kind() {
+        let mut tlsf = Tlsf::new(2048, 1024);
+        let image = tlsf.allocate(24, 1, ResourceKind::Optimal).unwrap();
+        assert_eq!(image.offset, 0);
+        let buffer = tlsf.allocate(1000, 1, ResourceKind::Linear).unwrap();
+        assert_eq!(buffer.offset, 1024);
+        // 1000 bytes are free right after the image, but on its page
+        assert!(tlsf.allocate(1000, 1, ResourceKind::Linear).is_none());
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn avoids_ending_on_the_page_of_the_next_resource() {
+        let mut tlsf = Tlsf::new(4096, 1024);
+        let first = tlsf.allocate(1500, 1, ResourceKind::Optimal).unwrap();
+        let image = tlsf.allocate(100, 1, ResourceKind::Optimal).unwrap();
+        assert_eq!(image.offset, 1500);
+        tlsf.free(first);
+
+        // would end on the page the image starts on
+        let buffer = tlsf.allocate(1200, 1, ResourceKind::Linear).unwrap();
+        assert_eq!(buffer.offset, 2048);
+        // ends on the page before
+        let buffer = tlsf.allocate(1000, 1, ResourceKind::Linear).unwrap();
+        assert_eq!(buffer.offset, 0);
+        check_invariants(&tlsf);
+    }
+
+    #[test]
+    fn too_big_allocations_fail() {
+        let mut tlsf = Tlsf::new(1000, 1);
+        assert!(tlsf.allocate(1001, 1, ResourceKind::Linear).is_none());
+        assert!(tlsf.allocate(1000, 1, ResourceKind::Linear).is_some());
+    }
+
+    #[test]
+    fn random_allocations_never_overlap() {
+        const SIZE: u64 = 1 << 24;
+        const GRANULARITY: u64 = 1024;
+        let mut tlsf = Tlsf::new(SIZE, GRANULARITY);
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut live: Vec<(TlsfAllocation, u64, ResourceKind)> = vec![];
+
+        for _ in 0..20_000 {
+            if live.is_empty() || rng.range(0, 99) < 60 {
+                let max = 1 << rng.range(4, 18);
+                let size = rng.range(1, max);
+                let alignment = 1 << rng.range(0, 12);
+                let kind = if rng.range(0, 1) == 0 {
+                    ResourceKind::Linear
+                } else {
+                    ResourceKind::Optimal
+                };
+                if let Some(allocation) = tlsf.allocate(size, alignment, kind) {
+                    assert_eq!(allocation.offset % alignment, 0);
+                    assert_eq!(allocation.size, size);
+                    live.push((allocation, alignment, kind));
+                }
+            } else {
+                let index = rng.range(0, live.len() as u64 - 1) as usize;
+                let (allocation, _, _) = live.swap_remove(index);
+                tlsf.free(allocation);
+            }
+        }
+        check_invariants(&tlsf);
+
+        live.sort_by_key(|(allocation, _, _)| allocation.offset);
+        for pair in live.windows(2) {
+            let (a, _, a_kind) = pair[0];
+            let (b, _, b_kind) = pair[1];
+            assert!(a.offset + a.size <= b.offset, "{:?} overlaps {:?}", a, b);
+            if a_kind != b_kind {
+                let a_last_page = align_down(a.offset + a.size - 1, GRANULARITY);
+                let b_first_page = align_down(b.offset, GRANULARITY);
+                assert!(
+                    a_last_page < b_first_page,
+                    "{:?} shares a page with {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+
+        for (allocation, _, _) in live {
+            tlsf.free(allocation);
+        }
+        check_invariants(&tlsf);
+        assert_eq!(tlsf.largest_free(), SIZE);
+    }
+}
@@ -0,0 +1,1023 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Range,
+};
+
+use ash::vk;
+
+use super::{layer_size, mip_extent, Buffer, BufferDesc, Device, Image, OwnershipTransfer};
+use crate::RendererError;
+
+/// Handle to a staged copy, poll it or wait on it through the renderer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadTicket(u64);
+
+/// Destination of an image upload, the image ends up in `new_layout`
+#[derive(Debug, Clone, Copy)]
+pub struct ImageUpload {
+    pub image: vk::Image,
+    pub subresource: vk::ImageSubresourceLayers,
+    pub offset: vk::Offset3D,
+    pub extent: vk::Extent3D,
+    /// Layout before the copy, UNDEFINED discards the previous contents
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+/// Mip chain blitted from level 0 on the graphics queue, level 0 must be in
+/// TRANSFER_DST_OPTIMAL and the format must support linear blits
+#[derive(Debug, Clone, Copy)]
+pub struct MipGeneration {
+    pub image: vk::Image,
+    pub extent: vk::Extent2D,
+    pub aspect: vk::ImageAspectFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    /// Layout of every level once done
+    pub new_layout: vk::ImageLayout,
+}
+
+enum Copy {
+    Buffer {
+        dst: vk::Buffer,
+        region: vk::BufferCopy,
+        /// Overwrites the whole buffer, nothing to keep from the previous owner
+        whole: bool,
+    },
+    Image {
+        upload: ImageUpload,
+        buffer_offset: vk::DeviceSize,
+    },
+    Mips(MipGeneration),
+}
+
+struct PendingCopy {
+    src: vk::Buffer,
+    copy: Copy,
+}
+
+/// What a copy writes to, images are tracked per mip level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Destination {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+impl PendingCopy {
+    /// Written resource and its mip levels, buffers have a single level
+    fn destination(&self) -> (Destination, Range<u32>) {
+        match &self.copy {
+            Copy::Buffer { dst, .. } => (Destination::Buffer(*dst), 0..1),
+            Copy::Image { upload, .. } => {
+                let level = upload.subresource.mip_level;
+                (Destination::Image(upload.image), level..level + 1)
+            }
+            Copy::Mips(mips) => (Destination::Image(mips.image), 0..mips.mip_levels),
+        }
+    }
+}
+
+/// Where a batch of copies was submitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submission {
+    /// Recorded before the frame with this number, done once the frame is
+    Frame(u64),
+    /// Already waited on
+    Done,
+}
+
+struct Batch {
+    /// Tickets below this one belong to this batch or an older one
+    end_ticket: u64,
+    submission: Submission,
+    /// Ring bytes to give back and where the ring tail moves once the batch is done
+    ring_bytes: vk::DeviceSize,
+    ring_end: vk::DeviceSize,
+}
+
+/// Space of the circular staging buffer, reclaimed in submission order
+struct StagingRing {
+    capacity: vk::DeviceSize,
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+    /// Bytes between tail and head, wrap waste included
+    used: vk::DeviceSize,
+}
+
+impl StagingRing {
+    fn new(capacity: vk::DeviceSize) -> Self {
+        Self {
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+        }
+    }
+
+    /// Offset of `size` free bytes aligned to `alignment` and the bytes it consumes
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+        let capacity = self.capacity;
+        if self.used == 0 {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let start = align_up(self.head, alignment);
+        let (offset, consumed) = if self.used == 0 || self.tail < self.head {
+            if start + size <= capacity {
+                (start, start + size - self.head)
+            } else if size <= self.tail {
+                // wrap around, the end of the buffer is wasted until the tail passes it
+                (0, capacity - self.head + size)
+            } else {
+                return None;
+            }
+        } else if start + size <= self.tail {
+            (start, start + size - self.head)
+        } else {
+            return None;
+        };
+
+        self.head = offset + size;
+        self.used += consumed;
+        Some((offset, consumed))
+    }
+
+    /// Gives back the oldest `bytes` allocated, `end` is where the head was after them
+    fn release(&mut self, bytes: vk::DeviceSize, end: vk::DeviceSize) {
+        self.used -= bytes;
+        self.tail = end;
+    }
+}
+
+/// Checks the region lies inside `image` and `len` bytes cover it tightly packed
+fn validate_image_upload(
+    image: &Image,
+    upload: &ImageUpload,
+    len: usize,
+) -> Result<(), RendererError> {
+    if upload.image != image.handle() {
+        return Err(RendererError::InvalidUsage(
+            "image upload targets a different image".to_owned(),
+        ));
+    }
+    if !image.usage().contains(vk::ImageUsageFlags::TRANSFER_DST) {
+        return Err(RendererError::InvalidUsage(
+            "upload destination lacks TRANSFER_DST usage".to_owned(),
+        ));
+    }
+
+    let subresource = upload.subresource;
+    let layers_end = subresource
+        .base_array_layer
+        .checked_add(subresource.layer_count);
+    if subresource.mip_level >= image.mip_levels()
+        || subresource.layer_count == 0
+        || layers_end.is_none_or(|end| end > image.array_layers())
+    {
+        return Err(RendererError::OutOfRange(format!(
+            "mip {} layers {}..+{} are outside an image with {} mips and {} layers",
+            subresource.mip_level,
+            subresource.base_array_layer,
+            subresource.layer_count,
+            image.mip_levels(),
+            image.array_layers()
+        )));
+    }
+
+    let level = mip_extent(image.extent(), subresource.mip_level);
+    let fits = |offset: i32, size: u32, limit: u32| {
+        u32::try_from(offset)
+            .ok()
+            .and_then(|offset| offset.checked_add(size))
+            .is_some_and(|end| size > 0 && end <= limit)
+    };
+    let (offset, extent) = (upload.offset, upload.extent);
+    if !fits(offset.x, extent.width, level.width)
+        || !fits(offset.y, extent.height, level.height)
+        || !fits(offset.z, extent.depth, 1)
+    {
+        return Err(RendererError::OutOfRange(format!(
+            "region {:?} {:?} is outside mip {} of {}x{}",
+            offset, extent, subresource.mip_level, level.width, level.height
+        )));
+    }
+
+    let region = vk::Extent2D {
+        width: extent.width,
+        height: extent.height,
+    };
+    let expected = layer_size(image.format(), region)
+        .ok_or(RendererError::UnsupportedFormat(image.format()))?
+        .checked_mul(u64::from(subresource.layer_count));
+    if expected != Some(len as u64) {
+        return Err(RendererError::InvalidUsage(format!(
+            "{} bytes of texels don't match a {}x{} region of {} layers",
+            len, extent.width, extent.height, subresource.layer_count
+        )));
+    }
+    Ok(())
+}
+
+/// Records and submits one-off command buffers, blocking until they are done
+struct Immediate {
+    pool: vk::CommandPool,
+    buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+/// Copies run on the dedicated transfer queue, the graphics queue waits on `semaphore`
+#[derive(Clone, Copy)]
+struct TransferSubmit {
+    buffer: vk::CommandBuffer,
+    semaphore: vk::Semaphore,
+}
+
+/// Copies that don't need the previous contents of their destination go through the
+/// dedicated transfer queue, ownership is then released to the graphics family
+struct TransferPath {
+    pool: vk::CommandPool,
+    /// One per frame slot, reused once the frame on the slot is done
+    frames: Vec<TransferSubmit>,
+    immediate: TransferSubmit,
+    /// Partial image copies would have to honor the queue transfer granularity
+    images: bool,
+}
+
+/// Batches copies from host memory into gpu only buffers and images through a staging ring.
+/// Pending copies are recorded before the next frame or flushed right away with `flush_immediate`
+/// Copies overwriting their whole destination run on the dedicated transfer queue if any,
+/// as long as it is the first upload to it: nothing may use a resource on the graphics
+/// queue before its first upload, later ones stay in order with the frames using it
+pub struct UploadManager {
+    staging: Buffer,
+    ring: StagingRing,
+    /// Staging buffers for copies bigger than the ring, retired once flushed
+    oversized: Vec<Buffer>,
+    pending: Vec<PendingCopy>,
+    pending_ring_bytes: vk::DeviceSize,
+    batches: VecDeque<Batch>,
+    next_ticket: u64,
+    /// Tickets below this one were submitted
+    flushed: u64,
+    /// Tickets below this one are done
+    completed: u64,
+    immediate: Immediate,
+    transfer: Option<TransferPath>,
+    /// Destinations of the flushed copies, they may be in use on the graphics queue
+    uploaded: HashSet<Destination>,
+}
+
+impl UploadManager {
+    pub(crate) fn new(
+        device: &Device,
+        ring_size: vk::DeviceSize,
+        frames_in_flight: usize,
+    ) -> Result<Self, vk::Result> {
+        let staging = device.create_buffer(&BufferDesc::staging(ring_size.max(1)))?;
+        let pool = device.create_command_pool(
+            vk::CommandPoolCreateFlags::TRANSIENT
+                | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+        )?;
+        let immediate = Immediate {
+            pool,
+            buffer: device.allocate_command_buffer(pool, vk::CommandBufferLevel::PRIMARY)?,
+            fence: device.create_fence(vk::FenceCreateFlags::empty())?,
+        };
+
+        let transfer = match device.dedicated_transfer_queue() {
+            Some(queue) => {
+                let pool = device.create_command_pool_for(
+                    queue.family(),
+                    vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                )?;
+                let submit = || -> Result<TransferSubmit, vk::Result> {
+                    Ok(TransferSubmit {
+                        buffer: device
+                            .allocate_command_buffer(pool, vk::CommandBufferLevel::PRIMARY)?,
+                        semaphore: device.create_semaphore(vk::SemaphoreCreateFlags::empty())?,
+                    })
+                };
+                let frames = (0..frames_in_flight)
+                    .map(|_| submit())
+                    .collect::<Result<_, _>>()?;
+                log::info!("Uploads use the transfer queue family {}", queue.family());
+                Some(TransferPath {
+                    pool,
+                    frames,
+                    immediate: submit()?,
+                    images: queue.min_image_transfer_granularity()
+                        == (vk::Extent3D {
+                            width: 1,
+                            height: 1,
+                            depth: 1,
+                        }),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            ring: StagingRing::new(staging.size()),
+            staging,
+            oversized: vec![],
+            pending: vec![],
+            pending_ring_bytes: 0,
+            batches: VecDeque::new(),
+            next_ticket: 0,
+            flushed: 0,
+            completed: 0,
+            immediate,
+            transfer,
+            uploaded: HashSet::new(),
+        })
+    }
+
+    /// Copies `data` into staging memory, returns the buffer and offset it landed at
+    fn stage(
+        &mut self,
+        device: &Device,
+        data: &[u8],
+        alignment: vk::DeviceSize,
+    ) -> Result<(vk::Buffer, vk::DeviceSize), RendererError> {
+        let size = data.len() as vk::DeviceSize;
+        if let Some((offset, consumed)) = self.ring.allocate(size, alignment) {
+            self.pending_ring_bytes += consumed;
+            self.staging.write_slice(offset, data)?;
+            return Ok((self.staging.handle(), offset));
+        }
+
+        log::trace!(
+            "Staging ring full or too small, using a dedicated {} bytes staging buffer",
+            size
+        );
+        let mut buffer = device.create_buffer(&BufferDesc::staging(size))?;
+        if let Err(err) = buffer.write_slice(0, data) {
+            device.destroy_buffer(buffer);
+            return Err(err);
+        }
+        let handle = buffer.handle();
+        self.oversized.push(buffer);
+        Ok((handle, 0))
+    }
+
+    fn ticket(&mut self) -> UploadTicket {
+        let ticket = UploadTicket(self.next_ticket);
+        self.next_ticket += 1;
+        ticket
+    }
+
+    /// Stages a copy of `data` to `dst` at `offset` bytes, `dst` needs TRANSFER_DST usage
+    pub fn upload_buffer(
+        &mut self,
+        device: &Device,
+        dst: &Buffer,
+        offset: vk::DeviceSize,
+        data: &[u8],
+    ) -> Result<UploadTicket, RendererError> {
+        if data.is_empty() {
+            return Err(RendererError::InvalidUsage(
+                "empty buffer upload".to_owned(),
+            ));
+        }
+        if !dst.usage().contains(vk::BufferUsageFlags::TRANSFER_DST) {
+            return Err(RendererError::InvalidUsage(
+                "upload destination lacks TRANSFER_DST usage".to_owned(),
+            ));
+        }
+        let end = offset.checked_add(data.len() as vk::DeviceSize);
+        if end.is_none_or(|end| end > dst.size()) {
+            return Err(RendererError::OutOfRange(format!(
+                "upload of {} bytes at {} overflows a {} bytes buffer",
+                data.len(),
+                offset,
+                dst.size()
+            )));
+        }
+
+        let (src, src_offset) = self.stage(device, data, 4)?;
+        self.pending.push(PendingCopy {
+            src,
+            copy: Copy::Buffer {
+                dst: dst.handle(),
+                region: vk::BufferCopy {
+                    src_offset,
+                    dst_offset: offset,
+                    size: data.len() as vk::DeviceSize,
+                },
+                whole: offset == 0 && data.len() as vk::DeviceSize == dst.size(),
+            },
+        });
+        Ok(self.ticket())
+    }
+
+    /// Stages tightly packed texels for a region of `image`, the one `upload` targets,
+    /// which needs TRANSFER_DST usage
+    pub fn upload_image(
+        &mut self,
+        device: &Device,
+        image: &Image,
+        upload: ImageUpload,
+        data: &[u8],
+    ) -> Result<UploadTicket, RendererError> {
+        validate_image_upload(image, &upload, data.len())?;
+        // multiple of every texel size the renderer uses and of the 4 bytes the spec requires
+        let (src, buffer_offset) = self.stage(device, data, 16)?;
+        self.pending.push(PendingCopy {
+            src,
+            copy: Copy::Image {
+                upload,
+                buffer_offset,
+            },
+        });
+        Ok(self.ticket())
+    }
+
+    /// Generates the mips after the copies staged so far, the image needs TRANSFER_SRC usage
+    pub fn generate_mips(&mut self, mips: MipGeneration) -> UploadTicket {
+        self.pending.push(PendingCopy {
+            src: vk::Buffer::null(),
+            copy: Copy::Mips(mips),
+        });
+        self.ticket()
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Records the pending copies before the frame with this number, returns the semaphore
+    /// the frame submission has to wait on when some of them went to the transfer queue
+    pub fn record_frame(
+        &mut self,
+        device: &Device,
+        slot: usize,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+    ) -> Result<Option<vk::Semaphore>, RendererError> {
+        let transfer = self.transfer.as_ref().map(|path| path.frames[slot]);
+        let waits = self.record(device, cmd, transfer, Submission::Frame(frame))?;
+        Ok(transfer.filter(|_| waits).map(|submit| submit.semaphore))
+    }
+
+    /// Records every pending copy followed by a barrier making them visible to any later
+    /// command on the graphics queue. Copies moved to `transfer` get submitted right away,
+    /// true if they did and `cmd` must wait on its semaphore
+    fn record(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        transfer: Option<TransferSubmit>,
+        submission: Submission,
+    ) -> Result<bool, RendererError> {
+        let handle = device.handle();
+        let pending = std::mem::take(&mut self.pending);
+        // levels written more than once keep their copies inline and in order
+        let mut writes: HashMap<(Destination, u32), usize> = HashMap::new();
+        for copy in &pending {
+            let (destination, levels) = copy.destination();
+            for level in levels {
+                *writes.entry((destination, level)).or_default() += 1;
+            }
+        }
+        let (background, inline): (Vec<_>, Vec<_>) = match transfer {
+            Some(_) => pending.into_iter().partition(|pending| {
+                let (destination, levels) = pending.destination();
+                self.is_background(pending)
+                    && !self.uploaded.contains(&destination)
+                    && levels
+                        .into_iter()
+                        .all(|level| writes[&(destination, level)] == 1)
+            }),
+            None => (vec![], pending),
+        };
+        self.uploaded.extend(
+            background
+                .iter()
+                .chain(&inline)
+                .map(|copy| copy.destination().0),
+        );
+
+        let waits = match transfer {
+            Some(transfer) if !background.is_empty() => {
+                self.submit_background(device, cmd, transfer, &background)?;
+                true
+            }
+            _ => false,
+        };
+
+        // earlier frames may still use the buffers and copies to the same buffer may overlap,
+        // images get a barrier with every copy
+        let mut written: Option<HashSet<vk::Buffer>> = None;
+        for pending in &inline {
+            if let Copy::Buffer { dst, .. } = &pending.copy {
+                let wait = match &written {
+                    None => Some((
+                        vk::PipelineStageFlags2::ALL_COMMANDS,
+                        vk::AccessFlags2::MEMORY_WRITE,
+                    )),
+                    Some(written) if written.contains(dst) => Some((
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    )),
+                    Some(_) => None,
+                };
+                let written = written.get_or_insert_with(HashSet::new);
+                if let Some((stage, access)) = wait {
+                    let barriers = [vk::MemoryBarrier2::default()
+                        .src_stage_mask(stage)
+                        .src_access_mask(access)
+                        .dst_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+                        .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)];
+                    let info = vk::DependencyInfo::default().memory_barriers(&barriers);
+                    unsafe { handle.cmd_pipeline_barrier2(cmd, &info) };
+                    written.clear();
+                }
+                written.insert(*dst);
+            }
+            record_copy(handle, cmd, pending, true);
+        }
+
+        let barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE);
+        let barriers = [barrier];
+        let info = vk::DependencyInfo::default().memory_barriers(&barriers);
+        unsafe { handle.cmd_pipeline_barrier2(cmd, &info) };
+
+        for buffer in self.oversized.drain(..) {
+            device.destroy_buffer(buffer);
+        }
+
+        self.batches.push_back(Batch {
+            end_ticket: self.next_ticket,
+            submission,
+            ring_bytes: self.pending_ring_bytes,
+            ring_end: self.ring.head,
+        });
+        self.pending_ring_bytes = 0;
+        self.flushed = self.next_ticket;
+        Ok(waits)
+    }
+
+    /// Only copies discarding the destination skip the acquire from the graphics family,
+    /// `record` also keeps destinations that were uploaded before or written twice inline
+    fn is_background(&self, pending: &PendingCopy) -> bool {
+        match &pending.copy {
+            Copy::Buffer { whole, .. } => *whole,
+            Copy::Image { upload, .. } => {
+                upload.old_layout == vk::ImageLayout::UNDEFINED
+                    && self.transfer.as_ref().is_some_and(|path| path.images)
+            }
+            Copy::Mips(_) => false,
+        }
+    }
+
+    /// Records the copies on the transfer queue releasing their destinations to the graphics
+    /// family and submits them, the matching acquire goes at the start of `cmd`
+    fn submit_background(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        transfer: TransferSubmit,
+        copies: &[PendingCopy],
+    ) -> Result<(), RendererError> {
+        let handle = device.handle();
+        let Some(queue) = device.dedicated_transfer_queue() else {
+            return Err(RendererError::Unsupported(
+                "background uploads without a dedicated transfer queue".to_owned(),
+            ));
+        };
+        let ownership = OwnershipTransfer::new(queue.family(), device.graphics_family());
+
+        device.reset_command_buffer(transfer.buffer)?;
+        device.begin_command_buffer(
+            transfer.buffer,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        )?;
+
+        let (write_stage, write_access) = (
+            vk::PipelineStageFlags2::ALL_TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+        let (use_stage, use_access) = (
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        );
+        let mut buffers = vec![];
+        let mut images: Vec<(vk::Image, vk::ImageSubresourceRange, vk::ImageLayout)> = vec![];
+        for pending in copies {
+            match &pending.copy {
+                Copy::Buffer { dst, .. } => {
+                    if !buffers.contains(dst) {
+                        buffers.push(*dst);
+                    }
+                }
+                Copy::Image { upload, .. } => {
+                    let range = subresource_range(upload);
+                    let seen = images.iter().any(|(image, seen, _)| {
+                        *image == upload.image
+                            && (seen.base_mip_level, seen.base_array_layer, seen.layer_count)
+                                == (
+                                    range.base_mip_level,
+                                    range.base_array_layer,
+                                    range.layer_count,
+                                )
+                    });
+                    if !seen {
+                        images.push((upload.image, range, upload.new_layout));
+                    }
+                }
+                Copy::Mips(_) => {
+                    return Err(RendererError::Unsupported(
+                        "mip generation on the transfer queue".to_owned(),
+                    ))
+                }
+            }
+            record_copy(handle, transfer.buffer, pending, false);
+        }
+
+        let layouts = |new_layout| (vk::ImageLayout::TRANSFER_DST_OPTIMAL, new_layout);
+        let release_buffers: Vec<_> = buffers
+            .iter()
+            .map(|buffer| ownership.release_buffer(*buffer, write_stage, write_access))
+            .collect();
+        let release_images: Vec<_> = images
+            .iter()
+            .map(|(image, range, layout)| {
+                ownership.release_image(*image, *range, layouts(*layout), write_stage, write_access)
+            })
+            .collect();
+        let info = vk::DependencyInfo::default()
+            .buffer_memory_barriers(&release_buffers)
+            .image_memory_barriers(&release_images);
+        unsafe { handle.cmd_pipeline_barrier2(transfer.buffer, &info) };
+
+        device.end_command_buffer(transfer.buffer)?;
+        queue.submit(
+            &[transfer.buffer],
+            &[],
+            &[(transfer.semaphore, vk::PipelineStageFlags2::ALL_TRANSFER)],
+            vk::Fence::null(),
+        )?;
+
+        let acquire_buffers: Vec<_> = buffers
+            .iter()
+            .map(|buffer| ownership.acquire_buffer(*buffer, use_stage, use_access))
+            .collect();
+        let acquire_images: Vec<_> = images
+            .iter()
+            .map(|(image, range, layout)| {
+                ownership.acquire_image(*image, *range, layouts(*layout), use_stage, use_access)
+            })
+            .collect();
+        let info = vk::DependencyInfo::default()
+            .buffer_memory_barriers(&acquire_buffers)
+            .image_memory_barriers(&acquire_images);
+        unsafe { handle.cmd_pipeline_barrier2(cmd, &info) };
+        Ok(())
+    }
+
+    /// Submits the pending copies on their own and waits for them
+    pub fn flush_immediate(&mut self, device: &Device) -> Result<(), RendererError> {
+        if !self.has_pending() {
+            return Ok(());
+        }
+        let cmd = self.immediate.buffer;
+        let transfer = self.transfer.as_ref().map(|path| path.immediate);
+        self.begin_immediate(device)?;
+        let waits = self.record(device, cmd, transfer, Submission::Done)?;
+        let wait: Vec<_> = transfer
+            .filter(|_| waits)
+            .map(|submit| (submit.semaphore, vk::PipelineStageFlags2::ALL_COMMANDS))
+            .into_iter()
+            .collect();
+        self.end_immediate(device, &wait)?;
+        self.reclaim(|_| false);
+        Ok(())
+    }
+
+    /// Records `record` into a one-off command buffer, submits it and waits for it
+    pub fn immediate_submit<F>(&mut self, device: &Device, record: F) -> Result<(), vk::Result>
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        self.begin_immediate(device)?;
+        record(self.immediate.buffer);
+        self.end_immediate(device, &[])
+    }
+
+    fn begin_immediate(&self, device: &Device) -> Result<(), vk::Result> {
+        device.reset_command_buffer(self.immediate.buffer)?;
+        device.begin_command_buffer(
+            self.immediate.buffer,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        )
+    }
+
+    fn end_immediate(
+        &self,
+        device: &Device,
+        wait: &[(vk::Semaphore, vk::PipelineStageFlags2)],
+    ) -> Result<(), vk::Result> {
+        device.end_command_buffer(self.immediate.buffer)?;
+        device.graphics_queue().submit(
+            &[self.immediate.buffer],
+            wait,
+            &[],
+            self.immediate.fence,
+        )?;
+        device.wait_fence(self.immediate.fence, u64::MAX)?;
+        device.reset_fence(self.immediate.fence)
+    }
+
+    /// Where the batch holding the ticket went, None if not flushed yet or already reclaimed
+    pub fn submission(&self, ticket: UploadTicket) -> Option<Submission> {
+        if ticket.0 < self.completed {
+            return Some(Submission::Done);
+        }
+        if ticket.0 >= self.flushed {
+            return None;
+        }
+        self.batches
+            .iter()
+            .find(|batch| ticket.0 < batch.end_ticket)
+            .map(|batch| batch.submission)
+    }
+
+    /// Gives back the staging space of the batches done on the gpu, in submission order
+    pub fn reclaim<F>(&mut self, frame_done: F)
+    where
+        F: Fn(u64) -> bool,
+    {
+        while let Some(batch) = self.batches.front() {
+            let done = match batch.submission {
+                Submission::Done => true,
+                Submission::Frame(frame) => frame_done(frame),
+            };
+            if !done {
+                break;
+            }
+            self.ring.release(batch.ring_bytes, batch.ring_end);
+            self.completed = batch.end_ticket;
+            self.batches.pop_front();
+        }
+    }
+
+    pub(crate) fn destroy(self, device: &Device) {
+        device.destroy_fence(self.immediate.fence);
+        device.destroy_command_pool(self.immediate.pool);
+        if let Some(path) = self.transfer {
+            for submit in path.frames.iter().chain([&path.immediate]) {
+                device.destroy_semaphore(submit.semaphore);
+            }
+            device.destroy_command_pool(path.pool);
+        }
+        device.destroy_buffer(self.staging);
+        for buffer in self.oversized {
+            device.destroy_buffer(buffer);
+        }
+    }
+}
+
+/// Copies into `cmd`, images are left in TRANSFER_DST_OPTIMAL unless `transition` is set
+fn record_copy(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    pending: &PendingCopy,
+    transition: bool,
+) {
+    match &pending.copy {
+        Copy::Buffer { dst, region, .. } => unsafe {
+            device.cmd_copy_buffer(cmd, pending.src, *dst, &[*region])
+        },
+        Copy::Mips(mips) => record_mips(device, cmd, mips),
+        Copy::Image {
+            upload,
+            buffer_offset,
+        } => {
+            let range = subresource_range(upload);
+            image_barrier(
+                device,
+                cmd,
+                upload.image,
+                range,
+                upload.old_layout,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            let region = vk::BufferImageCopy {
+                buffer_offset: *buffer_offset,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: upload.subresource,
+                image_offset: upload.offset,
+                image_extent: upload.extent,
+            };
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    cmd,
+                    pending.src,
+                    upload.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                )
+            };
+            if transition {
+                image_barrier(
+                    device,
+                    cmd,
+                    upload.image,
+                    range,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    upload.new_layout,
+                );
+            }
+        }
+    }
+}
+
+/// Blits every level from the previous one, each level goes TRANSFER_DST -> TRANSFER_SRC
+/// once written, then everything moves to the final layout
+fn record_mips(device: &ash::Device, cmd: vk::CommandBuffer, mips: &MipGeneration) {
+    let range = |level, levels| vk::ImageSubresourceRange {
+        aspect_mask: mips.aspect,
+        base_mip_level: level,
+        level_count: levels,
+        base_array_layer: 0,
+        layer_count: mips.array_layers,
+    };
+    let layers = |level| vk::ImageSubresourceLayers {
+        aspect_mask: mips.aspect,
+        mip_level: level,
+        base_array_layer: 0,
+        layer_count: mips.array_layers,
+    };
+    let corner = |level| {
+        let extent = mip_extent(mips.extent, level);
+        vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        }
+    };
+
+    if mips.mip_levels > 1 {
+        image_barrier(
+            device,
+            cmd,
+            mips.image,
+            range(1, mips.mip_levels - 1),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+    }
+    for level in 1..mips.mip_levels {
+        image_barrier(
+            device,
+            cmd,
+            mips.image,
+            range(level - 1, 1),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        let blit = vk::ImageBlit {
+            src_subresource: layers(level - 1),
+            src_offsets: [vk::Offset3D::default(), corner(level - 1)],
+            dst_subresource: layers(level),
+            dst_offsets: [vk::Offset3D::default(), corner(level)],
+        };
+        unsafe {
+            device.cmd_blit_image(
+                cmd,
+                mips.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                mips.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            )
+        };
+    }
+
+    let last = mips.mip_levels - 1;
+    if last > 0 {
+        image_barrier(
+            device,
+            cmd,
+            mips.image,
+            range(0, last),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            mips.new_layout,
+        );
+    }
+    image_barrier(
+        device,
+        cmd,
+        mips.image,
+        range(last, 1),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mips.new_layout,
+    );
+}
+
+fn subresource_range(upload: &ImageUpload) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: upload.subresource.aspect_mask,
+        base_mip_level: upload.subresource.mip_level,
+        level_count: 1,
+        base_array_layer: upload.subresource.base_array_layer,
+        layer_count: upload.subresource.layer_count,
+    }
+}
+
+fn image_barrier(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let barrier = vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .image(image)
+        .subresource_range(range);
+    let barriers = [barrier];
+    let info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+    unsafe { device.cmd_pipeline_barrier2(cmd, &info) };
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staging_ring_fills_up() {
+        let mut ring = StagingRing::new(64);
+        assert_eq!(ring.allocate(30, 4), Some((0, 30)));
+        // the alignment padding counts as consumed
+        assert_eq!(ring.allocate(16, 16), Some((32, 18)));
+        assert_eq!(ring.allocate(16, 16), Some((48, 16)));
+        assert_eq!(ring.used, 64);
+        assert_eq!(ring.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn staging_ring_rejects_what_never_fits() {
+        let mut ring = StagingRing::new(64);
+        assert_eq!(ring.allocate(65, 1), None);
+        assert_eq!(ring.allocate(64, 1), Some((0, 64)));
+        assert_eq!(ring.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn staging_ring_wraps_once_the_start_is_free() {
+        let mut ring = StagingRing::new(100);
+        let (_, first) = ring.allocate(40, 1).unwrap();
+        let first_end = ring.head;
+        assert_eq!(ring.allocate(40, 1), Some((40, 40)));
+        // the 20 bytes left at the end are too few and the start is still in use
+        assert_eq!(ring.allocate(30, 1), None);
+
+        ring.release(first, first_end);
+        // wrapping wastes the end of the buffer until the tail passes it
+        assert_eq!(ring.allocate(30, 1), Some((0, 50)));
+        assert_eq!(ring.used, 90);
+        assert_eq!(ring.allocate(20, 1), None);
+        assert_eq!(ring.allocate(10, 1), Some((30, 10)));
+        assert_eq!(ring.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn staging_ring_reclaims_in_order() {
+        let mut ring = StagingRing::new(100);
+        let mut batches = vec![];
+        for size in [30, 30, 30] {
+            let (_, consumed) = ring.allocate(size, 1).unwrap();
+            batches.push((consumed, ring.head));
+        }
+
+        for (consumed, end) in batches {
+            ring.release(consumed, end);
+            assert_eq!(ring.tail, end);
+        }
+        assert_eq!(ring.used, 0);
+        // an empty ring starts over from the beginning
+        assert_eq!(ring.allocate(100, 1), Some((0, 100)));
+    }
+}
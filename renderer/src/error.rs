@@ -0,0 +1,179 @@
+use std::{error::Error, fmt};
+
+use ash::vk;
+
+use crate::{
+    core::{GpuRejection, SpirvError},
+    PipelineKey,
+};
+
+/// Every failure the renderer can report to the client, grouped by the stage that produced it
+#[derive(Debug)]
+pub enum RendererError {
+    /// The vulkan loader library couldn't be found or opened
+    Loading(ash::LoadingError),
+    /// A requested instance layer is not installed on the system
+    MissingLayer {
+        name: String,
+        result: vk::Result,
+    },
+    /// A requested instance extension is not exposed by the loader/driver
+    MissingExtension {
+        name: String,
+        result: vk::Result,
+    },
+    InstanceCreation(vk::Result),
+    SurfaceCreation(Box<dyn Error + Send + Sync>),
+    /// No physical device passed the suitability checks, holds the reason for each one
+    NoSuitableGpu(Vec<GpuRejection>),
+    DeviceCreation(vk::Result),
+    SwapchainCreation(vk::Result),
+    OutOfMemory(vk::Result),
+    /// The format can't be used for what was requested
+    UnsupportedFormat(vk::Format),
+    /// The operation isn't available with the current device/target configuration
+    Unsupported(String),
+    /// An API called in a way its contract doesn't allow
+    InvalidUsage(String),
+    /// An offset or size past the end of the resource it refers to
+    OutOfRange(String),
+    /// Renderer configuration values that can't describe a working renderer
+    InvalidConfig(String),
+    Io(std::io::Error),
+    Png(png::EncodingError),
+    /// A texture file is corrupted or uses a feature of its container that isn't handled
+    TextureDecode(Box<dyn Error + Send + Sync>),
+    /// A shader binary is not valid SPIR-V
+    InvalidSpirv(SpirvError),
+    /// Pipeline state that doesn't agree with its shaders or with itself
+    InvalidPipeline(String),
+    /// Submesh ranges or buffer sizes that don't describe a drawable mesh
+    InvalidMesh(String),
+    /// Typed data that doesn't fit the layout it's written to
+    LayoutMismatch(String),
+    /// Nothing is registered under the key
+    UnknownPipeline(PipelineKey),
+    /// A pipeline is already registered under the key
+    DuplicatePipeline(PipelineKey),
+    /// Shader source that failed to compile, one entry per error
+    #[cfg(feature = "shader-compiler")]
+    ShaderCompilation(Vec<crate::ShaderDiagnostic>),
+    /// Any other vulkan call failing outside of the stages above
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Loading(err) => write!(f, "failed to load vulkan: {}", err),
+            Self::MissingLayer { name, result } => {
+                write!(f, "instance layer \"{}\" is not present ({})", name, result)
+            }
+            Self::MissingExtension { name, result } => {
+                write!(
+                    f,
+                    "instance extension \"{}\" is not present ({})",
+                    name, result
+                )
+            }
+            Self::InstanceCreation(result) => write!(f, "instance creation failed: {}", result),
+            Self::SurfaceCreation(err) => write!(f, "surface creation failed: {}", err),
+            Self::NoSuitableGpu(rejections) => {
+                write!(f, "no suitable GPU found")?;
+                for rejection in rejections {
+                    write!(f, "\n  {}", rejection)?;
+                }
+                Ok(())
+            }
+            Self::DeviceCreation(result) => write!(f, "device creation failed: {}", result),
+            Self::SwapchainCreation(result) => write!(f, "swapchain creation failed: {}", result),
+            Self::OutOfMemory(result) => write!(f, "out of memory: {}", result),
+            Self::UnsupportedFormat(format) => write!(f, "unsupported format {:?}", format),
+            Self::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Self::InvalidUsage(reason) => write!(f, "invalid usage: {}", reason),
+            Self::OutOfRange(reason) => write!(f, "out of range: {}", reason),
+            Self::InvalidConfig(reason) => write!(f, "invalid renderer config: {}", reason),
+            Self::Io(err) => write!(f, "io error: {}", err),
+            Self::Png(err) => write!(f, "png encoding failed: {}", err),
+            Self::TextureDecode(err) => write!(f, "texture decoding failed: {}", err),
+            Self::InvalidSpirv(err) => write!(f, "invalid SPIR-V: {}", err),
+            Self::InvalidPipeline(reason) => write!(f, "invalid pipeline: {}", reason),
+            Self::InvalidMesh(reason) => write!(f, "invalid mesh: {}", reason),
+            Self::LayoutMismatch(reason) => write!(f, "layout mismatch: {}", reason),
+            Self::UnknownPipeline(key) => write!(f, "no pipeline registered under key {}", key.0),
+            Self::DuplicatePipeline(key) => {
+                write!(f, "a pipeline is already registered under key {}", key.0)
+            }
+            #[cfg(feature = "shader-compiler")]
+            Self::ShaderCompilation(diagnostics) => {
+                write!(f, "shader compilation failed")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+            Self::Vulkan(result) => write!(f, "vulkan call failed: {}", result),
+        }
+    }
+}
+
+impl Error for RendererError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingLayer { result, .. }
+            | Self::MissingExtension { result, .. }
+            | Self::InstanceCreation(result)
+            | Self::DeviceCreation(result)
+            | Self::SwapchainCreation(result)
+            | Self::OutOfMemory(result)
+            | Self::Vulkan(result) => Some(result),
+            Self::SurfaceCreation(err) | Self::TextureDecode(err) => Some(err.as_ref()),
+            Self::Loading(err) => Some(err),
+            Self::Io(err) => Some(err),
+            Self::Png(err) => Some(err),
+            Self::InvalidSpirv(err) => Some(err),
+            Self::NoSuitableGpu(_)
+            | Self::UnsupportedFormat(_)
+            | Self::Unsupported(_)
+            | Self::InvalidUsage(_)
+            | Self::OutOfRange(_)
+            | Self::InvalidConfig(_)
+            | Self::InvalidPipeline(_)
+            | Self::InvalidMesh(_)
+            | Self::LayoutMismatch(_)
+            | Self::UnknownPipeline(_)
+            | Self::DuplicatePipeline(_) => None,
+            #[cfg(feature = "shader-compiler")]
+            Self::ShaderCompilation(_) => None,
+        }
+    }
+}
+
+impl From<vk::Result> for RendererError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => {
+                Self::OutOfMemory(result)
+            }
+            _ => Self::Vulkan(result),
+        }
+    }
+}
+
+impl From<std::io::Error> for RendererError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for RendererError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Png(err)
+    }
+}
+
+impl From<SpirvError> for RendererError {
+    fn from(err: SpirvError) -> Self {
+        Self::InvalidSpirv(err)
+    }
+}
@@ -0,0 +1,472 @@
+use ash::vk;
+
+use crate::core::{
+    compute_to_compute_barrier, compute_to_graphics_barrier, image_barrier, Buffer, ComputeOutput,
+    ImageState, Mesh, Pipeline, Submesh,
+};
+use crate::{GpuLayout, LayoutRules, RendererError};
+
+/// Handle to the frame being recorded, returned by `Renderer::begin_frame` and
+/// consumed by `Renderer::end_frame`
+pub struct Frame {
+    pub(crate) device: ash::Device,
+    pub(crate) slot: usize,
+    pub(crate) image_index: u32,
+    pub(crate) buffer: vk::CommandBuffer,
+    pub(crate) image: vk::Image,
+    pub(crate) view: vk::ImageView,
+    pub(crate) state: ImageState,
+    pub(crate) extent: vk::Extent2D,
+    pub(crate) format: vk::Format,
+    /// Layout of the last bound pipeline, what typed push constants are checked against
+    pub(crate) bound: Option<BoundLayout>,
+}
+
+pub(crate) struct BoundLayout {
+    handle: vk::PipelineLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl Frame {
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.buffer
+    }
+
+    /// Swapchain image this frame renders to
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn image_view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn image_index(&self) -> u32 {
+        self.image_index
+    }
+
+    /// Current layout of the target image, it starts as GENERAL
+    pub fn image_layout(&self) -> vk::ImageLayout {
+        self.state.layout
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// Moves the target image to a new layout, waiting on its last use
+    pub fn transition(&mut self, new_layout: vk::ImageLayout) {
+        let next = ImageState::for_layout(new_layout);
+        let barriers = [image_barrier(
+            self.image,
+            color_subresource_range(),
+            self.state,
+            next,
+        )];
+        let info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(self.buffer, &info) };
+        self.state = next;
+    }
+
+    /// Clears the whole target image, moving it to GENERAL first if needed
+    pub fn clear(&mut self, color: [f32; 4]) {
+        if self.state.layout != vk::ImageLayout::GENERAL
+            && self.state.layout != vk::ImageLayout::TRANSFER_DST_OPTIMAL
+        {
+            self.transition(vk::ImageLayout::GENERAL);
+        }
+
+        let clear = vk::ClearColorValue { float32: color };
+        let range = color_subresource_range();
+        unsafe {
+            self.device.cmd_clear_color_image(
+                self.buffer,
+                self.image,
+                self.state.layout,
+                &clear,
+                &[range],
+            )
+        };
+    }
+
+    /// Starts dynamic rendering into the target image, cleared to `clear` or loaded as it is,
+    /// with the viewport and scissor covering all of it
+    pub fn begin_rendering(&mut self, clear: Option<[f32; 4]>) {
+        if self.state.layout != vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL {
+            self.transition(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        }
+
+        let load_op = match clear {
+            Some(_) => vk::AttachmentLoadOp::CLEAR,
+            None => vk::AttachmentLoadOp::LOAD,
+        };
+        let attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(self.view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear.unwrap_or_default(),
+                },
+            })];
+        let area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent: self.extent,
+        };
+        let info = vk::RenderingInfo::default()
+            .render_area(area)
+            .layer_count(1)
+            .color_attachments(&attachments);
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        unsafe {
+            self.device.cmd_begin_rendering(self.buffer, &info);
+            self.device.cmd_set_viewport(self.buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(self.buffer, 0, &[area]);
+        }
+    }
+
+    pub fn end_rendering(&mut self) {
+        unsafe { self.device.cmd_end_rendering(self.buffer) };
+    }
+
+    pub fn bind_pipeline(&mut self, pipeline: &Pipeline) {
+        unsafe {
+            self.device
+                .cmd_bind_pipeline(self.buffer, pipeline.bind_point(), pipeline.handle())
+        };
+        let ranges = pipeline.layout().push_constant_ranges();
+        match &mut self.bound {
+            Some(bound) => {
+                bound.handle = pipeline.layout().handle();
+                bound.push_constant_ranges.clear();
+                bound.push_constant_ranges.extend_from_slice(ranges);
+            }
+            None => {
+                self.bound = Some(BoundLayout {
+                    handle: pipeline.layout().handle(),
+                    push_constant_ranges: ranges.to_vec(),
+                })
+            }
+        }
+    }
+
+    /// Pushes `data` at offset 0 of the last bound pipeline layout, see `push_constants_at`
+    pub fn push_constants<T: GpuLayout>(&mut self, data: &T) -> Result<(), RendererError> {
+        self.push_constants_at(0, data)
+    }
+
+    /// Pushes `data` at `offset` of the last bound pipeline layout. Fails without writing if no
+    /// pipeline is bound, `T` uses std140 or its bytes aren't all covered by the layout ranges
+    pub fn push_constants_at<T: GpuLayout>(
+        &mut self,
+        offset: u32,
+        data: &T,
+    ) -> Result<(), RendererError> {
+        let bound = self.bound.as_ref().ok_or_else(|| {
+            RendererError::LayoutMismatch("push constants before binding a pipeline".into())
+        })?;
+        if T::RULES == LayoutRules::Std140 {
+            return Err(RendererError::LayoutMismatch(format!(
+                "push constant block {} uses std140, push constants are std430 or scalar",
+                std::any::type_name::<T>()
+            )));
+        }
+        let bytes = data.to_bytes();
+        let stages = covering_stages(&bound.push_constant_ranges, offset, bytes.len() as u32)
+            .ok_or_else(|| {
+                RendererError::LayoutMismatch(format!(
+                    "{} bytes of {} at offset {} are not covered by the pipeline push constant ranges",
+                    bytes.len(),
+                    std::any::type_name::<T>(),
+                    offset
+                ))
+            })?;
+        unsafe {
+            self.device
+                .cmd_push_constants(self.buffer, bound.handle, stages, offset, &bytes)
+        };
+        Ok(())
+    }
+
+    /// Stages come from the pipeline push constant ranges overlapping the written bytes.
+    /// Fails without writing if the bytes would end past `u32::MAX`
+    pub fn push_constant_bytes(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: u32,
+        bytes: &[u8],
+    ) -> Result<(), RendererError> {
+        let end = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or_else(|| {
+                RendererError::LayoutMismatch(format!(
+                    "{} push constant bytes at offset {} overflow",
+                    bytes.len(),
+                    offset
+                ))
+            })?;
+        let stages = pipeline
+            .layout()
+            .push_constant_ranges()
+            .iter()
+            .filter(|range| range.offset < end && range_end(range).is_some_and(|e| offset < e))
+            .fold(vk::ShaderStageFlags::empty(), |stages, range| {
+                stages | range.stage_flags
+            });
+        unsafe {
+            self.device.cmd_push_constants(
+                self.buffer,
+                pipeline.layout().handle(),
+                stages,
+                offset,
+                bytes,
+            )
+        };
+        Ok(())
+    }
+
+    /// Binds `sets` starting at set number `first_set` of the pipeline layout
+    pub fn bind_descriptor_sets(
+        &mut self,
+        pipeline: &Pipeline,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+    ) {
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.buffer,
+                pipeline.bind_point(),
+                pipeline.layout().handle(),
+                first_set,
+                sets,
+                &[],
+            )
+        };
+    }
+
+    /// Runs the bound compute pipeline, outside of `begin_rendering`/`end_rendering`
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        unsafe { self.device.cmd_dispatch(self.buffer, x, y, z) };
+    }
+
+    /// Group counts read from a `vk::DispatchIndirectCommand` at `offset`
+    pub fn dispatch_indirect(&mut self, buffer: &Buffer, offset: vk::DeviceSize) {
+        unsafe {
+            self.device
+                .cmd_dispatch_indirect(self.buffer, buffer.handle(), offset)
+        };
+    }
+
+    /// Waits for the dispatches recorded so far before the draws reading their results
+    pub fn compute_to_graphics(&mut self, outputs: &[ComputeOutput]) {
+        self.memory_barrier(compute_to_graphics_barrier(outputs));
+    }
+
+    /// Waits for the dispatches recorded so far before the next ones
+    pub fn compute_to_compute(&mut self) {
+        self.memory_barrier(compute_to_compute_barrier());
+    }
+
+    fn memory_barrier(&mut self, barrier: vk::MemoryBarrier2) {
+        let barriers = [barrier];
+        let info = vk::DependencyInfo::default().memory_barriers(&barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(self.buffer, &info) };
+    }
+
+    /// Non indexed draw of the bound pipeline, between `begin_rendering` and `end_rendering`
+    pub fn draw(&mut self, vertex_count: u32, instance_count: u32) {
+        unsafe {
+            self.device
+                .cmd_draw(self.buffer, vertex_count, instance_count, 0, 0)
+        };
+    }
+
+    /// Binds `buffer` to a vertex binding of the bound pipeline, per instance data goes here
+    pub fn bind_vertex_buffer(&mut self, binding: u32, buffer: &Buffer, offset: vk::DeviceSize) {
+        unsafe {
+            self.device
+                .cmd_bind_vertex_buffers(self.buffer, binding, &[buffer.handle()], &[offset])
+        };
+    }
+
+    /// Draws every submesh with the bound pipeline, the vertices are bound to binding 0
+    pub fn draw_mesh(&mut self, mesh: &Mesh) {
+        self.draw_mesh_instanced(mesh, 1);
+    }
+
+    pub fn draw_mesh_instanced(&mut self, mesh: &Mesh, instance_count: u32) {
+        self.bind_mesh(mesh);
+        for submesh in mesh.submeshes() {
+            self.draw_range(mesh, submesh, instance_count);
+        }
+    }
+
+    /// Draws one of the ranges returned by `Mesh::submeshes`, fails on an out of range index
+    pub fn draw_submesh(
+        &mut self,
+        mesh: &Mesh,
+        submesh: usize,
+        instance_count: u32,
+    ) -> Result<(), RendererError> {
+        let Some(range) = mesh.submeshes().get(submesh) else {
+            return Err(RendererError::InvalidMesh(format!(
+                "submesh {} of a mesh with {}",
+                submesh,
+                mesh.submeshes().len()
+            )));
+        };
+        self.bind_mesh(mesh);
+        self.draw_range(mesh, range, instance_count);
+        Ok(())
+    }
+
+    fn bind_mesh(&mut self, mesh: &Mesh) {
+        self.bind_vertex_buffer(0, mesh.vertex_buffer(), 0);
+        if let (Some(indices), Some(index_type)) = (mesh.index_buffer(), mesh.index_type()) {
+            unsafe {
+                self.device
+                    .cmd_bind_index_buffer(self.buffer, indices.handle(), 0, index_type)
+            };
+        }
+    }
+
+    fn draw_range(&mut self, mesh: &Mesh, submesh: &Submesh, instance_count: u32) {
+        unsafe {
+            if mesh.index_buffer().is_some() {
+                self.device.cmd_draw_indexed(
+                    self.buffer,
+                    submesh.index_count,
+                    instance_count,
+                    submesh.first_index,
+                    submesh.vertex_offset,
+                    0,
+                )
+            } else {
+                self.device.cmd_draw(
+                    self.buffer,
+                    submesh.index_count,
+                    instance_count,
+                    submesh.first_index,
+                    0,
+                )
+            }
+        };
+    }
+}
+
+/// Stages of the ranges overlapping `offset..offset + size`. None when a byte is missed by a
+/// range of one of those stages, vulkan wants every byte pushed for every stage passed
+fn covering_stages(
+    ranges: &[vk::PushConstantRange],
+    offset: u32,
+    size: u32,
+) -> Option<vk::ShaderStageFlags> {
+    if size == 0 || !offset.is_multiple_of(4) || !size.is_multiple_of(4) {
+        return None;
+    }
+    let end = offset.checked_add(size)?;
+    // a range ending past u32::MAX is invalid, it covers nothing
+    let mut overlapping: Vec<_> = ranges
+        .iter()
+        .filter(|range| range.offset < end && range_end(range).is_some_and(|e| offset < e))
+        .collect();
+    overlapping.sort_by_key(|range| range.offset);
+    let stages = overlapping
+        .iter()
+        .fold(vk::ShaderStageFlags::empty(), |stages, range| {
+            stages | range.stage_flags
+        });
+
+    let covered = (0..u32::BITS)
+        .map(|bit| vk::ShaderStageFlags::from_raw(1 << bit))
+        .filter(|stage| stages.contains(*stage))
+        .all(|stage| {
+            let mut covered = offset;
+            for range in overlapping
+                .iter()
+                .filter(|range| range.stage_flags.contains(stage))
+            {
+                let Some(range_end) = range_end(range) else {
+                    return false;
+                };
+                if range.offset > covered {
+                    return false;
+                }
+                covered = covered.max(range_end);
+            }
+            covered >= end
+        });
+    (!stages.is_empty() && covered).then_some(stages)
+}
+
+fn range_end(range: &vk::PushConstantRange) -> Option<u32> {
+    range.offset.checked_add(range.size)
+}
+
+pub(crate) fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(stages: vk::ShaderStageFlags, offset: u32, size: u32) -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: stages,
+            offset,
+            size,
+        }
+    }
+
+    #[test]
+    fn stages_cover_every_byte() {
+        let vertex = vk::ShaderStageFlags::VERTEX;
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let ranges = [range(vertex, 0, 64), range(vertex | fragment, 64, 16)];
+
+        assert_eq!(covering_stages(&ranges, 0, 64), Some(vertex));
+        assert_eq!(covering_stages(&ranges, 64, 16), Some(vertex | fragment));
+        // the fragment stage doesn't see bytes 0..64
+        assert_eq!(covering_stages(&ranges, 0, 80), None);
+        assert_eq!(covering_stages(&ranges, 64, 32), None);
+        assert_eq!(covering_stages(&ranges, 2, 4), None);
+        assert_eq!(covering_stages(&[], 0, 4), None);
+        assert_eq!(covering_stages(&ranges, 0, 0), None);
+    }
+
+    #[test]
+    fn overflowing_bytes_are_never_covered() {
+        let vertex = vk::ShaderStageFlags::VERTEX;
+        let ranges = [range(vertex, 0, 64)];
+        assert_eq!(covering_stages(&ranges, u32::MAX - 3, 8), None);
+        assert_eq!(covering_stages(&[range(vertex, 16, u32::MAX)], 16, 4), None);
+    }
+
+    #[test]
+    fn split_ranges_of_one_stage() {
+        let vertex = vk::ShaderStageFlags::VERTEX;
+        let ranges = [range(vertex, 16, 16), range(vertex, 0, 16)];
+        assert_eq!(covering_stages(&ranges, 0, 32), Some(vertex));
+        assert_eq!(covering_stages(&ranges[..1], 0, 32), None);
+    }
+}
@@ -0,0 +1,232 @@
+/// Rules GLSL uses to place the members of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayoutRules {
+    /// Uniform buffers, arrays and structs are aligned to 16 bytes
+    Std140,
+    /// Storage buffers and push constants
+    Std430,
+    /// `GL_EXT_scalar_block_layout`, everything aligned to its scalar type
+    Scalar,
+}
+
+/// Where a type goes inside a block under one set of rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub align: usize,
+    pub size: usize,
+    /// The Rust type stores its contents at the same offsets, so its bytes can be copied
+    pub exact: bool,
+}
+
+impl FieldLayout {
+    const fn scalar(size: usize) -> Self {
+        Self {
+            align: size,
+            size,
+            exact: true,
+        }
+    }
+}
+
+/// Types that can be a member of a GLSL block: 32 bit scalars, `[T; 2..=4]` vectors of them,
+/// arrays of vectors or structs (`[[f32; 4]; 4]` is a mat4) and `#[derive(GpuLayout)]` structs.
+/// Scalars are arrayed through `[T; 1]`, `float weights[8]` is `[[f32; 1]; 8]`.
+///
+/// # Safety
+/// The layouts must describe the type and `write_bytes` must write `size_of::<Self>()` bytes
+pub unsafe trait GpuField: Copy + 'static {
+    const STD140: FieldLayout;
+    const STD430: FieldLayout;
+    const SCALAR: FieldLayout;
+
+    /// Copies the value to the start of `bytes`, padding is left alone
+    fn write_bytes(&self, bytes: &mut [u8]);
+}
+
+/// Struct whose field offsets were checked against `RULES` at compile time, implemented by
+/// `#[derive(GpuLayout)]` along with `GpuField`
+///
+/// # Safety
+/// Every field must be at the offset `RULES` gives it
+pub unsafe trait GpuLayout: GpuField {
+    const RULES: LayoutRules;
+
+    /// Contents as the shader sees them, padding zeroed
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; std::mem::size_of::<Self>()];
+        self.write_bytes(&mut bytes);
+        bytes
+    }
+}
+
+pub use renderer_derive::GpuLayout;
+
+impl LayoutRules {
+    pub const fn field<T: GpuField>(self) -> FieldLayout {
+        match self {
+            Self::Std140 => T::STD140,
+            Self::Std430 => T::STD430,
+            Self::Scalar => T::SCALAR,
+        }
+    }
+
+    /// Structs and arrays start at multiples of 16 in std140
+    const fn aggregate_align(self, align: usize) -> usize {
+        match self {
+            Self::Std140 if align < 16 => 16,
+            _ => align,
+        }
+    }
+}
+
+const fn round_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+macro_rules! scalar_fields {
+    ($($ty:ty),*) => {$(
+        unsafe impl GpuField for $ty {
+            const STD140: FieldLayout = FieldLayout::scalar(4);
+            const STD430: FieldLayout = FieldLayout::scalar(4);
+            const SCALAR: FieldLayout = FieldLayout::scalar(4);
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                bytes[..4].copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+
+        unsafe impl GpuField for [$ty; 1] {
+            const STD140: FieldLayout = FieldLayout::scalar(4);
+            const STD430: FieldLayout = FieldLayout::scalar(4);
+            const SCALAR: FieldLayout = FieldLayout::scalar(4);
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                self[0].write_bytes(bytes);
+            }
+        }
+
+        unsafe impl GpuField for [$ty; 2] {
+            const STD140: FieldLayout = vector(2, 2);
+            const STD430: FieldLayout = vector(2, 2);
+            const SCALAR: FieldLayout = vector(2, 1);
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                write_elements(self, bytes);
+            }
+        }
+
+        unsafe impl GpuField for [$ty; 3] {
+            const STD140: FieldLayout = vector(3, 4);
+            const STD430: FieldLayout = vector(3, 4);
+            const SCALAR: FieldLayout = vector(3, 1);
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                write_elements(self, bytes);
+            }
+        }
+
+        unsafe impl GpuField for [$ty; 4] {
+            const STD140: FieldLayout = vector(4, 4);
+            const STD430: FieldLayout = vector(4, 4);
+            const SCALAR: FieldLayout = vector(4, 1);
+
+            fn write_bytes(&self, bytes: &mut [u8]) {
+                write_elements(self, bytes);
+            }
+        }
+
+        impl ArrayElement for [$ty; 1] {}
+        impl ArrayElement for [$ty; 2] {}
+        impl ArrayElement for [$ty; 3] {}
+        impl ArrayElement for [$ty; 4] {}
+    )*};
+}
+
+/// `components` 4 byte scalars aligned to `align_components` of them
+const fn vector(components: usize, align_components: usize) -> FieldLayout {
+    FieldLayout {
+        align: align_components * 4,
+        size: components * 4,
+        exact: true,
+    }
+}
+
+fn write_elements<T: GpuField>(elements: &[T], bytes: &mut [u8]) {
+    let stride = std::mem::size_of::<T>();
+    for (index, element) in elements.iter().enumerate() {
+        element.write_bytes(&mut bytes[index * stride..]);
+    }
+}
+
+/// Types `[T; N]` is an array of rather than a vector, derived structs implement it too
+pub trait ArrayElement: GpuField {}
+
+scalar_fields!(f32, i32, u32);
+
+unsafe impl<T: ArrayElement, const N: usize> GpuField for [T; N] {
+    const STD140: FieldLayout = array::<T, N>(LayoutRules::Std140);
+    const STD430: FieldLayout = array::<T, N>(LayoutRules::Std430);
+    const SCALAR: FieldLayout = array::<T, N>(LayoutRules::Scalar);
+
+    fn write_bytes(&self, bytes: &mut [u8]) {
+        write_elements(self, bytes);
+    }
+}
+
+impl<T: ArrayElement, const N: usize> ArrayElement for [T; N] {}
+
+const fn array<T: GpuField, const N: usize>(rules: LayoutRules) -> FieldLayout {
+    let element = rules.field::<T>();
+    let align = rules.aggregate_align(element.align);
+    let stride = round_up(element.size, align);
+    FieldLayout {
+        align,
+        size: stride * N,
+        exact: element.exact && stride == std::mem::size_of::<T>(),
+    }
+}
+
+/// Offset of every field of a struct, the rules are already baked into the field layouts
+#[doc(hidden)]
+pub const fn struct_offsets<const N: usize>(fields: [FieldLayout; N]) -> [usize; N] {
+    let mut offsets = [0; N];
+    let mut end = 0;
+    let mut index = 0;
+    while index < N {
+        offsets[index] = round_up(end, fields[index].align);
+        end = offsets[index] + fields[index].size;
+        index += 1;
+    }
+    offsets
+}
+
+/// Layout of a struct as a member of another block, exact when every field is where `rules`
+/// put it and the Rust struct has the padded size
+#[doc(hidden)]
+pub const fn struct_layout<const N: usize>(
+    rules: LayoutRules,
+    fields: [FieldLayout; N],
+    rust_offsets: [usize; N],
+    rust_size: usize,
+) -> FieldLayout {
+    let offsets = struct_offsets(fields);
+    let mut align = 1;
+    let mut end = 0;
+    let mut exact = true;
+    let mut index = 0;
+    while index < N {
+        if fields[index].align > align {
+            align = fields[index].align;
+        }
+        end = offsets[index] + fields[index].size;
+        exact = exact && fields[index].exact && offsets[index] == rust_offsets[index];
+        index += 1;
+    }
+    let align = rules.aggregate_align(align);
+    let size = round_up(end, align);
+    FieldLayout {
+        align,
+        size,
+        exact: exact && size == rust_size,
+    }
+}
@@ -1,24 +1,66 @@
-use std::ffi::CString;
+use std::{borrow::Cow, ffi::c_char, mem::ManuallyDrop, path::Path};
 
-use ash::{ext, khr, vk};
+use ash::{khr, vk};
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
+// lets `#[derive(GpuLayout)]` name `::renderer` from inside the crate too
+extern crate self as renderer;
+
+mod config;
 mod core;
+mod error;
+mod frame;
+pub mod layout;
+mod screenshot;
+#[cfg(feature = "shader-compiler")]
+mod shader_compiler;
+mod shaders;
+mod texture;
+pub mod vertex;
+
+pub use ash;
+pub use bytemuck;
+pub use config::{
+    RendererBuilder, RendererConfig, DEFAULT_FRAMES_IN_FLIGHT, DEFAULT_STAGING_BUFFER_SIZE,
+};
+pub use core::{
+    aspect_flags, compute_to_compute_barrier, compute_to_graphics_barrier, image_barrier,
+    mip_count, mip_extent, numeric_type, texel_size, Allocation, AllocationRequest, AllocatorStats,
+    BindlessDesc, BindlessHeap, BindlessKind, BlendEquation, BlendState, Buffer, BufferDesc,
+    ComputeOutput, ComputePipelineBuilder, DedicatedResource, DescriptorAllocator,
+    DescriptorBinding, DescriptorLayoutCache, DescriptorWriter, Device, DeviceFeatures, EntryPoint,
+    GpuCandidate, GpuPreference, GpuRejection, GpuReport, GpuScorer, GpuSelector,
+    GraphicsPipelineBuilder, Image, ImageDesc, ImageDimension, ImageState, ImageUpload,
+    ImageViewDesc, MemoryStats, MemoryUsage, Mesh, MeshDesc, MeshIndex, NumericType,
+    OwnershipTransfer, Pipeline, PipelineLayout, PushConstantBlock, Queue, QueueFamilies,
+    RankedGpu, RejectionReason, ResourceKind, SamplerDesc, ShaderModule, ShaderReflection,
+    SpecConstant, SpecValue, Spirv, SpirvError, Submesh, Swapchain, SwapchainPreferences, Texture,
+    UploadTicket, VertexInput, VertexLayout, GPU_SELECTOR_ENV,
+};
+pub use error::RendererError;
+pub use frame::Frame;
+pub use layout::{FieldLayout, GpuField, GpuLayout, LayoutRules};
+pub use screenshot::Screenshot;
+#[cfg(feature = "shader-compiler")]
+pub use shader_compiler::{CompiledShader, ShaderCompiler, ShaderDiagnostic, ShaderLanguage};
+pub use shaders::{PipelineContext, PipelineDesc, PipelineHandle, PipelineKey, ShaderHandle};
+pub use texture::{TextureData, TextureOptions};
+pub use vertex::{Vertex, VertexFormat};
 
 /*
 *NOTE:
-* [] Create Swapchain
-* [] expose allocated image to the app to be drawn on
-* [] expose queue/swapchain functions to the app
-* [] clear color
+* [x] Create Swapchain
+* [x] expose allocated image to the app to be drawn on
+* [x] expose queue/swapchain functions to the app
+* [x] clear color
 *
 *NOTE:
-* [] expose logical device handle to create pipelines
-* [] load shaders
-* [] try to render a triangle with hardcoded vertex in shader
-* [] create a vertex buffer to draw a triangle
-* [] implement index buffer
-* [] draw a square
+* [x] expose logical device handle to create pipelines
+* [x] load shaders
+* [x] try to render a triangle with hardcoded vertex in shader
+* [x] create a vertex buffer to draw a triangle
+* [x] implement index buffer
+* [x] draw a square
 * [] implement a camera
 * [] create a cube
 *
@@ -59,24 +101,57 @@ mod core;
 * ```
 */
 
-// TODO: remove panics and unwrapping and move them to be handled in client code
-
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
-
 // NOTE: rust calls Drop implementations in order of member declaration.
 // This is stupid imho but it is what it is
 #[allow(dead_code)]
 pub struct Renderer {
     frame_number: usize,
-    frames: [FrameData; MAX_FRAMES_IN_FLIGHT],
+    frames: Vec<FrameData>,
+    gpu_report: core::GpuReport,
+    extent: vk::Extent2D,
+    /// Frame slot of the last submitted frame, used to read it back
+    last_submitted: Option<usize>,
+    /// Every frame up to this one is done on the gpu
+    completed_frame: Option<usize>,
+    /// A `Frame` was handed out and not passed to `end_frame` yet
+    recording: bool,
+    capture: Capture,
+    shaders: shaders::ShaderLibrary,
+    bindless: Option<core::BindlessHeap>,
+    /// Sets living as long as the renderer
+    descriptors: core::DescriptorAllocator,
+    /// Destroyed by hand in Drop, its buffers go back to the device
+    uploads: ManuallyDrop<core::UploadManager>,
+    target: Target,
     device: core::Device,
-    surface: core::Surface,
     instance: core::Instance,
 }
 
+/// What frames are drawn into, the swapchain must be dropped before its surface
+enum Target {
+    Window {
+        swapchain: Box<core::Swapchain>,
+        surface: core::Surface,
+    },
+    Offscreen(Box<core::Offscreen>),
+}
+
+/// Screenshot requested by the client, copied out of the target at the end of the frame
+#[derive(Default)]
+struct Capture {
+    requested: bool,
+    /// Frame number of the captured frame, until it gets taken
+    pending: Option<usize>,
+    readback: Option<core::Readback>,
+}
+
 struct FrameData {
     pub pool: vk::CommandPool,
     pub buffer: vk::CommandBuffer,
+    /// Pending uploads, submitted right before `buffer`
+    pub upload_buffer: vk::CommandBuffer,
+    /// Sets used by this frame only, reset once the slot comes around again
+    pub descriptors: core::DescriptorAllocator,
 
     pub swapchain_sem: vk::Semaphore,
     pub render_sem: vk::Semaphore,
@@ -88,6 +163,8 @@ impl Default for FrameData {
         Self {
             pool: vk::CommandPool::null(),
             buffer: vk::CommandBuffer::null(),
+            upload_buffer: vk::CommandBuffer::null(),
+            descriptors: core::DescriptorAllocator::default(),
             swapchain_sem: vk::Semaphore::null(),
             render_sem: vk::Semaphore::null(),
             render_fen: vk::Fence::null(),
@@ -96,104 +173,220 @@ impl Default for FrameData {
 }
 
 impl Renderer {
-    pub fn new<T>(window: &T, app_name: CString, validation: bool) -> Self
+    pub fn builder(app_name: std::ffi::CString) -> RendererBuilder {
+        RendererBuilder::new(app_name)
+    }
+
+    pub fn new<T>(window: &T, config: RendererConfig) -> Result<Self, RendererError>
     where
         T: HasDisplayHandle + HasWindowHandle,
     {
-        let mut layers = vec![];
-        let rwh = window.display_handle().unwrap().as_raw();
-        let mut extensions = ash_window::enumerate_required_extensions(rwh)
-            .unwrap()
+        let rwh = window
+            .display_handle()
+            .map_err(|err| RendererError::SurfaceCreation(Box::new(err)))?
+            .as_raw();
+        let extensions = ash_window::enumerate_required_extensions(rwh)
+            .map_err(|err| RendererError::SurfaceCreation(Box::new(err)))?
             .to_vec();
 
-        if validation {
+        let instance = Self::create_instance(&config, extensions)?;
+
+        let surface = instance.create_surface(window).inspect_err(|err| {
+            log::error!("Surface creation error: {}", err);
+        })?;
+        log::info!("Vulkan surface created successfully");
+
+        Self::init(config, instance, Some(surface))
+    }
+
+    /// Renderer without a window, frames are drawn into offscreen images of `config.extent`
+    /// and can be read back with `read_frame`
+    pub fn headless(config: RendererConfig) -> Result<Self, RendererError> {
+        if config.extent.width == 0 || config.extent.height == 0 {
+            return Err(RendererError::InvalidConfig(format!(
+                "headless renderers need a non-zero extent, got {}x{}, set it with `extent`",
+                config.extent.width, config.extent.height
+            )));
+        }
+        let instance = Self::create_instance(&config, vec![])?;
+        Self::init(config, instance, None)
+    }
+
+    fn create_instance(
+        config: &RendererConfig,
+        mut extensions: Vec<*const c_char>,
+    ) -> Result<core::Instance, RendererError> {
+        let mut layers = vec![];
+        extensions.extend(config.instance_extensions.iter().map(|name| name.as_ptr()));
+
+        if config.validation {
             extensions.push(ash::ext::debug_utils::NAME.as_ptr());
             layers.push(c"VK_LAYER_KHRONOS_validation".as_ptr());
         }
 
         let instance_spec = core::InstanceSpec {
-            app_name,
+            app_name: config.app_name.clone(),
+            app_version: config.app_version,
+            engine_name: config.engine_name.clone(),
+            engine_version: config.engine_version,
+            api_version: config.api_version,
             extensions,
+            optional_extensions: config
+                .optional_instance_extensions
+                .iter()
+                .map(|name| name.as_ptr())
+                .collect(),
             layers,
-            validation,
+            validation: config.validation,
+            debug_severity: config.debug_severity,
         };
 
-        let instance = match core::Instance::new(instance_spec) {
-            Ok(val) => val,
-            Err(err) => {
-                log::error!("Instance creation error: {}", err);
-                panic!("{}", err);
-            }
-        };
+        let instance = core::Instance::new(instance_spec).inspect_err(|err| {
+            log::error!("Instance creation error: {}", err);
+        })?;
         log::info!("Vulkan instance created successfully");
+        Ok(instance)
+    }
 
-        let surface = match instance.create_surface(window) {
-            Ok(val) => val,
-            Err(err) => {
-                log::error!("Surface creation error: {}", err);
-                panic!("{}", err);
-            }
-        };
-        log::info!("Vulkan surface created successfully");
+    fn init(
+        config: RendererConfig,
+        instance: core::Instance,
+        surface: Option<core::Surface>,
+    ) -> Result<Self, RendererError> {
+        // nothing to present to without a surface
+        let mut extensions: Vec<_> = config
+            .device_extensions
+            .iter()
+            .filter(|name| surface.is_some() || name.as_c_str() != khr::swapchain::NAME)
+            .map(|name| name.as_ptr())
+            .collect();
+        let optional_extensions: Vec<_> = config
+            .optional_device_extensions
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect();
 
-        let extensions = vec![
-            khr::swapchain::NAME.as_ptr(),
-            khr::dynamic_rendering::NAME.as_ptr(),
-            khr::synchronization2::NAME.as_ptr(),
-            khr::buffer_device_address::NAME.as_ptr(),
-            ext::descriptor_indexing::NAME.as_ptr(),
-        ];
-
-        let (gpu, graphics_family_index) =
-            match core::select_gpu(instance.handle(), &surface, &extensions) {
-                Ok(val) => val,
-                Err(err) => {
-                    log::error!("GPU selection failed: {}", err);
-                    panic!();
-                }
-            };
+        let selection = core::select_gpu(
+            instance.handle(),
+            instance.api_version(),
+            surface.as_ref(),
+            &extensions,
+            &config.required_features,
+            &config.gpu_selector,
+        )
+        .inspect_err(|err| {
+            log::error!("GPU selection failed: {}", err);
+        })?;
+        let gpu = selection.gpu;
 
-        let device = match instance.create_device(gpu, graphics_family_index, &extensions) {
-            Ok(val) => val,
-            Err(err) => {
+        extensions.extend(core::supported_extensions(
+            instance.handle(),
+            gpu,
+            &optional_extensions,
+        )?);
+
+        let api_version =
+            core::effective_api_version(instance.handle(), gpu, instance.api_version());
+        let supported = core::DeviceFeatures::query(instance.handle(), gpu, api_version);
+        let features = config
+            .required_features
+            .union(&config.optional_features.intersection(&supported));
+
+        let device = instance
+            .create_device(gpu, &selection.families, &extensions, &features)
+            .inspect_err(|err| {
                 log::error!("Device creation failed: {}", err);
-                panic!();
+            })?;
+        log::info!("Device created succesfully");
+
+        let frames_in_flight = config.frames_in_flight.max(1);
+        let target = match surface {
+            Some(surface) => {
+                let swapchain = device
+                    .create_swapchain(&instance, &surface, config.extent, config.swapchain.clone())
+                    .map_err(RendererError::SwapchainCreation)
+                    .inspect_err(|err| {
+                        log::error!("Swapchain creation failed: {}", err);
+                    })?;
+                log::info!("Swapchain created successfully");
+                Target::Window {
+                    swapchain: Box::new(swapchain),
+                    surface,
+                }
+            }
+            None => {
+                let offscreen = device.create_offscreen(config.extent, frames_in_flight)?;
+                log::info!("Offscreen target created successfully");
+                Target::Offscreen(Box::new(offscreen))
             }
         };
-        log::info!("Device created succesfully");
 
-        let frames = match Self::create_frames_structs(&device) {
-            Ok(val) => val,
-            Err(err) => {
+        let resources = Self::create_frames_structs(&device, frames_in_flight)
+            .inspect_err(|err| {
                 log::error!("Failed to initialize frames data: {}", err);
-                panic!();
+            })
+            .and_then(|frames| {
+                let uploads = core::UploadManager::new(
+                    &device,
+                    config.staging_buffer_size,
+                    frames_in_flight,
+                )?;
+                let bindless = config
+                    .bindless
+                    .map(|desc| device.create_bindless_heap(&desc))
+                    .transpose()?;
+                Ok((frames, uploads, bindless))
+            });
+        let (frames, uploads, bindless) = match resources {
+            Ok(resources) => resources,
+            Err(err) => {
+                // the swapchain cleans up after itself, offscreen images go back to the device
+                if let Target::Offscreen(offscreen) = target {
+                    device.destroy_offscreen(*offscreen);
+                }
+                return Err(err);
             }
         };
 
-        Self {
+        Ok(Self {
             frame_number: 0,
             frames,
-            instance,
-            surface,
+            gpu_report: selection.report,
+            extent: config.extent,
+            last_submitted: None,
+            completed_frame: None,
+            recording: false,
+            capture: Capture::default(),
+            shaders: shaders::ShaderLibrary::new(config.shader_dir, config.hot_reload),
+            bindless,
+            descriptors: core::DescriptorAllocator::default(),
+            uploads: ManuallyDrop::new(uploads),
+            target,
             device,
-        }
+            instance,
+        })
     }
 
-    fn create_frames_structs(device: &core::Device) -> Result<[FrameData; 2], vk::Result> {
+    fn create_frames_structs(
+        device: &core::Device,
+        count: usize,
+    ) -> Result<Vec<FrameData>, RendererError> {
         // Init frames data
-        let mut frames: [FrameData; MAX_FRAMES_IN_FLIGHT] =
-            [FrameData::default(), FrameData::default()];
+        let mut frames: Vec<FrameData> = (0..count).map(|_| FrameData::default()).collect();
 
         for frame in &mut frames {
             let pool =
                 device.create_command_pool(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)?;
             let buffer = device.allocate_command_buffer(pool, vk::CommandBufferLevel::PRIMARY)?;
+            let upload_buffer =
+                device.allocate_command_buffer(pool, vk::CommandBufferLevel::PRIMARY)?;
             let swapchain_sem = device.create_semaphore(vk::SemaphoreCreateFlags::default())?;
             let render_sem = device.create_semaphore(vk::SemaphoreCreateFlags::default())?;
             let render_fen = device.create_fence(vk::FenceCreateFlags::SIGNALED)?;
 
             frame.pool = pool;
             frame.buffer = buffer;
+            frame.upload_buffer = upload_buffer;
             frame.render_fen = render_fen;
             frame.render_sem = render_sem;
             frame.swapchain_sem = swapchain_sem;
@@ -202,8 +395,697 @@ impl Renderer {
         Ok(frames)
     }
 
+    /// Call it whenever the window size changes, a zero size (minimized window) is allowed
+    /// and the swapchain is rebuilt once the window gets a valid size again
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+        self.extent = vk::Extent2D { width, height };
+        self.recreate_target()
+    }
+
+    fn recreate_target(&mut self) -> Result<(), RendererError> {
+        self.device.wait_idle();
+        let format = self.target_format();
+        match &mut self.target {
+            Target::Window { swapchain, surface } => {
+                swapchain
+                    .recreate(surface, self.extent)
+                    .map_err(RendererError::SwapchainCreation)?;
+            }
+            Target::Offscreen(offscreen) => {
+                offscreen.recreate(&self.device, self.extent, self.frames.len())?;
+                self.last_submitted = None;
+            }
+        }
+        if self.target_format() != format {
+            self.shaders.rebuild_all(&self.device, self.target_format());
+        }
+        Ok(())
+    }
+
+    /// Format of the images frames are drawn into
+    pub fn target_format(&self) -> vk::Format {
+        match &self.target {
+            Target::Window { swapchain, .. } => swapchain.format().format,
+            Target::Offscreen(offscreen) => offscreen.format(),
+        }
+    }
+
+    /// None for headless renderers
+    pub fn swapchain(&self) -> Option<&core::Swapchain> {
+        match &self.target {
+            Target::Window { swapchain, .. } => Some(swapchain),
+            Target::Offscreen(_) => None,
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        matches!(self.target, Target::Offscreen(_))
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Ranking and rejection reasons from the GPU selection done at creation
+    pub fn gpu_report(&self) -> &core::GpuReport {
+        &self.gpu_report
+    }
+
     fn get_current_frame(&self) -> &FrameData {
-        &self.frames[self.frame_number % MAX_FRAMES_IN_FLIGHT]
+        &self.frames[self.frame_number % self.frames.len()]
+    }
+
+    /// Waits for the current frame slot, acquires a target image and starts recording.
+    /// Returns None when there is nothing to draw on (minimized window or out of date
+    /// swapchain), just skip the frame and try again on the next one.
+    /// Fails while the previous frame was not passed to `end_frame`, a dropped `Frame`
+    /// leaves its command buffer recording and its acquired image never presented
+    pub fn begin_frame(&mut self) -> Result<Option<Frame>, RendererError> {
+        if self.recording {
+            return Err(RendererError::InvalidUsage(
+                "begin_frame called while the previous frame was not ended".to_owned(),
+            ));
+        }
+
+        if let Target::Window { swapchain, .. } = &self.target {
+            if swapchain.needs_recreate() {
+                self.recreate_target()?;
+            }
+        }
+
+        let slot = self.frame_number % self.frames.len();
+        let frame = self.get_current_frame();
+        let (buffer, swapchain_sem, render_fen) =
+            (frame.buffer, frame.swapchain_sem, frame.render_fen);
+
+        self.device.wait_fence(render_fen, u64::MAX)?;
+        self.frames[slot].descriptors.reset(&self.device)?;
+        // the fence covers every frame up to the one that last used this slot
+        let completed = self.frame_number.checked_sub(self.frames.len());
+        self.device
+            .advance_frame(self.frame_number as u64, completed.map(|n| n as u64));
+        if let Some(bindless) = &self.bindless {
+            bindless.advance_frame(self.frame_number as u64, completed.map(|n| n as u64));
+        }
+        if completed.is_some() {
+            self.completed_frame = completed;
+        }
+        self.reclaim_uploads();
+        self.shaders.poll(&self.device, self.target_format());
+
+        let (image_index, image, view, extent, format) = match &mut self.target {
+            Target::Window { swapchain, .. } => {
+                let Some(index) = swapchain.acquire_next_image(swapchain_sem, u64::MAX)? else {
+                    log::trace!("Swapchain out of date, skipping frame");
+                    return Ok(None);
+                };
+                (
+                    index,
+                    swapchain.images()[index as usize],
+                    swapchain.views()[index as usize],
+                    swapchain.extent(),
+                    swapchain.format().format,
+                )
+            }
+            Target::Offscreen(offscreen) => (
+                slot as u32,
+                offscreen.image(slot),
+                offscreen.view(slot),
+                offscreen.extent(),
+                offscreen.format(),
+            ),
+        };
+
+        self.device.reset_command_buffer(buffer)?;
+        self.device
+            .begin_command_buffer(buffer, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)?;
+        self.recording = true;
+
+        let mut frame = Frame {
+            device: self.device.handle().clone(),
+            slot,
+            image_index,
+            buffer,
+            image,
+            view,
+            // the acquire semaphore is waited on at this stage
+            state: core::ImageState {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags2::NONE,
+            },
+            extent,
+            format,
+            bound: None,
+        };
+        frame.transition(vk::ImageLayout::GENERAL);
+
+        Ok(Some(frame))
+    }
+
+    /// Ends recording, submits the frame and presents it, then advances to the next frame slot.
+    /// An out of date swapchain is not an error, it gets rebuilt on the next `begin_frame`
+    pub fn end_frame(&mut self, mut frame: Frame) -> Result<(), RendererError> {
+        let slot = self.frame_number % self.frames.len();
+        if frame.slot != slot {
+            return Err(RendererError::InvalidUsage(format!(
+                "frame of slot {} ended while slot {} is current",
+                frame.slot, slot
+            )));
+        }
+        // the frame is consumed either way, a failed submit must not block the next begin_frame
+        self.recording = false;
+
+        let data = self.get_current_frame();
+        let (swapchain_sem, render_sem, render_fen, upload_buffer) = (
+            data.swapchain_sem,
+            data.render_sem,
+            data.render_fen,
+            data.upload_buffer,
+        );
+
+        self.record_capture(&mut frame)?;
+
+        let mut buffers = vec![];
+        let mut wait = vec![];
+        if self.uploads.has_pending() {
+            self.device.reset_command_buffer(upload_buffer)?;
+            self.device.begin_command_buffer(
+                upload_buffer,
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            )?;
+            let transfer = self.uploads.record_frame(
+                &self.device,
+                slot,
+                upload_buffer,
+                self.frame_number as u64,
+            )?;
+            wait.extend(transfer.map(|sem| (sem, vk::PipelineStageFlags2::ALL_COMMANDS)));
+            self.device.end_command_buffer(upload_buffer)?;
+            buffers.push(upload_buffer);
+        }
+        buffers.push(frame.buffer);
+
+        match &mut self.target {
+            Target::Window { swapchain, .. } => {
+                frame.transition(vk::ImageLayout::PRESENT_SRC_KHR);
+                self.device.end_command_buffer(frame.buffer)?;
+
+                wait.push((
+                    swapchain_sem,
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                ));
+                // reset only right before the submit that signals it, a frame dropped or failing
+                // earlier would otherwise leave the next wait on this slot hanging forever
+                self.device.reset_fence(render_fen)?;
+                self.device.graphics_queue().submit(
+                    &buffers,
+                    &wait,
+                    &[(render_sem, vk::PipelineStageFlags2::ALL_GRAPHICS)],
+                    render_fen,
+                )?;
+
+                swapchain.present(
+                    self.device.present_queue(),
+                    frame.image_index,
+                    &[render_sem],
+                )?;
+            }
+            Target::Offscreen(offscreen) => {
+                if frame.state.layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+                    frame.transition(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+                }
+                offscreen.record_readback(frame.buffer, slot);
+                self.device.end_command_buffer(frame.buffer)?;
+                self.device.reset_fence(render_fen)?;
+                self.device
+                    .graphics_queue()
+                    .submit(&buffers, &wait, &[], render_fen)?;
+            }
+        }
+
+        self.last_submitted = Some(slot);
+        self.frame_number += 1;
+        Ok(())
+    }
+
+    /// Tightly packed RGBA8 pixels of the last frame ended on a headless renderer,
+    /// waits for it to finish on the gpu. None if no frame was rendered yet or
+    /// the renderer draws to a window
+    pub fn read_frame(&self) -> Result<Option<Vec<u8>>, RendererError> {
+        let (Target::Offscreen(offscreen), Some(slot)) = (&self.target, self.last_submitted) else {
+            return Ok(None);
+        };
+
+        self.device
+            .wait_fence(self.frames[slot].render_fen, u64::MAX)?;
+        Ok(Some(offscreen.read(slot)?))
+    }
+
+    /// Copies the target image of the next ended frame so it can be retrieved with `take_capture`.
+    /// Fails if the swapchain images can't be used as a transfer source
+    pub fn capture_next_frame(&mut self) -> Result<(), RendererError> {
+        if let Target::Window { swapchain, .. } = &self.target {
+            if !swapchain
+                .usage()
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            {
+                return Err(RendererError::Unsupported(
+                    "swapchain images can't be used as a transfer source".to_owned(),
+                ));
+            }
+        }
+        self.capture.requested = true;
+        Ok(())
+    }
+
+    fn record_capture(&mut self, frame: &mut Frame) -> Result<(), RendererError> {
+        if !self.capture.requested {
+            return Ok(());
+        }
+        self.capture.requested = false;
+
+        let matches = self.capture.readback.as_ref().is_some_and(|readback| {
+            readback.extent() == frame.extent && readback.format() == frame.format
+        });
+        if !matches {
+            // the old buffer may still be the destination of a frame in flight
+            self.wait_capture()?;
+            if let Some(readback) = self.capture.readback.take() {
+                self.device.destroy_readback(readback);
+            }
+            self.capture.readback = Some(self.device.create_readback(frame.extent, frame.format)?);
+        }
+
+        frame.transition(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        if let Some(readback) = &self.capture.readback {
+            readback.record_copy(frame.buffer, frame.image);
+        }
+        self.capture.pending = Some(self.frame_number);
+        Ok(())
+    }
+
+    /// Waits for the pending capture, if any, to be done on the gpu
+    fn wait_capture(&self) -> Result<(), RendererError> {
+        let Some(number) = self.capture.pending else {
+            return Ok(());
+        };
+        // frames up to `completed_frame` were waited on by begin_frame, their slot fence may
+        // already track a later frame
+        if self
+            .completed_frame
+            .is_none_or(|completed| number > completed)
+        {
+            let slot = number % self.frames.len();
+            self.device
+                .wait_fence(self.frames[slot].render_fen, u64::MAX)?;
+        }
+        Ok(())
+    }
+
+    /// Screenshot of the frame requested with `capture_next_frame`, waits for it to finish
+    /// on the gpu. None if no capture was requested or the frame wasn't ended yet
+    pub fn take_capture(&mut self) -> Result<Option<Screenshot>, RendererError> {
+        if self.capture.pending.is_none() {
+            return Ok(None);
+        }
+        self.wait_capture()?;
+        self.capture.pending = None;
+
+        let Some(readback) = &self.capture.readback else {
+            return Ok(None);
+        };
+        let data = readback.read()?;
+        Screenshot::from_raw(readback.extent(), readback.format(), data).map(Some)
+    }
+
+    /// Stages `data` for a copy to `dst` at `offset` bytes, the copy runs before the next
+    /// submitted frame unless flushed earlier. `dst` needs TRANSFER_DST usage
+    pub fn upload_buffer<T: bytemuck::Pod>(
+        &mut self,
+        dst: &core::Buffer,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<UploadTicket, RendererError> {
+        let bytes = bytemuck::cast_slice(data);
+        self.uploads.upload_buffer(&self.device, dst, offset, bytes)
+    }
+
+    /// Creates the mesh buffers and stages `vertices` and `indices` for them, the mesh is ready
+    /// for the next frame or right after `flush_uploads`. Empty `indices` draws the vertices in
+    /// order and empty `submeshes` draws the whole mesh at once
+    pub fn create_mesh<V: Vertex, I: MeshIndex>(
+        &mut self,
+        vertices: &[V],
+        indices: &[I],
+        submeshes: &[Submesh],
+    ) -> Result<Mesh, RendererError> {
+        let desc = MeshDesc::new::<V>(vertices.len() as u32)
+            .with_indices::<I>(indices.len() as u32)
+            .with_submeshes(submeshes);
+        let mesh = self.device.create_mesh(&desc)?;
+        let staged = self
+            .uploads
+            .upload_buffer(
+                &self.device,
+                mesh.vertex_buffer(),
+                0,
+                bytemuck::cast_slice(vertices),
+            )
+            .and_then(|_| match mesh.index_buffer() {
+                Some(buffer) => self
+                    .uploads
+                    .upload_buffer(&self.device, buffer, 0, bytemuck::cast_slice(indices))
+                    .map(|_| ()),
+                None => Ok(()),
+            });
+
+        match staged {
+            Ok(()) => Ok(mesh),
+            Err(err) => {
+                self.device.destroy_mesh(mesh);
+                Err(err)
+            }
+        }
+    }
+
+    /// Stages tightly packed texels for a region of `image`, like `upload_buffer`.
+    /// `upload` must target `image`, usually it comes from `Image::upload_levels`
+    pub fn upload_image(
+        &mut self,
+        image: &Image,
+        upload: ImageUpload,
+        data: &[u8],
+    ) -> Result<UploadTicket, RendererError> {
+        self.uploads.upload_image(&self.device, image, upload, data)
+    }
+
+    /// Decodes a PNG, JPEG or KTX2 file into a texture, see `create_texture`
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: &TextureOptions,
+    ) -> Result<Texture, RendererError> {
+        let data = TextureData::load(path, options.srgb)?;
+        self.create_texture(&data, options)
+    }
+
+    /// Uploads the texels through the staging ring, the texture is ready for the next frame
+    /// or right after `flush_uploads`. Missing mips are blitted on the gpu when the format
+    /// allows linear blits and box filtered on the cpu otherwise
+    pub fn create_texture(
+        &mut self,
+        data: &TextureData,
+        options: &TextureOptions,
+    ) -> Result<Texture, RendererError> {
+        data.validate()?;
+        let format = data.format;
+        if !self.device.supports_format(
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ) {
+            return Err(RendererError::UnsupportedFormat(format));
+        }
+
+        let mut data = Cow::Borrowed(data);
+        let mut blit_mips = false;
+        if options.mipmaps && data.levels.len() == 1 && core::mip_count(data.extent()) > 1 {
+            let blit = vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+            if !core::is_compressed(format)
+                && self
+                    .device
+                    .supports_format(format, vk::ImageTiling::OPTIMAL, blit)
+            {
+                blit_mips = true;
+            } else if let Err(err) = data.to_mut().generate_mips() {
+                // the base level alone is still a usable texture
+                log::warn!("Can't generate mips for a {:?} texture: {}", format, err);
+            }
+        }
+
+        let levels = if blit_mips {
+            core::mip_count(data.extent())
+        } else {
+            data.levels.len() as u32
+        };
+        let mut desc = ImageDesc::texture(data.width, data.height, format)
+            .with_dimension(data.dimension)
+            .with_mip_levels(levels);
+        if blit_mips {
+            desc = desc.with_usage(vk::ImageUsageFlags::TRANSFER_SRC);
+        }
+
+        let mut texture = self.device.create_texture(&desc, &options.sampler)?;
+        let image = texture.image_mut();
+        let staged = if blit_mips {
+            let upload = image.upload_levels(1, vk::ImageLayout::TRANSFER_DST_OPTIMAL)[0];
+            self.uploads
+                .upload_image(&self.device, image, upload, &data.levels[0])
+                .map(|_| {
+                    self.uploads.generate_mips(core::MipGeneration {
+                        image: image.handle(),
+                        extent: image.extent(),
+                        aspect: image.aspect(),
+                        mip_levels: levels,
+                        array_layers: image.array_layers(),
+                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    });
+                    image.assume_state(ImageState {
+                        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        stage: vk::PipelineStageFlags2::NONE,
+                        access: vk::AccessFlags2::NONE,
+                    });
+                })
+        } else {
+            image
+                .upload_levels(levels, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .into_iter()
+                .zip(&data.levels)
+                .try_for_each(|(upload, texels)| {
+                    self.uploads
+                        .upload_image(&self.device, image, upload, texels)
+                        .map(|_| ())
+                })
+        };
+
+        match staged {
+            Ok(()) => Ok(texture),
+            Err(err) => {
+                self.device.destroy_texture(texture);
+                Err(err)
+            }
+        }
+    }
+
+    /// Submits the pending uploads on their own and waits for them
+    pub fn flush_uploads(&mut self) -> Result<(), RendererError> {
+        self.uploads.flush_immediate(&self.device)?;
+        self.reclaim_uploads();
+        Ok(())
+    }
+
+    /// True once the copy is done on the gpu, never blocks
+    pub fn is_upload_complete(&mut self, ticket: UploadTicket) -> bool {
+        self.reclaim_uploads();
+        match self.uploads.submission(ticket) {
+            Some(core::Submission::Done) => true,
+            Some(core::Submission::Frame(frame)) => self.is_frame_done(frame as usize),
+            None => false,
+        }
+    }
+
+    /// Blocks until the copy is done, flushing it first if it wasn't submitted yet
+    pub fn wait_upload(&mut self, ticket: UploadTicket) -> Result<(), RendererError> {
+        match self.uploads.submission(ticket) {
+            Some(core::Submission::Done) => {}
+            Some(core::Submission::Frame(frame)) => {
+                if !self.is_frame_done(frame as usize) {
+                    let slot = frame as usize % self.frames.len();
+                    self.device
+                        .wait_fence(self.frames[slot].render_fen, u64::MAX)?;
+                }
+            }
+            None => self.uploads.flush_immediate(&self.device)?,
+        }
+        self.reclaim_uploads();
+        Ok(())
+    }
+
+    /// Records `record` into a one-off command buffer, submits it and blocks until it is done
+    pub fn immediate_submit<F>(&mut self, record: F) -> Result<(), RendererError>
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        Ok(self.uploads.immediate_submit(&self.device, record)?)
+    }
+
+    /// SPIR-V file or, with the shader-compiler feature, GLSL/WGSL source relative to the
+    /// shader directory. Watched for changes when hot reload is on
+    pub fn load_shader<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        defines: &[(&str, &str)],
+    ) -> Result<ShaderHandle, RendererError> {
+        self.shaders.load(&self.device, path.as_ref(), defines)
+    }
+
+    pub fn shader(&self, handle: ShaderHandle) -> &ShaderModule {
+        self.shaders.shader(handle)
+    }
+
+    /// Builds a pipeline from loaded shaders. `build` is kept to rebuild it whenever one of
+    /// the shaders is reloaded or the target format changes
+    pub fn create_pipeline<F>(
+        &mut self,
+        shaders: &[ShaderHandle],
+        build: F,
+    ) -> Result<PipelineHandle, RendererError>
+    where
+        F: Fn(&PipelineContext) -> Result<Pipeline, RendererError> + 'static,
+    {
+        let format = self.target_format();
+        self.shaders
+            .create_pipeline(&self.device, format, shaders, Box::new(build))
+    }
+
+    /// The current version of the pipeline, fetch it every frame as reloads replace it
+    pub fn pipeline(&self, handle: PipelineHandle) -> &Pipeline {
+        self.shaders.pipeline(handle)
+    }
+
+    /// Builds the pipeline described by `desc` and keeps it under `key` until the renderer is
+    /// dropped. It is rebuilt like the ones from `create_pipeline`
+    pub fn register_pipeline<K: Into<PipelineKey>>(
+        &mut self,
+        key: K,
+        desc: PipelineDesc,
+    ) -> Result<(), RendererError> {
+        let format = self.target_format();
+        self.shaders
+            .register(&self.device, format, key.into(), desc)
+    }
+
+    pub fn is_pipeline_registered<K: Into<PipelineKey>>(&self, key: K) -> bool {
+        self.shaders.is_registered(key.into())
+    }
+
+    /// The current version of the pipeline registered under `key`
+    pub fn registered_pipeline<K: Into<PipelineKey>>(
+        &self,
+        key: K,
+    ) -> Result<&Pipeline, RendererError> {
+        self.shaders.registered(key.into())
+    }
+
+    /// Binds the pipeline registered under `key` to the frame command buffer
+    pub fn bind_pipeline<K: Into<PipelineKey>>(
+        &self,
+        frame: &mut Frame,
+        key: K,
+    ) -> Result<(), RendererError> {
+        frame.bind_pipeline(self.shaders.registered(key.into())?);
+        Ok(())
+    }
+
+    /// Binds the pipeline registered under `key` and draws every submesh of `mesh` with it
+    pub fn draw_mesh<K: Into<PipelineKey>>(
+        &self,
+        frame: &mut Frame,
+        key: K,
+        mesh: &Mesh,
+    ) -> Result<(), RendererError> {
+        self.draw_mesh_instanced(frame, key, mesh, 1)
+    }
+
+    /// `draw_mesh` for `instance_count` instances, per instance buffers are bound on the frame
+    pub fn draw_mesh_instanced<K: Into<PipelineKey>>(
+        &self,
+        frame: &mut Frame,
+        key: K,
+        mesh: &Mesh,
+        instance_count: u32,
+    ) -> Result<(), RendererError> {
+        self.bind_pipeline(frame, key)?;
+        frame.draw_mesh_instanced(mesh, instance_count);
+        Ok(())
+    }
+
+    /// Set when the renderer was built with `RendererBuilder::bindless`. Indices freed through it
+    /// are recycled once the frames recorded so far are done
+    pub fn bindless(&self) -> Option<&BindlessHeap> {
+        self.bindless.as_ref()
+    }
+
+    /// Shared layout for these bindings, the same one pipelines get when their layout is
+    /// derived from reflection. See `Device::descriptor_set_layout`
+    pub fn descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, RendererError> {
+        self.device.descriptor_set_layout(bindings)
+    }
+
+    /// Set living as long as the renderer, for resources that don't change every frame
+    pub fn allocate_descriptor_set(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, RendererError> {
+        self.descriptors.allocate(&self.device, layout)
+    }
+
+    /// Set for `frame`, it is recycled once the frame is done on the gpu
+    pub fn allocate_frame_descriptor_set(
+        &mut self,
+        frame: &Frame,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, RendererError> {
+        self.frames[frame.slot]
+            .descriptors
+            .allocate(&self.device, layout)
+    }
+
+    /// Watches the loaded shaders, reloading them and their pipelines between frames
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.shaders.set_hot_reload(enabled);
+    }
+
+    pub fn is_hot_reload_enabled(&self) -> bool {
+        self.shaders.hot_reload()
+    }
+
+    /// Only valid for frames already submitted
+    fn is_frame_done(&self, frame: usize) -> bool {
+        if self
+            .completed_frame
+            .is_some_and(|completed| frame <= completed)
+        {
+            return true;
+        }
+        // a later frame on the same slot only resets the fence after waiting for this one
+        let slot = frame % self.frames.len();
+        self.device
+            .is_fence_signaled(self.frames[slot].render_fen)
+            .unwrap_or(false)
+    }
+
+    fn reclaim_uploads(&mut self) {
+        let done: Vec<usize> = (0..self.frame_number)
+            .rev()
+            .take(self.frames.len())
+            .filter(|frame| self.is_frame_done(*frame))
+            .collect();
+        let completed = self.completed_frame;
+        self.uploads.reclaim(|frame| {
+            let frame = frame as usize;
+            completed.is_some_and(|completed| frame <= completed) || done.contains(&frame)
+        });
+    }
+
+    pub fn frame_number(&self) -> usize {
+        self.frame_number
     }
 }
 
@@ -212,7 +1094,23 @@ impl Drop for Renderer {
         log::trace!("Destroying Renderer");
         self.device.wait_idle();
 
-        for frame in &self.frames {
+        self.shaders.destroy(&self.device);
+        if let Some(bindless) = self.bindless.take() {
+            self.device.destroy_bindless_heap(bindless);
+        }
+        std::mem::take(&mut self.descriptors).destroy(&self.device);
+        if let Some(readback) = self.capture.readback.take() {
+            self.device.destroy_readback(readback);
+        }
+        if let Target::Offscreen(offscreen) = &mut self.target {
+            self.device
+                .destroy_offscreen(std::mem::take(offscreen.as_mut()));
+        }
+        let uploads = unsafe { ManuallyDrop::take(&mut self.uploads) };
+        uploads.destroy(&self.device);
+
+        for frame in &mut self.frames {
+            std::mem::take(&mut frame.descriptors).destroy(&self.device);
             self.device.destroy_command_pool(frame.pool);
             self.device.destroy_semaphore(frame.swapchain_sem);
             self.device.destroy_semaphore(frame.render_sem);
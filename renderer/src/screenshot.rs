@@ -0,0 +1,76 @@
+use std::{fs::File, io::BufWriter, io::Write, path::Path};
+
+use ash::vk;
+
+use crate::RendererError;
+
+/// Frame read back from the gpu, tightly packed RGBA8 rows from top to bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Converts the raw texels of an image of `format` to RGBA8.
+    /// sRGB and UNORM texels are kept as they are, that's what ends up on the screen.
+    /// `data` must hold exactly 4 bytes per texel of `extent`
+    pub fn from_raw(
+        extent: vk::Extent2D,
+        format: vk::Format,
+        mut data: Vec<u8>,
+    ) -> Result<Self, RendererError> {
+        let swizzle = match format {
+            vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_UNORM_PACK32
+            | vk::Format::A8B8G8R8_SRGB_PACK32 => false,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            _ => return Err(RendererError::UnsupportedFormat(format)),
+        };
+
+        let size = (extent.width as usize)
+            .checked_mul(extent.height as usize)
+            .and_then(|texels| texels.checked_mul(4));
+        if size != Some(data.len()) {
+            return Err(RendererError::InvalidUsage(format!(
+                "{} bytes of pixel data don't match a {}x{} image",
+                data.len(),
+                extent.width,
+                extent.height
+            )));
+        }
+
+        if swizzle {
+            for texel in data.chunks_exact_mut(4) {
+                texel.swap(0, 2);
+            }
+        }
+
+        Ok(Self {
+            width: extent.width,
+            height: extent.height,
+            pixels: data,
+        })
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), RendererError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), RendererError> {
+        let file = File::create(path.as_ref())?;
+        self.write_png(BufWriter::new(file))?;
+        log::info!("Screenshot saved to {}", path.as_ref().display());
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+use naga::{back::spv, front, valid};
+
+use crate::{core::Spirv, RendererError};
+
+/// Language of a shader source, GLSL files hold a single stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Glsl(vk::ShaderStageFlags),
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    /// `.vert`, `.frag` and `.comp` are GLSL, `.wgsl` is WGSL
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let language = match path.extension()?.to_str()? {
+            "vert" => Self::Glsl(vk::ShaderStageFlags::VERTEX),
+            "frag" => Self::Glsl(vk::ShaderStageFlags::FRAGMENT),
+            "comp" => Self::Glsl(vk::ShaderStageFlags::COMPUTE),
+            "wgsl" => Self::Wgsl,
+            _ => return None,
+        };
+        Some(language)
+    }
+}
+
+/// Error or warning pointing at the file it comes from, included files included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    pub file: PathBuf,
+    /// 1 based, 0 when the error has no location
+    pub line: u32,
+    /// 1 based, 0 when the error has no location
+    pub column: u32,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file.display(),
+                self.line,
+                self.column,
+                self.message
+            )
+        }
+    }
+}
+
+/// SPIR-V ready for `Device::create_shader_module`, with every file it was built from
+#[derive(Debug, Clone)]
+pub struct CompiledShader {
+    pub spirv: Spirv,
+    /// The compiled file first, then its includes
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Compiles GLSL and WGSL to SPIR-V at runtime.
+///
+/// `#include "file"` is looked up next to the including file, then in the shader directory,
+/// `#include <file>` only in the shader directory. `#pragma once` skips repeated includes.
+/// Defines are handed to the GLSL preprocessor, WGSL gets `#ifdef`, `#ifndef`, `#else`,
+/// `#endif` and whole word substitution of the defines with a value
+#[derive(Debug, Clone)]
+pub struct ShaderCompiler {
+    root: PathBuf,
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderCompiler {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            defines: BTreeMap::new(),
+        }
+    }
+
+    /// Define used by every compilation, on top of the per call ones
+    pub fn with_define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// `path` is relative to the shader directory, the language comes from its extension
+    pub fn compile_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        defines: &[(&str, &str)],
+    ) -> Result<CompiledShader, RendererError> {
+        let path = self.root.join(path);
+        let Some(language) = ShaderLanguage::from_path(&path) else {
+            return Err(RendererError::Unsupported(format!(
+                "shader language of {}",
+                path.display()
+            )));
+        };
+        let source = std::fs::read_to_string(&path)?;
+        self.compile(&path, &source, language, defines)
+    }
+
+    /// Like `compile_file` for a source already in memory, `path` names it in diagnostics
+    /// and anchors its relative includes
+    pub fn compile_source<P: AsRef<Path>>(
+        &self,
+        path: P,
+        source: &str,
+        language: ShaderLanguage,
+        defines: &[(&str, &str)],
+    ) -> Result<CompiledShader, RendererError> {
+        self.compile(&self.root.join(path), source, language, defines)
+    }
+
+    fn compile(
+        &self,
+        path: &Path,
+        source: &str,
+        language: ShaderLanguage,
+        defines: &[(&str, &str)],
+    ) -> Result<CompiledShader, RendererError> {
+        let mut all_defines = self.defines.clone();
+        for (name, value) in defines {
+            all_defines.insert(name.to_string(), value.to_string());
+        }
+
+        let mut expanded = Expanded::new(self, language == ShaderLanguage::Wgsl, &all_defines);
+        expanded.expand(path, source)?;
+        let fail = |diagnostics| Err(RendererError::ShaderCompilation(diagnostics));
+
+        let module = match language {
+            ShaderLanguage::Glsl(stage) => {
+                let Some(stage) = naga_stage(stage) else {
+                    return Err(RendererError::Unsupported(format!(
+                        "compiling {:?} shaders",
+                        stage
+                    )));
+                };
+                let mut options = front::glsl::Options::from(stage);
+                options.defines.extend(all_defines.clone());
+                match front::glsl::Frontend::default().parse(&options, &expanded.text) {
+                    Ok(module) => module,
+                    Err(errors) => {
+                        return fail(
+                            errors
+                                .errors
+                                .iter()
+                                .map(|err| {
+                                    let location = err
+                                        .meta
+                                        .is_defined()
+                                        .then(|| err.meta.location(&expanded.text));
+                                    expanded.diagnostic(location, err.kind.to_string())
+                                })
+                                .collect(),
+                        )
+                    }
+                }
+            }
+            ShaderLanguage::Wgsl => match front::wgsl::parse_str(&expanded.text) {
+                Ok(module) => module,
+                Err(err) => {
+                    let location = err.location(&expanded.text);
+                    let mut message = err.message().to_owned();
+                    for (_, label) in err.labels().filter(|(_, label)| !label.is_empty()) {
+                        message.push_str(&format!(" ({})", label));
+                    }
+                    return fail(vec![expanded.diagnostic(location, message)]);
+                }
+            },
+        };
+
+        // the device decides what it supports when the module gets created
+        let info =
+            match valid::Validator::new(valid::ValidationFlags::all(), valid::Capabilities::all())
+                .validate(&module)
+            {
+                Ok(info) => info,
+                Err(err) => {
+                    let location = err.location(&expanded.text);
+                    let mut message = err.as_inner().to_string();
+                    let mut source = std::error::Error::source(err.as_inner());
+                    while let Some(inner) = source {
+                        message.push_str(&format!(": {}", inner));
+                        source = inner.source();
+                    }
+                    return fail(vec![expanded.diagnostic(location, message)]);
+                }
+            };
+
+        // names are kept for reflection, the coordinate space is left as written
+        let options = spv::Options {
+            lang_version: (1, 3),
+            flags: spv::WriterFlags::DEBUG | spv::WriterFlags::LABEL_VARYINGS,
+            ..Default::default()
+        };
+        let words = match spv::write_vec(&module, &info, &options, None) {
+            Ok(words) => words,
+            Err(err) => return fail(vec![expanded.diagnostic(None, err.to_string())]),
+        };
+
+        Ok(CompiledShader {
+            spirv: Spirv::from_words(words)?,
+            dependencies: expanded.files,
+        })
+    }
+}
+
+fn naga_stage(stage: vk::ShaderStageFlags) -> Option<naga::ShaderStage> {
+    match stage {
+        vk::ShaderStageFlags::VERTEX => Some(naga::ShaderStage::Vertex),
+        vk::ShaderStageFlags::FRAGMENT => Some(naga::ShaderStage::Fragment),
+        vk::ShaderStageFlags::COMPUTE => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+/// Source with its includes pasted in, remembering where every line comes from
+struct Expanded<'a> {
+    compiler: &'a ShaderCompiler,
+    /// WGSL has no preprocessor, conditionals and defines are handled here
+    preprocess: bool,
+    defines: &'a BTreeMap<String, String>,
+    text: String,
+    /// File index and 1 based line of each line of `text`
+    lines: Vec<(usize, u32)>,
+    files: Vec<PathBuf>,
+    once: HashSet<PathBuf>,
+    stack: Vec<PathBuf>,
+}
+
+impl<'a> Expanded<'a> {
+    fn new(
+        compiler: &'a ShaderCompiler,
+        preprocess: bool,
+        defines: &'a BTreeMap<String, String>,
+    ) -> Self {
+        Self {
+            compiler,
+            preprocess,
+            defines,
+            text: String::new(),
+            lines: vec![],
+            files: vec![],
+            once: HashSet::new(),
+            stack: vec![],
+        }
+    }
+
+    fn expand(&mut self, path: &Path, source: &str) -> Result<(), RendererError> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let file = match self.files.iter().position(|file| *file == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path.clone());
+                self.files.len() - 1
+            }
+        };
+        self.stack.push(path.clone());
+
+        // whether each enclosing conditional block is active
+        let mut conditionals: Vec<bool> = vec![];
+        for (index, line) in source.lines().enumerate() {
+            let number = index as u32 + 1;
+            let error = |message: String| {
+                RendererError::ShaderCompilation(vec![ShaderDiagnostic {
+                    file: path.clone(),
+                    line: number,
+                    column: 1,
+                    message,
+                }])
+            };
+            let active = conditionals.iter().all(|active| *active);
+            let directive = line.trim_start().strip_prefix('#').map(str::trim_start);
+
+            if let (true, Some(directive)) = (self.preprocess, directive) {
+                let mut words = directive.split_whitespace();
+                let keyword = words.next().unwrap_or_default();
+                let name = words.next().unwrap_or_default();
+                let handled = match keyword {
+                    "ifdef" => {
+                        conditionals.push(self.defines.contains_key(name));
+                        true
+                    }
+                    "ifndef" => {
+                        conditionals.push(!self.defines.contains_key(name));
+                        true
+                    }
+                    "else" => {
+                        let Some(last) = conditionals.last_mut() else {
+                            return Err(error("#else without #ifdef".to_owned()));
+                        };
+                        *last = !*last;
+                        true
+                    }
+                    "endif" => {
+                        if conditionals.pop().is_none() {
+                            return Err(error("#endif without #ifdef".to_owned()));
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                if handled {
+                    self.push_line(file, number, "");
+                    continue;
+                }
+            }
+            if !active {
+                self.push_line(file, number, "");
+                continue;
+            }
+
+            match directive {
+                Some(directive) if directive.starts_with("include") => {
+                    let target = directive["include".len()..].trim();
+                    let (name, local) = if let Some(name) =
+                        target.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+                    {
+                        (name, true)
+                    } else if let Some(name) =
+                        target.strip_prefix('<').and_then(|t| t.strip_suffix('>'))
+                    {
+                        (name, false)
+                    } else {
+                        return Err(error(format!("malformed include {}", target)));
+                    };
+
+                    let Some(include) = self.resolve(&path, name, local) else {
+                        return Err(error(format!("can't find include \"{}\"", name)));
+                    };
+                    let include = include.canonicalize().unwrap_or(include);
+                    if self.stack.contains(&include) {
+                        return Err(error(format!("\"{}\" includes itself", name)));
+                    }
+                    if !self.once.contains(&include) {
+                        let source = std::fs::read_to_string(&include)
+                            .map_err(|err| error(format!("can't read \"{}\": {}", name, err)))?;
+                        self.expand(&include, &source)?;
+                    }
+                }
+                Some(directive) if directive.split_whitespace().eq(["pragma", "once"]) => {
+                    self.once.insert(path.clone());
+                    self.push_line(file, number, "");
+                }
+                _ if self.preprocess => {
+                    let line = self.substitute(line);
+                    self.push_line(file, number, &line);
+                }
+                _ => self.push_line(file, number, line),
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(RendererError::ShaderCompilation(vec![ShaderDiagnostic {
+                file: path,
+                line: 0,
+                column: 0,
+                message: "#ifdef without #endif".to_owned(),
+            }]));
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn resolve(&self, from: &Path, name: &str, local: bool) -> Option<PathBuf> {
+        let local = local
+            .then(|| from.parent().map(|dir| dir.join(name)))
+            .flatten();
+        local
+            .into_iter()
+            .chain([self.compiler.root.join(name)])
+            .find(|path| path.is_file())
+    }
+
+    fn push_line(&mut self, file: usize, line: u32, text: &str) {
+        self.text.push_str(text);
+        self.text.push('\n');
+        self.lines.push((file, line));
+    }
+
+    /// Replaces whole identifiers matching a define that has a value
+    fn substitute(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut word = String::new();
+        for c in line.chars().chain(['\n']) {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                word.push(c);
+                continue;
+            }
+            match self.defines.get(&word) {
+                Some(value) if !value.is_empty() => out.push_str(value),
+                _ => out.push_str(&word),
+            }
+            word.clear();
+            if c != '\n' {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    /// Maps a location in the expanded text back to the file and line it was pasted from
+    fn diagnostic(
+        &self,
+        location: Option<naga::SourceLocation>,
+        message: String,
+    ) -> ShaderDiagnostic {
+        let origin = location.and_then(|location| {
+            let (file, line) = self.lines.get(location.line_number as usize - 1)?;
+            Some((*file, *line, location.line_position))
+        });
+        match origin {
+            Some((file, line, column)) => ShaderDiagnostic {
+                file: self.files[file].clone(),
+                line,
+                column,
+                message,
+            },
+            None => ShaderDiagnostic {
+                file: self.files[0].clone(),
+                line: 0,
+                column: 0,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory with the given files written in it
+    fn shader_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("renderer-shaders-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, source) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn diagnostics(result: Result<CompiledShader, RendererError>) -> Vec<ShaderDiagnostic> {
+        match result {
+            Err(RendererError::ShaderCompilation(diagnostics)) => diagnostics,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("compilation succeeded"),
+        }
+    }
+
+    #[test]
+    fn compiles_glsl_with_includes_and_defines() {
+        let dir = shader_dir(
+            "glsl",
+            &[
+                (
+                    "common/color.glsl",
+                    "#pragma once\nvec4 tint(vec4 color) { return color * TINT; }\n",
+                ),
+                (
+                    "lit.frag",
+                    "#version 450\n#include \"common/color.glsl\"\n#include <common/color.glsl>\n\
+                     layout(location = 0) out vec4 color;\n\
+                     void main() {\n#ifdef RED\n    color = tint(vec4(1.0, 0.0, 0.0, 1.0));\n\
+                     #else\n    color = tint(vec4(1.0));\n#endif\n}\n",
+                ),
+            ],
+        );
+        let compiler = ShaderCompiler::new(&dir).with_define("TINT", "0.5");
+
+        let shader = compiler.compile_file("lit.frag", &[("RED", "")]).unwrap();
+        let reflection = shader.spirv.reflect().unwrap();
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(shader.dependencies.len(), 2);
+        assert!(shader.dependencies[1].ends_with("common/color.glsl"));
+    }
+
+    #[test]
+    fn compiles_wgsl_permutations() {
+        let dir = shader_dir(
+            "wgsl",
+            &[
+                (
+                    "lib.wgsl",
+                    "fn scale(x: f32) -> f32 {\n#ifdef DOUBLE\n    return x * 2.0;\n#else\n    return x;\n#endif\n}\n",
+                ),
+                (
+                    "fill.wgsl",
+                    "#include \"lib.wgsl\"\n\
+                     @group(0) @binding(0) var<storage, read_write> data: array<f32>;\n\
+                     @compute @workgroup_size(GROUP)\n\
+                     fn main(@builtin(global_invocation_id) id: vec3<u32>) {\n\
+                         data[id.x] = scale(data[id.x]);\n}\n",
+                ),
+            ],
+        );
+        let compiler = ShaderCompiler::new(&dir);
+
+        for defines in [&[("GROUP", "32")][..], &[("GROUP", "32"), ("DOUBLE", "")]] {
+            let shader = compiler.compile_file("fill.wgsl", defines).unwrap();
+            let reflection = shader.spirv.reflect().unwrap();
+            assert_eq!(
+                reflection.entry_point("main").unwrap().workgroup_size,
+                Some([32, 1, 1])
+            );
+        }
+    }
+
+    #[test]
+    fn reports_errors_at_the_included_line() {
+        let dir = shader_dir(
+            "errors",
+            &[
+                (
+                    "broken.wgsl",
+                    "fn helper() -> f32 {\n    return undefined_name;\n}\n",
+                ),
+                (
+                    "main.wgsl",
+                    "// comment\n#include \"broken.wgsl\"\n@fragment\nfn main() {}\n",
+                ),
+                ("missing.wgsl", "\n\n#include \"nowhere.wgsl\"\n"),
+            ],
+        );
+        let compiler = ShaderCompiler::new(&dir);
+
+        let errors = diagnostics(compiler.compile_file("main.wgsl", &[]));
+        assert!(errors[0].file.ends_with("broken.wgsl"));
+        assert_eq!(errors[0].line, 2);
+        assert!(errors[0].to_string().contains("broken.wgsl:2:"));
+
+        let errors = diagnostics(compiler.compile_file("missing.wgsl", &[]));
+        assert_eq!(errors[0].line, 3);
+        assert!(errors[0].message.contains("nowhere.wgsl"));
+
+        let errors = diagnostics(compiler.compile_source(
+            "inline.frag",
+            "#version 450\nvoid main() {\n    float x = ;\n}\n",
+            ShaderLanguage::Glsl(vk::ShaderStageFlags::FRAGMENT),
+            &[],
+        ));
+        assert!(errors[0].file.ends_with("inline.frag"));
+        assert_eq!(errors[0].line, 3);
+    }
+}
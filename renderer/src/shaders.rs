@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use ash::vk;
+
+use crate::{
+    core::{Device, GraphicsPipelineBuilder, Pipeline, ShaderModule, Spirv},
+    RendererError,
+};
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Shader loaded through `Renderer::load_shader`, stays valid across reloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(usize);
+
+/// Pipeline created through `Renderer::create_pipeline`, stays valid across rebuilds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineHandle(usize);
+
+/// Key a pipeline is registered under with `Renderer::register_pipeline`, clients usually
+/// turn their own enum or constants into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PipelineKey(pub u64);
+
+impl From<u64> for PipelineKey {
+    fn from(key: u64) -> Self {
+        Self(key)
+    }
+}
+
+/// What a registered pipeline is built and rebuilt from
+pub struct PipelineDesc {
+    shaders: Vec<ShaderHandle>,
+    build: Box<PipelineBuild>,
+}
+
+impl PipelineDesc {
+    pub fn new<F>(shaders: &[ShaderHandle], build: F) -> Self
+    where
+        F: Fn(&PipelineContext) -> Result<Pipeline, RendererError> + 'static,
+    {
+        Self {
+            shaders: shaders.to_vec(),
+            build: Box::new(build),
+        }
+    }
+
+    /// Graphics pipeline described by `describe`, which gets the shaders and the current
+    /// target format every time the pipeline is built
+    pub fn graphics<F>(shaders: &[ShaderHandle], describe: F) -> Self
+    where
+        F: for<'a> Fn(&PipelineContext<'a>) -> GraphicsPipelineBuilder<'a> + 'static,
+    {
+        Self::new(shaders, move |context| {
+            describe(context).build(context.device)
+        })
+    }
+}
+
+/// What a pipeline gets rebuilt from, the shaders are in the order they were given
+pub struct PipelineContext<'a> {
+    pub device: &'a Device,
+    pub shaders: Vec<&'a ShaderModule>,
+    /// Format of the images frames are drawn into
+    pub color_format: vk::Format,
+}
+
+pub(crate) type PipelineBuild = dyn Fn(&PipelineContext) -> Result<Pipeline, RendererError>;
+
+struct ShaderEntry {
+    path: PathBuf,
+    defines: Vec<(String, String)>,
+    module: ShaderModule,
+    /// Every file the module was built from
+    files: Vec<WatchedFile>,
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    /// Last seen modification time
+    modified: Option<SystemTime>,
+}
+
+struct PipelineEntry {
+    shaders: Vec<ShaderHandle>,
+    build: Box<PipelineBuild>,
+    pipeline: Pipeline,
+}
+
+/// Shaders and the pipelines built from them. With hot reload on, changed files are
+/// recompiled between frames and every dependent pipeline is rebuilt, a failure keeps the
+/// previous module or pipeline in use
+pub(crate) struct ShaderLibrary {
+    root: PathBuf,
+    #[cfg(feature = "shader-compiler")]
+    compiler: crate::ShaderCompiler,
+    shaders: Vec<ShaderEntry>,
+    pipelines: Vec<PipelineEntry>,
+    registry: HashMap<PipelineKey, PipelineHandle>,
+    hot_reload: bool,
+    last_poll: Instant,
+}
+
+impl ShaderLibrary {
+    pub fn new(root: PathBuf, hot_reload: bool) -> Self {
+        Self {
+            #[cfg(feature = "shader-compiler")]
+            compiler: crate::ShaderCompiler::new(&root),
+            root,
+            shaders: vec![],
+            pipelines: vec![],
+            registry: HashMap::new(),
+            hot_reload,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.hot_reload = enabled;
+    }
+
+    pub fn hot_reload(&self) -> bool {
+        self.hot_reload
+    }
+
+    pub fn load(
+        &mut self,
+        device: &Device,
+        path: &Path,
+        defines: &[(&str, &str)],
+    ) -> Result<ShaderHandle, RendererError> {
+        let defines: Vec<_> = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let (module, files) = self.build_module(device, path, &defines)?;
+        self.shaders.push(ShaderEntry {
+            path: path.to_owned(),
+            defines,
+            module,
+            files,
+        });
+        Ok(ShaderHandle(self.shaders.len() - 1))
+    }
+
+    pub fn shader(&self, handle: ShaderHandle) -> &ShaderModule {
+        &self.shaders[handle.0].module
+    }
+
+    pub fn create_pipeline(
+        &mut self,
+        device: &Device,
+        color_format: vk::Format,
+        shaders: &[ShaderHandle],
+        build: Box<PipelineBuild>,
+    ) -> Result<PipelineHandle, RendererError> {
+        let pipeline = build(&self.context(device, color_format, shaders))?;
+        self.pipelines.push(PipelineEntry {
+            shaders: shaders.to_vec(),
+            build,
+            pipeline,
+        });
+        Ok(PipelineHandle(self.pipelines.len() - 1))
+    }
+
+    pub fn pipeline(&self, handle: PipelineHandle) -> &Pipeline {
+        &self.pipelines[handle.0].pipeline
+    }
+
+    pub fn register(
+        &mut self,
+        device: &Device,
+        color_format: vk::Format,
+        key: PipelineKey,
+        desc: PipelineDesc,
+    ) -> Result<(), RendererError> {
+        if self.registry.contains_key(&key) {
+            return Err(RendererError::DuplicatePipeline(key));
+        }
+        let handle = self.create_pipeline(device, color_format, &desc.shaders, desc.build)?;
+        self.registry.insert(key, handle);
+        Ok(())
+    }
+
+    pub fn registered(&self, key: PipelineKey) -> Result<&Pipeline, RendererError> {
+        match self.registry.get(&key) {
+            Some(handle) => Ok(self.pipeline(*handle)),
+            None => Err(RendererError::UnknownPipeline(key)),
+        }
+    }
+
+    pub fn is_registered(&self, key: PipelineKey) -> bool {
+        self.registry.contains_key(&key)
+    }
+
+    /// Reloads the shaders whose files changed since the last poll, at most every
+    /// `POLL_INTERVAL`. Meant to run between frames, old pipelines are retired so the
+    /// frames in flight can keep using them
+    pub fn poll(&mut self, device: &Device, color_format: vk::Format) {
+        if !self.hot_reload || self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (index, entry) in self.shaders.iter_mut().enumerate() {
+            let mut modified = false;
+            for file in &mut entry.files {
+                let time = modified_time(&file.path);
+                if time != file.modified {
+                    file.modified = time;
+                    modified = true;
+                }
+            }
+            if modified {
+                changed.push(index);
+            }
+        }
+
+        let mut reloaded = vec![];
+        for index in changed {
+            let entry = &self.shaders[index];
+            match self.build_module(device, &entry.path, &entry.defines) {
+                Ok((module, files)) => {
+                    log::info!("Reloaded shader {}", entry.path.display());
+                    let entry = &mut self.shaders[index];
+                    device.destroy_shader_module(std::mem::replace(&mut entry.module, module));
+                    entry.files = files;
+                    reloaded.push(ShaderHandle(index));
+                }
+                // the times are already updated, the next save triggers a new attempt
+                Err(err) => log::error!("Shader {} not reloaded: {}", entry.path.display(), err),
+            }
+        }
+        if !reloaded.is_empty() {
+            self.rebuild(device, color_format, |entry| {
+                entry.shaders.iter().any(|shader| reloaded.contains(shader))
+            });
+        }
+    }
+
+    /// Rebuilds every pipeline, e.g. after the target format changed
+    pub fn rebuild_all(&mut self, device: &Device, color_format: vk::Format) {
+        self.rebuild(device, color_format, |_| true);
+    }
+
+    fn rebuild(
+        &mut self,
+        device: &Device,
+        color_format: vk::Format,
+        filter: impl Fn(&PipelineEntry) -> bool,
+    ) {
+        for index in 0..self.pipelines.len() {
+            let entry = &self.pipelines[index];
+            if !filter(entry) {
+                continue;
+            }
+            match (entry.build)(&self.context(device, color_format, &entry.shaders)) {
+                Ok(pipeline) => {
+                    let old = std::mem::replace(&mut self.pipelines[index].pipeline, pipeline);
+                    device.destroy_pipeline(old);
+                }
+                Err(err) => log::error!(
+                    "Pipeline {} not rebuilt, keeping the old one: {}",
+                    index,
+                    err
+                ),
+            }
+        }
+    }
+
+    fn context<'a>(
+        &'a self,
+        device: &'a Device,
+        color_format: vk::Format,
+        shaders: &[ShaderHandle],
+    ) -> PipelineContext<'a> {
+        PipelineContext {
+            device,
+            shaders: shaders.iter().map(|handle| self.shader(*handle)).collect(),
+            color_format,
+        }
+    }
+
+    /// SPIR-V files are loaded as they are, anything else goes through the compiler
+    fn build_module(
+        &self,
+        device: &Device,
+        path: &Path,
+        defines: &[(String, String)],
+    ) -> Result<(ShaderModule, Vec<WatchedFile>), RendererError> {
+        let full_path = self.root.join(path);
+        let (spirv, files) = if full_path.extension().is_some_and(|ext| ext == "spv") {
+            (Spirv::load(&full_path)?, vec![full_path])
+        } else {
+            self.compile(path, defines)?
+        };
+        let module = device.create_shader_module(&spirv)?;
+        let files = files
+            .into_iter()
+            .map(|path| WatchedFile {
+                modified: modified_time(&path),
+                path,
+            })
+            .collect();
+        Ok((module, files))
+    }
+
+    #[cfg(feature = "shader-compiler")]
+    fn compile(
+        &self,
+        path: &Path,
+        defines: &[(String, String)],
+    ) -> Result<(Spirv, Vec<PathBuf>), RendererError> {
+        let defines: Vec<_> = defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let compiled = self.compiler.compile_file(path, &defines)?;
+        Ok((compiled.spirv, compiled.dependencies))
+    }
+
+    #[cfg(not(feature = "shader-compiler"))]
+    fn compile(
+        &self,
+        path: &Path,
+        _defines: &[(String, String)],
+    ) -> Result<(Spirv, Vec<PathBuf>), RendererError> {
+        Err(RendererError::Unsupported(format!(
+            "compiling {} needs the shader-compiler feature",
+            path.display()
+        )))
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.registry.clear();
+        for entry in self.pipelines.drain(..) {
+            device.destroy_pipeline(entry.pipeline);
+        }
+        for entry in self.shaders.drain(..) {
+            device.destroy_shader_module(entry.module);
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,540 @@
+use std::{io::Cursor, path::Path};
+
+use ash::vk;
+
+use crate::{
+    core::{block_info, layer_size, mip_count, mip_extent, ImageDimension, SamplerDesc},
+    RendererError,
+};
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// How a texture gets created from its texels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    /// PNG and JPEG texels are color data, KTX2 files carry their own format
+    pub srgb: bool,
+    /// Builds the full chain when the data only has the base level
+    pub mipmaps: bool,
+    pub sampler: SamplerDesc,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            srgb: true,
+            mipmaps: true,
+            sampler: SamplerDesc::linear(),
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Normal maps, masks and other non color data
+    pub fn linear() -> Self {
+        Self {
+            srgb: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// Decoded texels, each level holds its layers tightly packed one after the other
+#[derive(Debug, Clone)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub dimension: ImageDimension,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn rgba8(width: u32, height: u32, pixels: Vec<u8>, srgb: bool) -> Self {
+        Self {
+            width,
+            height,
+            format: rgba8_format(srgb),
+            dimension: ImageDimension::D2,
+            levels: vec![pixels],
+        }
+    }
+
+    /// PNG, JPEG or KTX2 file, told apart by their signature
+    pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> Result<Self, RendererError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_memory(&bytes, srgb)
+    }
+
+    pub fn from_memory(bytes: &[u8], srgb: bool) -> Result<Self, RendererError> {
+        if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+            Self::from_png(bytes, srgb)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Self::from_jpeg(bytes, srgb)
+        } else if bytes.starts_with(&KTX2_MAGIC) {
+            Self::from_ktx2(bytes)
+        } else {
+            Err(RendererError::Unsupported(
+                "texture is neither PNG, JPEG nor KTX2".to_owned(),
+            ))
+        }
+    }
+
+    /// Any color type and bit depth, converted to RGBA8
+    pub fn from_png(bytes: &[u8], srgb: bool) -> Result<Self, RendererError> {
+        let decode = |err: png::DecodingError| RendererError::TextureDecode(Box::new(err));
+
+        let mut decoder = png::Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(decode)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(decode)?;
+        buffer.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+            // expanded by normalize_to_color8, unless the decoder stops doing so
+            png::ColorType::Indexed => {
+                return Err(RendererError::TextureDecode(
+                    "indexed png left unexpanded by the decoder".into(),
+                ))
+            }
+        };
+        Ok(Self::rgba8(info.width, info.height, pixels, srgb))
+    }
+
+    /// Grayscale and RGB baseline or progressive JPEGs, converted to RGBA8
+    pub fn from_jpeg(bytes: &[u8], srgb: bool) -> Result<Self, RendererError> {
+        let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(bytes));
+        let data = decoder
+            .decode()
+            .map_err(|err| RendererError::TextureDecode(Box::new(err)))?;
+        let info = decoder.info().ok_or_else(|| {
+            RendererError::TextureDecode("jpeg decoder reported no image info".into())
+        })?;
+
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            jpeg_decoder::PixelFormat::L8 => data.iter().flat_map(|l| [*l, *l, *l, 255]).collect(),
+            // big endian, the high byte is enough for a color texture
+            jpeg_decoder::PixelFormat::L16 => data
+                .chunks_exact(2)
+                .flat_map(|l| [l[0], l[0], l[0], 255])
+                .collect(),
+            jpeg_decoder::PixelFormat::CMYK32 => {
+                return Err(RendererError::Unsupported("CMYK jpeg textures".to_owned()))
+            }
+        };
+        Ok(Self::rgba8(
+            info.width as u32,
+            info.height as u32,
+            pixels,
+            srgb,
+        ))
+    }
+
+    /// 2D, array and cubemap containers with every level they hold, BCn included.
+    /// Supercompressed and Basis Universal files are not handled
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, RendererError> {
+        let reader =
+            ktx2::Reader::new(bytes).map_err(|err| RendererError::TextureDecode(Box::new(err)))?;
+        let header = reader.header();
+
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(RendererError::Unsupported(format!(
+                "{:?} supercompressed KTX2 textures",
+                scheme
+            )));
+        }
+        let Some(format) = header.format else {
+            return Err(RendererError::Unsupported(
+                "KTX2 textures without a vulkan format (Basis Universal)".to_owned(),
+            ));
+        };
+        if header.pixel_depth > 1 || header.pixel_height == 0 {
+            return Err(RendererError::Unsupported(
+                "1D and 3D KTX2 textures".to_owned(),
+            ));
+        }
+
+        let dimension = match (header.face_count, header.layer_count) {
+            (6, 0) => ImageDimension::Cube,
+            (6, layers) => ImageDimension::CubeArray(layers),
+            (1, 0) => ImageDimension::D2,
+            (1, layers) => ImageDimension::D2Array(layers),
+            (faces, _) => {
+                return Err(RendererError::TextureDecode(
+                    format!("KTX2 texture with {} faces", faces).into(),
+                ))
+            }
+        };
+
+        let data = Self {
+            width: header.pixel_width,
+            height: header.pixel_height,
+            format: vk::Format::from_raw(format.value() as i32),
+            dimension,
+            levels: reader.levels().map(|level| level.data.to_vec()).collect(),
+        };
+        data.validate()?;
+        Ok(data)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Checks the format is known and every level has the size its extent and layers call for
+    pub fn validate(&self) -> Result<(), RendererError> {
+        if block_info(self.format).is_none() {
+            return Err(RendererError::UnsupportedFormat(self.format));
+        }
+        let invalid = |message: String| Err(RendererError::TextureDecode(message.into()));
+        if self.width == 0 || self.height == 0 || self.dimension.array_layers() == 0 {
+            return invalid(format!(
+                "{}x{} texture with {} layers",
+                self.width,
+                self.height,
+                self.dimension.array_layers()
+            ));
+        }
+        if self.levels.is_empty() || self.levels.len() > mip_count(self.extent()) as usize {
+            return invalid(format!(
+                "{} mip levels for a {}x{} texture",
+                self.levels.len(),
+                self.width,
+                self.height
+            ));
+        }
+
+        let layers = u64::from(self.dimension.array_layers());
+        for (level, data) in self.levels.iter().enumerate() {
+            let extent = mip_extent(self.extent(), level as u32);
+            let expected =
+                layer_size(self.format, extent).and_then(|size| size.checked_mul(layers));
+            if expected != Some(data.len() as u64) {
+                return invalid(format!(
+                    "mip level {} holds {} bytes, {} expected",
+                    level,
+                    data.len(),
+                    expected.map_or("more than u64".to_owned(), |size| size.to_string())
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Box filters the base level down to 1x1, only for 8 bit RGBA data.
+    /// Fails with `UnsupportedFormat` if the format can't be filtered on the cpu,
+    /// the data is left untouched on failure
+    pub fn generate_mips(&mut self) -> Result<(), RendererError> {
+        self.validate()?;
+        let srgb = match self.format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB => true,
+            vk::Format::R8G8B8A8_UNORM | vk::Format::B8G8R8A8_UNORM => false,
+            _ => return Err(RendererError::UnsupportedFormat(self.format)),
+        };
+
+        // the base level fits in memory, so do the bytes of every smaller one
+        let layers = self.dimension.array_layers() as usize;
+        let layer_bytes = |extent: vk::Extent2D| extent.width as usize * extent.height as usize * 4;
+        self.levels.truncate(1);
+        for level in 1..mip_count(self.extent()) {
+            let src_extent = mip_extent(self.extent(), level - 1);
+            let dst_extent = mip_extent(self.extent(), level);
+            let src = &self.levels[level as usize - 1];
+
+            let mut dst = Vec::with_capacity(layers * layer_bytes(dst_extent));
+            for layer in src.chunks_exact(layer_bytes(src_extent)) {
+                downsample(layer, src_extent, dst_extent, srgb, &mut dst);
+            }
+            self.levels.push(dst);
+        }
+        Ok(())
+    }
+}
+
+fn rgba8_format(srgb: bool) -> vk::Format {
+    if srgb {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    }
+}
+
+/// Averages 2x2 texels, odd edges reuse the last row or column. Color channels of sRGB
+/// data are averaged in linear space
+fn downsample(
+    src: &[u8],
+    src_extent: vk::Extent2D,
+    dst_extent: vk::Extent2D,
+    srgb: bool,
+    dst: &mut Vec<u8>,
+) {
+    let texel = |x: u32, y: u32| {
+        let x = x.min(src_extent.width - 1);
+        let y = y.min(src_extent.height - 1);
+        let index = (y as usize * src_extent.width as usize + x as usize) * 4;
+        &src[index..index + 4]
+    };
+
+    for y in 0..dst_extent.height {
+        for x in 0..dst_extent.width {
+            let quad = [
+                texel(x * 2, y * 2),
+                texel(x * 2 + 1, y * 2),
+                texel(x * 2, y * 2 + 1),
+                texel(x * 2 + 1, y * 2 + 1),
+            ];
+            for channel in 0..4 {
+                let color = srgb && channel < 3;
+                let sum: f32 = quad
+                    .iter()
+                    .map(|texel| {
+                        let value = texel[channel] as f32 / 255.0;
+                        if color {
+                            srgb_to_linear(value)
+                        } else {
+                            value
+                        }
+                    })
+                    .sum();
+                let mut value = sum / 4.0;
+                if color {
+                    value = linear_to_srgb(value);
+                }
+                dst.push((value * 255.0).round().clamp(0.0, 255.0) as u8);
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uncompressed RGBA8 container, `levels` base level first
+    fn ktx2(width: u32, height: u32, layers: u32, faces: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let index_end = 80 + 24 * levels.len() as u32;
+        let mut bytes = KTX2_MAGIC.to_vec();
+        for value in [
+            vk::Format::R8G8B8A8_UNORM.as_raw() as u32,
+            1,
+            width,
+            height,
+            0,
+            layers,
+            faces,
+            levels.len() as u32,
+            0,
+            // data format descriptor holding only its length, key/value data left empty
+            index_end,
+            4,
+            index_end + 4,
+            0,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0; 16]);
+
+        let mut offset = u64::from(index_end) + 4;
+        for level in levels {
+            let len = level.len() as u64;
+            for value in [offset, len, len] {
+                bytes.extend(value.to_le_bytes());
+            }
+            offset += len;
+        }
+        bytes.extend(4u32.to_le_bytes());
+        levels.iter().for_each(|level| bytes.extend(level));
+        bytes
+    }
+
+    fn png(color: png::ColorType, depth: png::BitDepth, texels: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if color == png::ColorType::Indexed {
+            encoder.set_palette(vec![255, 0, 0, 0, 0, 255]);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(texels).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn validate_checks_every_level() {
+        let mut data = TextureData::rgba8(4, 2, vec![0; 32], false);
+        data.validate().unwrap();
+
+        data.levels.push(vec![0; 8]);
+        data.validate().unwrap();
+        data.levels[1].pop();
+        assert!(data.validate().is_err());
+
+        data.levels[1].push(0);
+        data.levels.extend([vec![0; 4], vec![0; 4]]);
+        assert!(data.validate().is_err(), "more levels than the chain has");
+
+        let empty = TextureData::rgba8(0, 2, vec![], false);
+        assert!(empty.validate().is_err());
+
+        let unknown = TextureData {
+            format: vk::Format::UNDEFINED,
+            ..TextureData::rgba8(1, 1, vec![0; 4], false)
+        };
+        assert!(matches!(
+            unknown.validate(),
+            Err(RendererError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn ktx2_faces_and_layers_map_to_dimensions() {
+        let cases = [
+            (0, 1, Some(ImageDimension::D2)),
+            (3, 1, Some(ImageDimension::D2Array(3))),
+            (0, 6, Some(ImageDimension::Cube)),
+            (2, 6, Some(ImageDimension::CubeArray(2))),
+            (0, 3, None),
+        ];
+        for (layers, faces, expected) in cases {
+            let count = layers.max(1) as usize * faces as usize;
+            let levels = [vec![7; 2 * 2 * 4 * count], vec![7; 4 * count]];
+            let decoded = TextureData::from_ktx2(&ktx2(2, 2, layers, faces, &levels));
+            match expected {
+                Some(dimension) => {
+                    let data = decoded.unwrap();
+                    assert_eq!(data.dimension, dimension);
+                    assert_eq!(data.format, vk::Format::R8G8B8A8_UNORM);
+                    assert_eq!(data.levels, levels);
+                }
+                None => assert!(decoded.is_err()),
+            }
+        }
+
+        // one cube worth of texels for a cube array of two
+        let short = ktx2(1, 1, 2, 6, &[vec![0; 4 * 6]]);
+        assert!(TextureData::from_ktx2(&short).is_err());
+    }
+
+    #[test]
+    fn mips_of_srgb_data_are_averaged_in_linear_space() {
+        let quad = [0, 0, 0, 0, 255, 255, 255, 255].repeat(2);
+        let mut srgb = TextureData::rgba8(2, 2, quad.clone(), true);
+        srgb.generate_mips().unwrap();
+        // half the light is 188 in sRGB, alpha stays linear
+        assert_eq!(srgb.levels[1], [188, 188, 188, 128]);
+
+        let mut linear = TextureData::rgba8(2, 2, quad, false);
+        linear.generate_mips().unwrap();
+        assert_eq!(linear.levels[1], [128, 128, 128, 128]);
+    }
+
+    #[test]
+    fn mips_cover_every_layer_down_to_one_texel() {
+        let mut data = TextureData {
+            dimension: ImageDimension::D2Array(2),
+            ..TextureData::rgba8(3, 1, [[10; 12], [200; 12]].concat(), false)
+        };
+        data.generate_mips().unwrap();
+        assert_eq!(data.levels.len(), 2);
+        assert_eq!(data.levels[1], [[10; 4], [200; 4]].concat());
+        data.validate().unwrap();
+    }
+
+    #[test]
+    fn mips_need_an_rgba8_format_and_valid_data() {
+        let mut bc = TextureData {
+            format: vk::Format::BC1_RGB_UNORM_BLOCK,
+            ..TextureData::rgba8(4, 4, vec![0; 8], false)
+        };
+        assert!(matches!(
+            bc.generate_mips(),
+            Err(RendererError::UnsupportedFormat(_))
+        ));
+        assert_eq!(bc.levels.len(), 1);
+
+        let mut short = TextureData::rgba8(4, 4, vec![0; 60], true);
+        assert!(short.generate_mips().is_err());
+    }
+
+    #[test]
+    fn png_color_types_expand_to_rgba8() {
+        use png::{BitDepth, ColorType};
+
+        let cases: [(ColorType, BitDepth, &[u8], [u8; 8]); 5] = [
+            (
+                ColorType::Grayscale,
+                BitDepth::Eight,
+                &[10, 20],
+                [10, 10, 10, 255, 20, 20, 20, 255],
+            ),
+            (
+                ColorType::Grayscale,
+                BitDepth::Sixteen,
+                &[10, 1, 20, 2],
+                [10, 10, 10, 255, 20, 20, 20, 255],
+            ),
+            (
+                ColorType::GrayscaleAlpha,
+                BitDepth::Eight,
+                &[10, 1, 20, 2],
+                [10, 10, 10, 1, 20, 20, 20, 2],
+            ),
+            (
+                ColorType::Rgb,
+                BitDepth::Eight,
+                &[1, 2, 3, 4, 5, 6],
+                [1, 2, 3, 255, 4, 5, 6, 255],
+            ),
+            (
+                ColorType::Indexed,
+                BitDepth::Eight,
+                &[1, 0],
+                [0, 0, 255, 255, 255, 0, 0, 255],
+            ),
+        ];
+        for (color, depth, texels, expected) in cases {
+            let data = TextureData::from_png(&png(color, depth, texels), true).unwrap();
+            assert_eq!((data.width, data.height), (2, 1));
+            assert_eq!(data.format, vk::Format::R8G8B8A8_SRGB);
+            assert_eq!(data.levels, [expected.to_vec()], "{:?} {:?}", color, depth);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use ash::vk;
+
+/// Struct read by the vertex shader one element per vertex or instance, implemented by
+/// `#[derive(Vertex)]` which gives every field its own location
+pub trait Vertex: bytemuck::Pod {
+    /// One entry per location in location order, the binding is filled in by the methods below
+    const ATTRIBUTES: &'static [vk::VertexInputAttributeDescription];
+
+    fn binding_description(
+        binding: u32,
+        input_rate: vk::VertexInputRate,
+    ) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate,
+        }
+    }
+
+    fn attribute_descriptions(binding: u32) -> Vec<vk::VertexInputAttributeDescription> {
+        Self::ATTRIBUTES
+            .iter()
+            .map(|attribute| vk::VertexInputAttributeDescription {
+                binding,
+                ..*attribute
+            })
+            .collect()
+    }
+}
+
+pub use renderer_derive::Vertex;
+
+/// Field types `#[derive(Vertex)]` picks a format for, others need `#[vertex(format = ..)]`
+pub trait VertexFormat: Copy + 'static {
+    const FORMAT: vk::Format;
+    /// Matrices take a location per column
+    const LOCATIONS: u32 = 1;
+}
+
+macro_rules! vertex_formats {
+    ($($ty:ty => $format:ident $(* $locations:literal)?),* $(,)?) => {$(
+        impl VertexFormat for $ty {
+            const FORMAT: vk::Format = vk::Format::$format;
+            $(const LOCATIONS: u32 = $locations;)?
+        }
+    )*};
+}
+
+vertex_formats! {
+    f32 => R32_SFLOAT,
+    [f32; 2] => R32G32_SFLOAT,
+    [f32; 3] => R32G32B32_SFLOAT,
+    [f32; 4] => R32G32B32A32_SFLOAT,
+    i32 => R32_SINT,
+    [i32; 2] => R32G32_SINT,
+    [i32; 3] => R32G32B32_SINT,
+    [i32; 4] => R32G32B32A32_SINT,
+    u32 => R32_UINT,
+    [u32; 2] => R32G32_UINT,
+    [u32; 3] => R32G32B32_UINT,
+    [u32; 4] => R32G32B32A32_UINT,
+    // packed colors, read as normalized vec4
+    [u8; 4] => R8G8B8A8_UNORM,
+    [[f32; 2]; 2] => R32G32_SFLOAT * 2,
+    [[f32; 3]; 3] => R32G32B32_SFLOAT * 3,
+    [[f32; 4]; 4] => R32G32B32A32_SFLOAT * 4,
+}
+
+/// A struct field as `#[derive(Vertex)]` sees it
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub struct VertexField {
+    /// None continues after the previous field
+    pub location: Option<u32>,
+    pub format: vk::Format,
+    pub offset: usize,
+    pub size: usize,
+    pub locations: u32,
+}
+
+/// Attributes of the fields, `N` is the sum of their locations
+#[doc(hidden)]
+pub const fn vertex_attributes<const F: usize, const N: usize>(
+    fields: [VertexField; F],
+) -> [vk::VertexInputAttributeDescription; N] {
+    let mut attributes = [vk::VertexInputAttributeDescription {
+        location: 0,
+        binding: 0,
+        format: vk::Format::UNDEFINED,
+        offset: 0,
+    }; N];
+    let mut next = 0;
+    let mut count = 0;
+    let mut index = 0;
+    while index < F {
+        let field = fields[index];
+        let location = match field.location {
+            Some(location) => location,
+            None => next,
+        };
+        let column = field.size / field.locations as usize;
+        let mut column_index = 0;
+        while column_index < field.locations {
+            attributes[count] = vk::VertexInputAttributeDescription {
+                location: location + column_index,
+                binding: 0,
+                format: field.format,
+                offset: (field.offset + column * column_index as usize) as u32,
+            };
+            count += 1;
+            column_index += 1;
+        }
+        next = location + field.locations;
+        index += 1;
+    }
+    attributes
+}
@@ -0,0 +1,602 @@
+//! Golden image regression tests, each scene is rendered by a headless renderer and compared
+//! against `tests/golden/<scene>.png`.
+//!
+//! Set `GOLDEN_UPDATE=1` to (re)write the references from the current output and
+//! `GOLDEN_REQUIRE_VULKAN=1` to fail instead of skipping when no vulkan implementation is found.
+//! On mismatch the actual, expected and diff images are written to
+//! `target/tmp/golden/<scene>.{actual,expected,diff}.png`
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use renderer::{
+    ash::vk,
+    bytemuck::{Pod, Zeroable},
+    BlendState, DescriptorWriter, Frame, GpuLayout, GraphicsPipelineBuilder, Renderer,
+    RendererError, SamplerDesc, Screenshot, Spirv, Submesh, TextureData, TextureOptions, Vertex,
+    VertexLayout,
+};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+
+/// How far the output may drift from the reference before the test fails
+#[derive(Debug, Clone, Copy)]
+struct Tolerance {
+    /// Max absolute difference of a single channel for a pixel to be considered equal
+    per_channel: u8,
+    /// Fraction of pixels allowed to exceed `per_channel`, rasterizers disagree on edges
+    max_differing: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_channel: 2,
+            max_differing: 0.0,
+        }
+    }
+}
+
+struct Scene {
+    name: &'static str,
+    tolerance: Tolerance,
+    draw: fn(&mut Renderer, &mut Frame),
+}
+
+fn scene_clear(_renderer: &mut Renderer, frame: &mut Frame) {
+    frame.clear([0.2, 0.4, 0.6, 1.0]);
+}
+
+#[test]
+fn clear() {
+    run(Scene {
+        name: "clear",
+        tolerance: Tolerance::default(),
+        draw: scene_clear,
+    });
+}
+
+fn scene_triangle(renderer: &mut Renderer, frame: &mut Frame) {
+    let device = renderer.device();
+    let load = |bytes: &[u8]| {
+        let spirv = Spirv::from_bytes(bytes).unwrap();
+        device.create_shader_module(&spirv).unwrap()
+    };
+    let vertex = load(include_bytes!("spirv/triangle.spv"));
+    let fragment = load(include_bytes!("spirv/solid.spv"));
+    let pipeline = GraphicsPipelineBuilder::new()
+        .shader(&vertex, "main")
+        .shader(&fragment, "main")
+        .color_attachment(frame.format(), BlendState::opaque())
+        .build(device)
+        .unwrap();
+    device.destroy_shader_module(vertex);
+    device.destroy_shader_module(fragment);
+
+    frame.begin_rendering(Some([0.0, 0.0, 0.0, 1.0]));
+    frame.bind_pipeline(&pipeline);
+    frame.draw(3, 1);
+    frame.end_rendering();
+    device.destroy_pipeline(pipeline);
+}
+
+#[test]
+fn triangle() {
+    run(Scene {
+        name: "triangle",
+        // the reference is rasterized at pixel centers, drivers may disagree on the edges
+        tolerance: Tolerance {
+            max_differing: 0.02,
+            ..Tolerance::default()
+        },
+        draw: scene_triangle,
+    });
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct Position {
+    position: [f32; 2],
+}
+
+fn scene_square(renderer: &mut Renderer, frame: &mut Frame) {
+    let vertices =
+        [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]].map(|position| Position { position });
+    // one triangle per submesh
+    let mesh = renderer
+        .create_mesh(
+            &vertices,
+            &[0u16, 1, 2, 2, 3, 0],
+            &[Submesh::new(0, 3), Submesh::new(3, 3)],
+        )
+        .unwrap();
+
+    let device = renderer.device();
+    let load = |bytes: &[u8]| {
+        let spirv = Spirv::from_bytes(bytes).unwrap();
+        device.create_shader_module(&spirv).unwrap()
+    };
+    let vertex = load(include_bytes!("spirv/position.spv"));
+    let fragment = load(include_bytes!("spirv/solid.spv"));
+    let pipeline = GraphicsPipelineBuilder::new()
+        .shader(&vertex, "main")
+        .shader(&fragment, "main")
+        .vertex_layout(VertexLayout::new().vertex::<Position>(0))
+        .color_attachment(frame.format(), BlendState::opaque())
+        .build(device)
+        .unwrap();
+    device.destroy_shader_module(vertex);
+    device.destroy_shader_module(fragment);
+
+    frame.begin_rendering(Some([0.0, 0.0, 0.0, 1.0]));
+    frame.bind_pipeline(&pipeline);
+    frame.draw_mesh(&mesh);
+    frame.end_rendering();
+    device.destroy_pipeline(pipeline);
+    device.destroy_mesh(mesh);
+}
+
+#[test]
+fn square() {
+    run(Scene {
+        name: "square",
+        // the edges fall between pixel centers, no tolerance needed
+        tolerance: Tolerance::default(),
+        draw: scene_square,
+    });
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct TexturedVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+}
+
+/// 4x4 texels, red grows along u, green along v and blue alternates like a checkerboard
+fn checker_texels() -> Vec<u8> {
+    (0..4u8)
+        .flat_map(|v| {
+            (0..4u8)
+                .flat_map(move |u| [u * 85, v * 85, if (u + v) % 2 == 0 { 255 } else { 0 }, 255])
+        })
+        .collect()
+}
+
+fn scene_textured_quad(renderer: &mut Renderer, frame: &mut Frame) {
+    let vertices = [
+        ([-0.5, -0.5], [0.0, 0.0]),
+        ([0.5, -0.5], [1.0, 0.0]),
+        ([0.5, 0.5], [1.0, 1.0]),
+        ([-0.5, 0.5], [0.0, 1.0]),
+    ]
+    .map(|(position, uv)| TexturedVertex { position, uv });
+    let mesh = renderer
+        .create_mesh(&vertices, &[0u16, 1, 2, 2, 3, 0], &[])
+        .unwrap();
+    let options = TextureOptions {
+        srgb: false,
+        mipmaps: false,
+        sampler: SamplerDesc::nearest(),
+    };
+    let texture = renderer
+        .create_texture(&TextureData::rgba8(4, 4, checker_texels(), false), &options)
+        .unwrap();
+
+    let device = renderer.device();
+    let load = |bytes: &[u8]| {
+        let spirv = Spirv::from_bytes(bytes).unwrap();
+        device.create_shader_module(&spirv).unwrap()
+    };
+    let vertex = load(include_bytes!("spirv/uv.spv"));
+    let fragment = load(include_bytes!("spirv/texture.spv"));
+    let pipeline = GraphicsPipelineBuilder::new()
+        .shader(&vertex, "main")
+        .shader(&fragment, "main")
+        .vertex_layout(VertexLayout::new().vertex::<TexturedVertex>(0))
+        .color_attachment(frame.format(), BlendState::opaque())
+        .build(device)
+        .unwrap();
+    device.destroy_shader_module(vertex);
+    device.destroy_shader_module(fragment);
+
+    let set = renderer
+        .allocate_frame_descriptor_set(frame, pipeline.layout().set_layouts()[0])
+        .unwrap();
+    DescriptorWriter::new()
+        .image(
+            0,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            texture.view(),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::Sampler::null(),
+        )
+        .image(
+            1,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            texture.sampler(),
+        )
+        .update(renderer.device(), set);
+
+    frame.begin_rendering(Some([0.0, 0.0, 0.0, 1.0]));
+    frame.bind_pipeline(&pipeline);
+    frame.bind_descriptor_sets(&pipeline, 0, &[set]);
+    frame.draw_mesh(&mesh);
+    frame.end_rendering();
+
+    let device = renderer.device();
+    device.destroy_pipeline(pipeline);
+    device.destroy_texture(texture);
+    device.destroy_mesh(mesh);
+}
+
+#[test]
+fn textured_quad() {
+    run(Scene {
+        name: "textured_quad",
+        // a texel covers 8x8 pixels and nearest sampling never lands on a texel edge
+        tolerance: Tolerance::default(),
+        draw: scene_textured_quad,
+    });
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct VoxelVertex {
+    position: [f32; 3],
+    color: [u8; 4],
+}
+
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(std430)]
+#[repr(C)]
+struct VoxelPush {
+    view_proj: [[f32; 4]; 4],
+}
+
+const CHUNK: usize = 4;
+
+/// Column heights of the chunk indexed by `[x][z]`
+const HEIGHTS: [[usize; CHUNK]; CHUNK] = [[4, 3, 2, 1], [3, 3, 1, 1], [2, 1, 1, 2], [1, 1, 2, 3]];
+
+/// 2:1 dimetric projection looking down the (-1, -1, -1) diagonal, column major. A voxel
+/// edge is 6 pixels and every corner lands on a pixel corner, so no edge crosses a pixel center
+const VOXEL_VIEW: [[f32; 4]; 4] = [
+    [0.1875, 0.09375, 0.0, 0.0],
+    [0.0, -0.1875, 0.0, 0.0],
+    [-0.1875, 0.09375, 0.0, 0.0],
+    [0.0, 0.0, 0.5, 1.0],
+];
+
+struct VoxelFace {
+    normal: [i32; 3],
+    /// Edges spanning the face counter clockwise seen from outside
+    u: [i32; 3],
+    v: [i32; 3],
+    color: [u8; 4],
+}
+
+const fn face(normal: [i32; 3], u: [i32; 3], v: [i32; 3], color: [u8; 4]) -> VoxelFace {
+    VoxelFace {
+        normal,
+        u,
+        v,
+        color,
+    }
+}
+
+const DARK: [u8; 4] = [60, 42, 29, 255];
+
+const VOXEL_FACES: [VoxelFace; 6] = [
+    face([1, 0, 0], [0, 1, 0], [0, 0, 1], [121, 85, 58, 255]),
+    face([-1, 0, 0], [0, 0, 1], [0, 1, 0], DARK),
+    face([0, 1, 0], [0, 0, 1], [1, 0, 0], [96, 176, 64, 255]),
+    face([0, -1, 0], [1, 0, 0], [0, 0, 1], DARK),
+    face([0, 0, 1], [1, 0, 0], [0, 1, 0], [150, 108, 75, 255]),
+    face([0, 0, -1], [0, 1, 0], [1, 0, 0], DARK),
+];
+
+fn is_solid(x: i32, y: i32, z: i32) -> bool {
+    let size = CHUNK as i32;
+    (0..size).contains(&x)
+        && (0..size).contains(&z)
+        && y >= 0
+        && (y as usize) < HEIGHTS[x as usize][z as usize]
+}
+
+/// The faces of every voxel not hidden by a neighbour. There is no depth buffer, voxels
+/// are emitted back to front for `VOXEL_VIEW` and the back faces are left to culling
+fn voxel_chunk_mesh() -> (Vec<VoxelVertex>, Vec<u16>) {
+    let size = CHUNK as i32;
+    let mut voxels: Vec<[i32; 3]> = (0..size)
+        .flat_map(|x| (0..size).flat_map(move |y| (0..size).map(move |z| [x, y, z])))
+        .filter(|[x, y, z]| is_solid(*x, *y, *z))
+        .collect();
+    // voxels with the same sum never overlap on screen
+    voxels.sort_by_key(|[x, y, z]| x + y + z);
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    for voxel in voxels {
+        for VoxelFace {
+            normal,
+            u,
+            v,
+            color,
+        } in VOXEL_FACES
+        {
+            let [x, y, z] = std::array::from_fn(|i| voxel[i] + normal[i]);
+            if is_solid(x, y, z) {
+                continue;
+            }
+            // faces with a positive normal sit on the far side of the voxel
+            let origin: [i32; 3] = std::array::from_fn(|i| voxel[i] + normal[i].max(0));
+            let first = vertices.len() as u16;
+            for (du, dv) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+                let position = std::array::from_fn(|i| (origin[i] + u[i] * du + v[i] * dv) as f32);
+                vertices.push(VoxelVertex { position, color });
+            }
+            indices.extend([0, 1, 2, 2, 3, 0].map(|i| first + i));
+        }
+    }
+    (vertices, indices)
+}
+
+fn scene_voxel_chunk(renderer: &mut Renderer, frame: &mut Frame) {
+    let (vertices, indices) = voxel_chunk_mesh();
+    let mesh = renderer.create_mesh(&vertices, &indices, &[]).unwrap();
+
+    let device = renderer.device();
+    let load = |bytes: &[u8]| {
+        let spirv = Spirv::from_bytes(bytes).unwrap();
+        device.create_shader_module(&spirv).unwrap()
+    };
+    let vertex = load(include_bytes!("spirv/voxel.spv"));
+    let fragment = load(include_bytes!("spirv/color.spv"));
+    let pipeline = GraphicsPipelineBuilder::new()
+        .shader(&vertex, "main")
+        .shader(&fragment, "main")
+        .vertex_layout(VertexLayout::new().vertex::<VoxelVertex>(0))
+        .cull_mode(vk::CullModeFlags::BACK, vk::FrontFace::COUNTER_CLOCKWISE)
+        .color_attachment(frame.format(), BlendState::opaque())
+        .build(device)
+        .unwrap();
+    device.destroy_shader_module(vertex);
+    device.destroy_shader_module(fragment);
+
+    frame.begin_rendering(Some([0.0, 0.0, 0.0, 1.0]));
+    frame.bind_pipeline(&pipeline);
+    frame
+        .push_constants(&VoxelPush {
+            view_proj: VOXEL_VIEW,
+        })
+        .unwrap();
+    frame.draw_mesh(&mesh);
+    frame.end_rendering();
+    device.destroy_pipeline(pipeline);
+    device.destroy_mesh(mesh);
+}
+
+#[test]
+fn voxel_chunk() {
+    run(Scene {
+        name: "voxel_chunk",
+        // see `VOXEL_VIEW`, coverage doesn't depend on the rasterizer
+        tolerance: Tolerance::default(),
+        draw: scene_voxel_chunk,
+    });
+}
+
+fn run(scene: Scene) {
+    let Some(mut renderer) = headless_renderer(scene.name) else {
+        return;
+    };
+    let actual = render(&mut renderer, &scene);
+    drop(renderer);
+
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", scene.name));
+
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        actual.save_png(&reference).unwrap();
+        eprintln!("golden: updated {}", reference.display());
+        return;
+    }
+
+    let expected = load_png(&reference).unwrap_or_else(|err| {
+        panic!(
+            "golden: can't load {} ({}), run with GOLDEN_UPDATE=1 to create it",
+            reference.display(),
+            err
+        )
+    });
+
+    if let Err(report) = compare(&actual, &expected, scene.tolerance) {
+        let dir = output_dir();
+        actual
+            .save_png(dir.join(format!("{}.actual.png", scene.name)))
+            .unwrap();
+        expected
+            .save_png(dir.join(format!("{}.expected.png", scene.name)))
+            .unwrap();
+        diff_image(&actual, &expected, scene.tolerance)
+            .save_png(dir.join(format!("{}.diff.png", scene.name)))
+            .unwrap();
+        panic!(
+            "golden: scene \"{}\" doesn't match, images written to {}\n{}",
+            scene.name,
+            dir.display(),
+            report
+        );
+    }
+}
+
+/// None when no vulkan implementation can run the renderer, the test is skipped then
+fn headless_renderer(scene: &str) -> Option<Renderer> {
+    let result = Renderer::builder(c"golden".into())
+        .extent(WIDTH, HEIGHT)
+        .build_headless();
+
+    match result {
+        Ok(renderer) => Some(renderer),
+        Err(err) if vulkan_unavailable(&err) => {
+            if std::env::var_os("GOLDEN_REQUIRE_VULKAN").is_some() {
+                panic!("golden: vulkan is required but unavailable: {}", err);
+            }
+            eprintln!(
+                "golden: skipping \"{}\", no usable vulkan implementation: {}",
+                scene, err
+            );
+            None
+        }
+        Err(err) => panic!("golden: failed to create the renderer: {}", err),
+    }
+}
+
+fn vulkan_unavailable(err: &RendererError) -> bool {
+    match err {
+        RendererError::Loading(_) | RendererError::NoSuitableGpu(_) => true,
+        RendererError::InstanceCreation(result) => matches!(
+            *result,
+            vk::Result::ERROR_INCOMPATIBLE_DRIVER | vk::Result::ERROR_INITIALIZATION_FAILED
+        ),
+        _ => false,
+    }
+}
+
+fn render(renderer: &mut Renderer, scene: &Scene) -> Screenshot {
+    renderer.capture_next_frame().unwrap();
+    let mut frame = renderer
+        .begin_frame()
+        .unwrap()
+        .expect("headless renderers always have a target");
+    (scene.draw)(renderer, &mut frame);
+    renderer.end_frame(frame).unwrap();
+    renderer
+        .take_capture()
+        .unwrap()
+        .expect("the frame was captured")
+}
+
+fn compare(actual: &Screenshot, expected: &Screenshot, tolerance: Tolerance) -> Result<(), String> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return Err(format!(
+            "size is {}x{}, expected {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        ));
+    }
+
+    let mut differing = 0usize;
+    let mut max_delta = 0u8;
+    let mut first = None;
+    for (i, (a, e)) in actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+        .enumerate()
+    {
+        let delta = channel_delta(a, e);
+        max_delta = max_delta.max(delta);
+        if delta > tolerance.per_channel {
+            differing += 1;
+            first.get_or_insert((i, a.to_vec(), e.to_vec()));
+        }
+    }
+
+    let total = (actual.width * actual.height) as f64;
+    let ratio = differing as f64 / total;
+    if ratio <= tolerance.max_differing {
+        return Ok(());
+    }
+
+    let (index, a, e) = first.unwrap_or_default();
+    Err(format!(
+        "{} of {} pixels ({:.2}%) differ by more than {}, {:.2}% allowed, max delta {}\n\
+         first at ({}, {}): got {:?}, expected {:?}",
+        differing,
+        total,
+        ratio * 100.0,
+        tolerance.per_channel,
+        tolerance.max_differing * 100.0,
+        max_delta,
+        index % actual.width as usize,
+        index / actual.width as usize,
+        a,
+        e
+    ))
+}
+
+fn channel_delta(a: &[u8], b: &[u8]) -> u8 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Pixels over the tolerance in red, the others dimmed to grey so the shape stays visible
+fn diff_image(actual: &Screenshot, expected: &Screenshot, tolerance: Tolerance) -> Screenshot {
+    let pixels = actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+        .flat_map(|(a, e)| {
+            if channel_delta(a, e) > tolerance.per_channel {
+                [255, 0, 0, 255]
+            } else {
+                let luma = ((a[0] as u32 + a[1] as u32 + a[2] as u32) / 3 / 4) as u8;
+                [luma, luma, luma, 255]
+            }
+        })
+        .collect();
+
+    Screenshot {
+        width: actual.width,
+        height: actual.height,
+        pixels,
+    }
+}
+
+fn load_png(path: &Path) -> Result<Screenshot, png::DecodingError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("expanded by normalize_to_color8"),
+    };
+
+    Ok(Screenshot {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
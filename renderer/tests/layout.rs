@@ -0,0 +1,173 @@
+//! `#[derive(GpuLayout)]` on blocks whose offsets are known from the GLSL spec, the offset
+//! checks themselves run at compile time so a wrong layout doesn't build
+
+use std::mem::size_of;
+
+use renderer::{FieldLayout, GpuField, GpuLayout, LayoutRules};
+
+/// `layout(push_constant) uniform Push { mat4 mvp; vec3 tint; uint index; }`
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(std430)]
+#[repr(C)]
+struct Push {
+    mvp: [[f32; 4]; 4],
+    tint: [f32; 3],
+    index: u32,
+}
+
+/// `layout(std140) uniform Globals { float time; vec2 resolution; vec4 lights[2]; }`
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(std140)]
+#[repr(C)]
+struct Globals {
+    time: f32,
+    _pad0: u32,
+    resolution: [f32; 2],
+    lights: [[f32; 4]; 2],
+}
+
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(std430)]
+#[repr(C)]
+struct Light {
+    position: [f32; 3],
+    radius: f32,
+}
+
+/// `layout(std430) buffer Scene { uint count; Light lights[2]; }`
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(std430)]
+#[repr(C)]
+struct Scene {
+    count: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+    lights: [Light; 2],
+}
+
+/// `layout(scalar) buffer Points { vec3 a; vec3 b; float w; }`
+#[derive(Clone, Copy, GpuLayout)]
+#[layout(scalar)]
+#[repr(C)]
+struct Points {
+    a: [f32; 3],
+    b: [f32; 3],
+    w: f32,
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn vectors_follow_the_rules() {
+    assert_eq!(LayoutRules::Std430.field::<[f32; 3]>().align, 16);
+    assert_eq!(LayoutRules::Scalar.field::<[f32; 3]>().align, 4);
+    assert_eq!(LayoutRules::Std140.field::<[f32; 2]>().align, 8);
+}
+
+#[test]
+fn std140_arrays_are_padded_to_16() {
+    assert_eq!(
+        <[f32; 4] as GpuField>::STD140,
+        FieldLayout {
+            align: 16,
+            size: 16,
+            exact: true
+        }
+    );
+
+    // float[4] has a 16 byte stride in std140, a [f32; 4] array of scalars can't hold it
+    let array = LayoutRules::Std140.field::<[[f32; 1]; 4]>();
+    assert_eq!(array.size, 64);
+    assert!(!array.exact);
+    assert!(LayoutRules::Std430.field::<[[f32; 4]; 2]>().exact);
+}
+
+#[test]
+fn push_block() {
+    assert_eq!(Push::RULES, LayoutRules::Std430);
+    assert_eq!(size_of::<Push>(), 80);
+    const { assert!(Push::STD430.exact) };
+
+    let push = Push {
+        mvp: [[1.0, 0.0, 0.0, 0.0]; 4],
+        tint: [0.5, 0.25, 0.125],
+        index: 7,
+    };
+    let bytes = push.to_bytes();
+    assert_eq!(bytes.len(), 80);
+    assert_eq!(floats(&bytes[64..76]), [0.5, 0.25, 0.125]);
+    assert_eq!(u32::from_ne_bytes(bytes[76..80].try_into().unwrap()), 7);
+}
+
+#[test]
+fn std140_block() {
+    assert_eq!(size_of::<Globals>(), 48);
+    const { assert!(Globals::STD140.exact) };
+    assert_eq!(Globals::STD140.align, 16);
+
+    let globals = Globals {
+        time: 2.0,
+        _pad0: 0,
+        resolution: [640.0, 480.0],
+        lights: [[1.0; 4], [2.0; 4]],
+    };
+    let bytes = globals.to_bytes();
+    assert_eq!(floats(&bytes[8..16]), [640.0, 480.0]);
+    assert_eq!(floats(&bytes[32..48]), [2.0; 4]);
+}
+
+#[test]
+fn nested_structs() {
+    assert_eq!(Light::STD430.align, 16);
+    assert_eq!(Light::STD430.size, 16);
+    assert_eq!(size_of::<Scene>(), 48);
+    const { assert!(Scene::STD430.exact) };
+    // the struct member starts a 16 aligned array in std140 as well
+    const { assert!(Scene::STD140.exact) };
+
+    let scene = Scene {
+        count: 2,
+        _pad0: 0,
+        _pad1: 0,
+        _pad2: 0,
+        lights: [
+            Light {
+                position: [1.0, 2.0, 3.0],
+                radius: 4.0,
+            },
+            Light {
+                position: [5.0, 6.0, 7.0],
+                radius: 8.0,
+            },
+        ],
+    };
+    assert_eq!(
+        floats(&scene.to_bytes()[16..]),
+        [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]
+    );
+}
+
+#[test]
+fn scalar_block() {
+    assert_eq!(size_of::<Points>(), 28);
+    const { assert!(Points::SCALAR.exact) };
+    // std430 would push `b` to offset 16
+    const { assert!(!Points::STD430.exact) };
+    assert_eq!(
+        floats(
+            &Points {
+                a: [1.0; 3],
+                b: [2.0; 3],
+                w: 3.0
+            }
+            .to_bytes()[12..]
+        ),
+        [2.0, 2.0, 2.0, 3.0]
+    );
+}
@@ -0,0 +1,30 @@
+struct Instance {
+    center: vec4<f32>,
+    index_count: u32,
+}
+
+struct Params {
+    frustum: array<vec4<f32>, 6>,
+    count: u32,
+}
+
+@group(0) @binding(0) var<storage, read> instances: array<Instance>;
+@group(0) @binding(1) var<storage, read_write> visible: array<u32>;
+@group(0) @binding(2) var<uniform> params: Params;
+@group(1) @binding(0) var depth_pyramid: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.count {
+        return;
+    }
+    let center = instances[id.x].center;
+    var inside = 1u;
+    for (var i = 0u; i < 6u; i++) {
+        if dot(params.frustum[i], vec4<f32>(center.xyz, 1.0)) < -center.w {
+            inside = 0u;
+        }
+    }
+    visible[id.x] = inside * instances[id.x].index_count;
+    textureStore(depth_pyramid, vec2<i32>(id.xy), vec4<f32>(center.z));
+}
@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+layout(location = 3) in uvec2 bones;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_proj;
+} camera;
+
+layout(push_constant) uniform Push {
+    mat4 transform;
+    uint material;
+} push;
+
+void main() {
+    out_uv = uv;
+    out_color = color * float(bones.x + push.material);
+    gl_Position = camera.view_proj * push.transform * vec4(position, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 color;
+
+layout(set = 0, binding = 0) uniform texture2D albedo;
+layout(set = 0, binding = 1) uniform sampler albedo_sampler;
+
+void main() {
+    color = texture(sampler2D(albedo, albedo_sampler), uv);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 frag_color;
+
+layout(set = 1, binding = 0) uniform texture2D albedo;
+layout(set = 1, binding = 1) uniform sampler albedo_sampler;
+
+void main() {
+    frag_color = color * texture(sampler2D(albedo, albedo_sampler), uv);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+layout(push_constant) uniform Push {
+    mat4 view_proj;
+} push;
+
+void main() {
+    out_color = color;
+    gl_Position = push.view_proj * vec4(position, 1.0);
+}
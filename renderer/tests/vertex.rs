@@ -0,0 +1,82 @@
+//! Attributes generated by `#[derive(Vertex)]`
+
+use renderer::{
+    ash::vk,
+    bytemuck::{Pod, Zeroable},
+    Vertex, VertexLayout,
+};
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct MeshVertex {
+    position: [f32; 3],
+    uv: [f32; 2],
+    #[vertex(format = R8G8B8A8_SRGB)]
+    color: [u8; 4],
+    bones: [u32; 2],
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct Instance {
+    #[vertex(location = 4)]
+    transform: [[f32; 4]; 4],
+    material: u32,
+}
+
+/// (location, binding, format, offset), the vulkan struct can't be compared
+fn attributes<V: Vertex>(binding: u32) -> Vec<(u32, u32, vk::Format, u32)> {
+    V::attribute_descriptions(binding)
+        .into_iter()
+        .map(|attribute| {
+            (
+                attribute.location,
+                attribute.binding,
+                attribute.format,
+                attribute.offset,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn fields_take_consecutive_locations() {
+    assert_eq!(
+        attributes::<MeshVertex>(0),
+        [
+            (0, 0, vk::Format::R32G32B32_SFLOAT, 0),
+            (1, 0, vk::Format::R32G32_SFLOAT, 12),
+            (2, 0, vk::Format::R8G8B8A8_SRGB, 20),
+            (3, 0, vk::Format::R32G32_UINT, 24),
+        ]
+    );
+    let binding = MeshVertex::binding_description(0, vk::VertexInputRate::VERTEX);
+    assert_eq!(binding.stride, 32);
+}
+
+#[test]
+fn matrices_take_a_location_per_column() {
+    assert_eq!(
+        attributes::<Instance>(1),
+        [
+            (4, 1, vk::Format::R32G32B32A32_SFLOAT, 0),
+            (5, 1, vk::Format::R32G32B32A32_SFLOAT, 16),
+            (6, 1, vk::Format::R32G32B32A32_SFLOAT, 32),
+            (7, 1, vk::Format::R32G32B32A32_SFLOAT, 48),
+            (8, 1, vk::Format::R32_UINT, 64),
+        ]
+    );
+}
+
+#[test]
+fn layout_with_instances() {
+    let layout = VertexLayout::new()
+        .vertex::<MeshVertex>(0)
+        .instance::<Instance>(1);
+    assert_eq!(layout.bindings.len(), 2);
+    assert_eq!(layout.bindings[1].input_rate, vk::VertexInputRate::INSTANCE);
+    assert_eq!(layout.bindings[1].stride, 68);
+    assert_eq!(layout.attributes.len(), 9);
+}
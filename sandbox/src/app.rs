@@ -1,14 +1,33 @@
 use std::ffi::CString;
 
-use renderer::Renderer;
-use winit::{application::ApplicationHandler, event::WindowEvent};
+use renderer::{
+    bytemuck::{Pod, Zeroable},
+    BlendState, GraphicsPipelineBuilder, Mesh, PipelineDesc, PipelineKey, Renderer, RendererError,
+    Vertex, VertexLayout,
+};
+use winit::{
+    application::ApplicationHandler,
+    event::{ElementState, KeyEvent, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
 
 use crate::window::Window;
 
+const SQUARE: PipelineKey = PipelineKey(0);
+
+#[derive(Clone, Copy, Pod, Zeroable, Vertex)]
+#[bytemuck(crate = "renderer::bytemuck")]
+#[repr(C)]
+struct ColorVertex {
+    position: [f32; 2],
+    color: [f32; 3],
+}
+
 #[derive(Default)]
 pub struct App {
     window: Window,
     renderer: Option<Renderer>,
+    square: Option<Mesh>,
 }
 
 impl App {
@@ -16,6 +35,72 @@ impl App {
         Self {
             window,
             renderer: None,
+            square: None,
+        }
+    }
+
+    fn draw_frame(&mut self) -> Result<(), RendererError> {
+        let (Some(renderer), Some(square)) = (&mut self.renderer, &self.square) else {
+            return Ok(());
+        };
+
+        let Some(mut frame) = renderer.begin_frame()? else {
+            return Ok(());
+        };
+
+        let flash = (renderer.frame_number() % 120) as f32 / 120.0;
+        frame.begin_rendering(Some([0.0, 0.0, flash, 1.0]));
+        renderer.draw_mesh(&mut frame, SQUARE, square)?;
+        frame.end_rendering();
+
+        renderer.end_frame(frame)?;
+
+        if let Some(screenshot) = renderer.take_capture()? {
+            let time = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+            screenshot.save_png(format!("screenshot-{}.png", time))?;
+        }
+        Ok(())
+    }
+
+    fn register_pipelines(renderer: &mut Renderer) -> Result<(), RendererError> {
+        let vertex = renderer.load_shader("mesh.vert", &[])?;
+        let fragment = renderer.load_shader("color.frag", &[])?;
+        renderer.register_pipeline(
+            SQUARE,
+            PipelineDesc::graphics(&[vertex, fragment], |context| {
+                GraphicsPipelineBuilder::new()
+                    .shader(context.shaders[0], "main")
+                    .shader(context.shaders[1], "main")
+                    .vertex_layout(VertexLayout::new().vertex::<ColorVertex>(0))
+                    .color_attachment(context.color_format, BlendState::opaque())
+            }),
+        )
+    }
+
+    fn create_square(renderer: &mut Renderer) -> Result<Mesh, RendererError> {
+        let vertices = [
+            ([-0.5, -0.5], [1.0, 0.0, 0.0]),
+            ([0.5, -0.5], [0.0, 1.0, 0.0]),
+            ([0.5, 0.5], [0.0, 0.0, 1.0]),
+            ([-0.5, 0.5], [1.0, 1.0, 0.0]),
+        ]
+        .map(|(position, color)| ColorVertex { position, color });
+        renderer.create_mesh(&vertices, &[0u16, 1, 2, 2, 3, 0], &[])
+    }
+
+    fn screenshot(&mut self) {
+        if let Some(renderer) = &mut self.renderer {
+            if let Err(err) = renderer.capture_next_frame() {
+                log::warn!("Can't take a screenshot: {}", err);
+            }
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        if let (Some(renderer), Some(square)) = (&self.renderer, self.square.take()) {
+            renderer.device().destroy_mesh(square);
         }
     }
 }
@@ -30,9 +115,39 @@ impl ApplicationHandler for App {
             log::info!("Winit window created successfully");
 
             let app_name = CString::new(self.window.title.clone()).unwrap();
-            let renderer = Renderer::new(self.window.handle(), app_name, true);
-            self.renderer = Some(renderer);
-            log::info!("Renderer created succesfully");
+            let size = self.window.handle().inner_size();
+            let builder = Renderer::builder(app_name)
+                .validation(true)
+                .extent(size.width, size.height)
+                .shader_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"))
+                // shader edits show up without restarting while developing
+                .hot_reload(cfg!(debug_assertions));
+            let renderer = match builder.clone().build(self.window.handle()) {
+                Err(RendererError::MissingLayer { name, .. }) => {
+                    log::warn!(
+                        "Layer \"{}\" not available, retrying without validation",
+                        name
+                    );
+                    builder.validation(false).build(self.window.handle())
+                }
+                result => result,
+            };
+
+            match renderer.and_then(|mut renderer| {
+                Self::register_pipelines(&mut renderer)?;
+                let square = Self::create_square(&mut renderer)?;
+                Ok((renderer, square))
+            }) {
+                Ok((renderer, square)) => {
+                    self.renderer = Some(renderer);
+                    self.square = Some(square);
+                    log::info!("Renderer created succesfully");
+                }
+                Err(err) => {
+                    log::error!("Failed to create renderer: {}", err);
+                    event_loop.exit();
+                }
+            }
         }
     }
 
@@ -42,11 +157,39 @@ impl ApplicationHandler for App {
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        event_loop.exit(); // TODO: remove
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::Resized(_) => (),
+            WindowEvent::Resized(size) => {
+                if let Some(renderer) = &mut self.renderer {
+                    if let Err(err) = renderer.resize(size.width, size.height) {
+                        log::error!("Failed to resize renderer: {}", err);
+                        event_loop.exit();
+                    }
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F12),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => self.screenshot(),
+            WindowEvent::RedrawRequested => {
+                if let Err(err) = self.draw_frame() {
+                    log::error!("Failed to draw frame: {}", err);
+                    event_loop.exit();
+                }
+            }
             _ => (),
         }
     }
+
+    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        if let Some(handle) = &self.window.handle {
+            handle.request_redraw();
+        }
+    }
 }